@@ -1,6 +1,8 @@
 pub use crate::aabb::AAbb;
 pub use crate::hiitable::Hiitable;
-pub use crate::material::Material;
+pub use crate::material::{HitRecord, Material};
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64_1;
 pub use crate::vec3::Vec3;
 
 use std::sync::Arc;
@@ -64,6 +66,8 @@ impl Hiitable for Xyrect {
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (y - self.y0) / (self.y1 - self.y0);
         rec.t = t;
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, self.y1 - self.y0, 0.0);
         let outward_normal = Vec3::new(0.0, 0.0, 1.0);
         rec.set_front_size(r, &outward_normal);
         rec.mat = self.mp.clone();
@@ -71,6 +75,34 @@ impl Hiitable for Xyrect {
 
         true
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.y1 - self.y0);
+        rect_pdf(&rec, v, area)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let point = Vec3::new(
+            random_f64_1(self.x0, self.x1),
+            random_f64_1(self.y0, self.y1),
+            self.k,
+        );
+        point - *o
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        *point = self.random(&Vec3::zero());
+        *normal = Vec3::new(0.0, 0.0, 1.0);
+        true
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
 }
 
 pub struct Xzrect {
@@ -132,6 +164,8 @@ impl Hiitable for Xzrect {
         rec.u = (x - self.x0) / (self.x1 - self.x0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
+        rec.dpdu = Vec3::new(self.x1 - self.x0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         let outward_normal = Vec3::new(0.0, 1.0, 0.0);
         rec.set_front_size(r, &outward_normal);
         rec.mat = self.mp.clone();
@@ -139,6 +173,34 @@ impl Hiitable for Xzrect {
 
         true
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        rect_pdf(&rec, v, area)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let point = Vec3::new(
+            random_f64_1(self.x0, self.x1),
+            self.k,
+            random_f64_1(self.z0, self.z1),
+        );
+        point - *o
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        *point = self.random(&Vec3::zero());
+        *normal = Vec3::new(0.0, 1.0, 0.0);
+        true
+    }
+
+    fn area(&self) -> f64 {
+        (self.x1 - self.x0) * (self.z1 - self.z0)
+    }
 }
 
 pub struct Yzrect {
@@ -200,6 +262,8 @@ impl Hiitable for Yzrect {
         rec.u = (y - self.y0) / (self.y1 - self.y0);
         rec.v = (z - self.z0) / (self.z1 - self.z0);
         rec.t = t;
+        rec.dpdu = Vec3::new(0.0, self.y1 - self.y0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 0.0, self.z1 - self.z0);
         let outward_normal = Vec3::new(1.0, 0.0, 0.0);
         rec.set_front_size(r, &outward_normal);
         rec.mat = self.mp.clone();
@@ -207,4 +271,42 @@ impl Hiitable for Yzrect {
 
         true
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let area = (self.y1 - self.y0) * (self.z1 - self.z0);
+        rect_pdf(&rec, v, area)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let point = Vec3::new(
+            self.k,
+            random_f64_1(self.y0, self.y1),
+            random_f64_1(self.z0, self.z1),
+        );
+        point - *o
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        *point = self.random(&Vec3::zero());
+        *normal = Vec3::new(1.0, 0.0, 0.0);
+        true
+    }
+
+    fn area(&self) -> f64 {
+        (self.y1 - self.y0) * (self.z1 - self.z0)
+    }
+}
+
+/// Converts the uniform area density of a rectangle to solid angle at the sampled hit.
+fn rect_pdf(rec: &HitRecord, v: &Vec3, area: f64) -> f64 {
+    let distance_squared = rec.t * rec.t * v.squared_length();
+    let cosine = (*v * rec.normal).abs() / v.length();
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
 }
@@ -0,0 +1,124 @@
+pub use crate::aabb::AAbb;
+pub use crate::hiitable::{Hiitable, HitRecord};
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::texture::Texture;
+
+use std::sync::Arc;
+
+const MAX_MASKED_HITS: i32 = 64;
+
+/// Cuts holes into any hittable with an opacity texture (its `x` channel). Hits where the
+/// opacity is zero are skipped and the search continues behind them; fractional opacity is
+/// resolved stochastically, so half-transparent leaves let through half of the rays.
+pub struct AlphaMask {
+    ptr: Option<Arc<dyn Hiitable>>,
+    alpha: Option<Arc<dyn Texture>>,
+}
+
+impl AlphaMask {
+    pub fn new(p: Option<Arc<dyn Hiitable>>, alpha: Option<Arc<dyn Texture>>) -> Self {
+        Self { ptr: p, alpha }
+    }
+
+    pub fn opacity(&self, rec: &HitRecord) -> f64 {
+        self.alpha
+            .as_ref()
+            .map_or(1.0, |a| a.value(rec.u, rec.v, &rec.point3).x())
+            .clamp(0.0, 1.0)
+    }
+}
+
+impl Hiitable for AlphaMask {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let ptr = match &self.ptr {
+            Some(p) => p,
+            None => return false,
+        };
+        // Work on a scratch record: callers such as BvhNode keep using `rec` after a miss.
+        let mut temp_rec = HitRecord::new();
+        let mut t_start = t_min;
+        for _ in 0..MAX_MASKED_HITS {
+            if !ptr.hit(r, t_start, t_max, &mut temp_rec) {
+                return false;
+            }
+            let a = self.opacity(&temp_rec);
+            if a >= 1.0 || (a > 0.0 && random_f64() < a) {
+                *rec = temp_rec;
+                return true;
+            }
+            t_start = temp_rec.t + 0.0001;
+        }
+        false
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AAbb) -> bool {
+        self.ptr
+            .as_ref()
+            .is_some_and(|p| p.bounding_box(time0, time1, output_box))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xyrect;
+    use crate::hittable_list::HittableList;
+    use crate::texture::SolidColor;
+    use crate::vec3::Vec3;
+
+    /// Transparent over the left half of a rectangle, opaque over the right.
+    struct HalfCut;
+
+    impl Texture for HalfCut {
+        fn value(&self, u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            if u < 0.5 {
+                Vec3::zero()
+            } else {
+                Vec3::ones()
+            }
+        }
+    }
+
+    fn masked_rect(alpha: Arc<dyn Texture>) -> AlphaMask {
+        AlphaMask::new(
+            Some(Arc::new(Xyrect::new(-1.0, 1.0, -1.0, 1.0, 0.0, None))),
+            Some(alpha),
+        )
+    }
+
+    fn ray_at(x: f64) -> Ray {
+        Ray::new(Vec3::new(x, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0)
+    }
+
+    #[test]
+    fn test_transparent_texel_shows_what_is_behind() {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(masked_rect(Arc::new(HalfCut)))));
+        world.add(Some(Arc::new(Xyrect::new(
+            -1.0, 1.0, -1.0, 1.0, -1.0, None,
+        ))));
+
+        let mut rec = HitRecord::new();
+        assert!(world.hit(&ray_at(-0.5), 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 2.0).abs() < 1e-9);
+        assert!(world.hit(&ray_at(0.5), 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1e-9);
+
+        // With nothing behind, the hole is a miss.
+        let mask = masked_rect(Arc::new(HalfCut));
+        assert!(!mask.hit(&ray_at(-0.5), 0.001, f64::INFINITY, &mut rec));
+        assert!(mask.hit(&ray_at(0.5), 0.001, f64::INFINITY, &mut rec));
+    }
+
+    #[test]
+    fn test_fractional_alpha_stops_that_fraction_of_rays() {
+        let mask = masked_rect(Arc::new(SolidColor::new(Vec3::ones() * 0.3)));
+        let n = 20_000;
+        let mut rec = HitRecord::new();
+        let hits = (0..n)
+            .filter(|_| mask.hit(&ray_at(0.0), 0.001, f64::INFINITY, &mut rec))
+            .count();
+        assert!((hits as f64 / n as f64 - 0.3).abs() < 0.02);
+    }
+}
@@ -0,0 +1,755 @@
+pub use crate::camera::{Camera, ImportanceSample};
+pub use crate::environment::Environment;
+pub use crate::hiitable::HitRecord;
+pub use crate::hittable_list::HittableList;
+pub use crate::integrator::Integrator;
+pub use crate::light::{EmissionSample, Light, LightList, LightSample};
+pub use crate::material::{beer_lambert, Material};
+pub use crate::path_tracer::{power_heuristic, sample_environment};
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64_1;
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+const INFINITY: f64 = f64::INFINITY;
+
+#[derive(Clone)]
+enum VertexKind {
+    Camera,
+    Light {
+        light: Arc<dyn Light>,
+        /// Emits from a single point, so no camera path can ever reach it.
+        delta: bool,
+        tm: f64,
+    },
+    Surface {
+        rec: Box<HitRecord>,
+        r_in: Ray,
+        mat: Arc<dyn Material>,
+    },
+}
+
+#[derive(Clone)]
+struct Vertex {
+    kind: VertexKind,
+    point: Vec3,
+    /// Geometric normal on surfaces and area lights; zero in media, at point lights and at
+    /// the camera, where no cosine applies.
+    normal: Vec3,
+    /// Throughput from the start of the subpath up to this vertex.
+    beta: Vec3,
+    /// Left by a delta bounce, which no connection can reproduce.
+    delta: bool,
+    /// Area densities of sampling this vertex from its own end of the path and from the
+    /// other end.
+    pdf_fwd: f64,
+    pdf_rev: f64,
+}
+
+impl Vertex {
+    fn on_surface(&self) -> bool {
+        !self.normal.near_zero()
+    }
+
+    /// Whether a connection can end here: the material has some lobe that is not a delta,
+    /// whichever lobe the path itself went on through.
+    fn connectible(&self) -> bool {
+        match &self.kind {
+            VertexKind::Surface { rec, mat, .. } => !mat.is_specular(rec),
+            _ => !self.delta,
+        }
+    }
+
+    /// Turns a solid-angle density at this vertex into an area density at `next`.
+    fn convert_density(&self, pdf: f64, next: &Vertex) -> f64 {
+        let w = next.point - self.point;
+        let distance_squared = w.squared_length();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf / distance_squared;
+        if next.on_surface() {
+            pdf *= (next.normal * w.unit()).abs();
+        }
+        pdf
+    }
+
+    /// BSDF times cosine towards `to`, or emitted light times cosine at a light.
+    fn eval(&self, to: &Vertex) -> Vec3 {
+        let direction = (to.point - self.point).unit();
+        match &self.kind {
+            VertexKind::Surface { rec, r_in, mat } => mat.eval(r_in, rec, &direction),
+            VertexKind::Light { light, tm, .. } => {
+                let mut radiance = Vec3::zero();
+                light.emission(&self.point, &self.normal, &direction, *tm, &mut radiance);
+                if self.on_surface() {
+                    radiance * (self.normal * direction).abs()
+                } else {
+                    radiance
+                }
+            }
+            VertexKind::Camera => Vec3::zero(),
+        }
+    }
+
+    /// `eval` at a vertex of a light path, which carries importance. Refraction scales
+    /// radiance but not importance.
+    fn eval_importance(&self, to: &Vertex) -> Vec3 {
+        match &self.kind {
+            VertexKind::Surface { rec, r_in, mat } => {
+                let direction = (to.point - self.point).unit();
+                self.eval(to) / mat.radiance_scale(r_in, rec, &direction)
+            }
+            _ => self.eval(to),
+        }
+    }
+
+    /// Solid-angle density of scattering towards `to` for light arriving from `from`.
+    fn pdf_dir(&self, from: &Vec3, to: &Vec3) -> f64 {
+        let (rec, r_in, mat) = match &self.kind {
+            VertexKind::Surface { rec, r_in, mat } => (rec, r_in, mat),
+            _ => return 0.0,
+        };
+        // Face the record towards `from`, which need not be where the path came from.
+        let r = Ray::new(*from, self.point - *from, r_in.tm());
+        let mut rec = (**rec).clone();
+        let outward = if rec.front_size {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        rec.set_front_size(&r, &outward);
+        mat.scattering_pdf(&r, &rec, &(*to - self.point).unit())
+    }
+
+    /// Area density with which this vertex samples `next`, coming from `prev`.
+    fn pdf(&self, prev: Option<&Vertex>, next: &Vertex, camera: &Camera) -> f64 {
+        let direction = next.point - self.point;
+        let pdf_dir = match &self.kind {
+            VertexKind::Camera => camera.pdf_we(&Ray::new(self.point, direction, 0.0)).1,
+            VertexKind::Light { light, tm, .. } => {
+                let mut radiance = Vec3::zero();
+                light.emission(&self.point, &self.normal, &direction, *tm, &mut radiance)
+            }
+            VertexKind::Surface { .. } => match prev {
+                Some(prev) => self.pdf_dir(&prev.point, &next.point),
+                None => 0.0,
+            },
+        };
+        self.convert_density(pdf_dir, next)
+    }
+}
+
+/// Bidirectional path tracing: a path from the camera and one from a light are joined at
+/// every pair of vertices, and each way of building a path is weighted against the others
+/// by multiple importance sampling. Light paths that reach the lens are splatted onto the
+/// film, which finds caustics seen through diffuse surfaces and lights hidden behind glass.
+///
+/// Directional lights and the environment have no position to start light paths from, so
+/// only camera paths sample them. `radiance` leaves out the splats; call `radiance_with_splats`.
+pub struct Bdpt {
+    camera: Camera,
+    max_depth: usize,
+}
+
+impl Bdpt {
+    /// `camera` must be the one primary rays come from, as light tracing projects onto it.
+    /// Splats carry no per-channel camera weight, so a camera with chromatic aberration is
+    /// rejected; the same goes for a `StereoCamera` around it, which light tracing cannot see.
+    pub fn new(camera: &Camera, max_depth: u32) -> Self {
+        assert!(
+            !camera.has_chromatic_aberration(),
+            "Bdpt cannot trace light to a camera with chromatic aberration"
+        );
+        Self {
+            camera: camera.clone(),
+            max_depth: max_depth as usize,
+        }
+    }
+
+    /// Extends `path` by bouncing `ray` through the scene. On the camera side, also returns
+    /// the light the path picks up from the environment and from directional lights.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk(
+        &self,
+        ray: Ray,
+        beta: Vec3,
+        pdf_dir: f64,
+        max_vertices: usize,
+        camera_side: Option<(&dyn Environment, &LightList)>,
+        world: &HittableList,
+        path: &mut Vec<Vertex>,
+    ) -> Vec3 {
+        let mut radiance = Vec3::zero();
+        let mut ray = ray;
+        let mut beta = beta;
+        let mut pdf_fwd = pdf_dir;
+        // BSDF density of the last bounce, for weighting the environment against light
+        // sampling; `None` after the camera or a specular bounce.
+        let mut bsdf_pdf: Option<f64> = None;
+
+        while path.len() < max_vertices {
+            let mut rec = HitRecord::new();
+            if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+                if let Some((background, _)) = camera_side {
+                    let mut env = background.radiance(&ray.direc());
+                    if let Some(pdf) = bsdf_pdf {
+                        env *= power_heuristic(pdf, background.pdf(&ray.direc()));
+                    }
+                    radiance += Vec3::elemul(&beta, &env);
+                }
+                break;
+            }
+            let mat = match rec.mat.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            rec.footprint = ray.footprint(rec.t);
+            rec.tm = ray.tm();
+            if !rec.front_size {
+                let interior = beer_lambert(&mat.absorption(), rec.t * ray.direc().length());
+                beta = Vec3::elemul(&beta, &interior);
+            }
+
+            let prev = path.len() - 1;
+            let mut vertex = Vertex {
+                kind: VertexKind::Surface {
+                    rec: Box::new(rec.clone()),
+                    r_in: ray.clone(),
+                    mat: mat.clone(),
+                },
+                point: rec.point3,
+                normal: if mat.is_volume() {
+                    Vec3::zero()
+                } else {
+                    rec.normal
+                },
+                beta,
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            vertex.pdf_fwd = path[prev].convert_density(pdf_fwd, &vertex);
+            let specular = mat.is_specular(&rec);
+            vertex.delta = specular;
+            path.push(vertex);
+            if path.len() >= max_vertices {
+                break;
+            }
+
+            if let (Some((background, lights)), false) = (camera_side, specular) {
+                radiance += Vec3::elemul(
+                    &beta,
+                    &sample_environment(&ray, &rec, mat.as_ref(), background, world),
+                );
+                radiance += Vec3::elemul(
+                    &beta,
+                    &sample_distant_lights(&ray, &rec, mat.as_ref(), lights, world),
+                );
+            }
+
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), random_f64_1(0.0, 1.0));
+            if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+            let current = path.len() - 1;
+            let delta = specular || mat.is_delta(&ray, &rec, &scattered.direc());
+            path[current].delta = delta;
+            let (pdf_next, pdf_back) = if delta {
+                (0.0, 0.0)
+            } else {
+                let direction = scattered.direc().unit();
+                let vertex = &path[current];
+                (
+                    vertex.pdf_dir(&ray.ori(), &(vertex.point + direction)),
+                    vertex.pdf_dir(&(vertex.point + direction), &path[prev].point),
+                )
+            };
+            path[prev].pdf_rev = path[current].convert_density(pdf_back, &path[prev]);
+            bsdf_pdf = if delta { None } else { Some(pdf_next) };
+            pdf_fwd = pdf_next;
+            beta = Vec3::elemul(&beta, &attenuation);
+            if camera_side.is_none() {
+                beta = beta / mat.radiance_scale(&ray, &rec, &scattered.direc());
+            }
+            if beta.near_zero() {
+                break;
+            }
+
+            scattered.width = rec.footprint;
+            scattered.spread = ray.spread;
+            ray = scattered;
+        }
+        radiance
+    }
+
+    fn camera_subpath(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+        path: &mut Vec<Vertex>,
+    ) -> Vec3 {
+        path.push(Vertex {
+            kind: VertexKind::Camera,
+            point: r.ori(),
+            normal: Vec3::zero(),
+            beta: Vec3::ones(),
+            delta: false,
+            pdf_fwd: 1.0,
+            pdf_rev: 0.0,
+        });
+        let (_, pdf_dir) = self.camera.pdf_we(r);
+        self.random_walk(
+            r.clone(),
+            Vec3::ones(),
+            pdf_dir,
+            self.max_depth + 2,
+            Some((background, lights)),
+            world,
+            path,
+        )
+    }
+
+    fn light_subpath(
+        &self,
+        tm: f64,
+        lights: &LightList,
+        world: &HittableList,
+        path: &mut Vec<Vertex>,
+    ) {
+        let mut sample = EmissionSample::new();
+        let light = match lights.sample_emission(tm, &mut sample) {
+            Some(light) => light,
+            None => return,
+        };
+        if sample.pdf_pos <= 0.0 || sample.pdf_dir <= 0.0 || sample.radiance.near_zero() {
+            return;
+        }
+        let cos_theta = if sample.normal.near_zero() {
+            1.0
+        } else {
+            (sample.normal * sample.ray.direc()).abs()
+        };
+        path.push(Vertex {
+            kind: VertexKind::Light {
+                light,
+                delta: sample.delta,
+                tm,
+            },
+            point: sample.ray.ori(),
+            normal: sample.normal,
+            beta: Vec3::ones() / sample.pdf_pos,
+            delta: false,
+            pdf_fwd: sample.pdf_pos,
+            pdf_rev: 0.0,
+        });
+        let beta = sample.radiance * (cos_theta / (sample.pdf_pos * sample.pdf_dir));
+        self.random_walk(
+            sample.ray,
+            beta,
+            sample.pdf_dir,
+            self.max_depth + 1,
+            None,
+            world,
+            path,
+        );
+    }
+
+    /// The path made of the first `s` light vertices and the first `t` camera vertices, MIS
+    /// weighted. Connections straight to the lens also return where they land on the film.
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        s: usize,
+        t: usize,
+        lights: &LightList,
+        world: &HittableList,
+        tm: f64,
+        splat: &mut Option<(f64, f64)>,
+    ) -> Vec3 {
+        let mut sampled = None;
+        let contribution = if s == 0 {
+            let pt = &camera_path[t - 1];
+            match &pt.kind {
+                VertexKind::Surface { rec, r_in, mat } => {
+                    Vec3::elemul(&pt.beta, &mat.emitted(r_in, rec, rec.u, rec.v, &rec.point3))
+                }
+                _ => Vec3::zero(),
+            }
+        } else if t == 1 {
+            let qs = &light_path[s - 1];
+            let mut sample = ImportanceSample {
+                lens: Vec3::zero(),
+                s: 0.0,
+                t: 0.0,
+                importance: 0.0,
+                pdf: 0.0,
+            };
+            if !qs.connectible() || !self.camera.sample_wi(&qs.point, &mut sample) {
+                return Vec3::zero();
+            }
+            let lens = Vertex {
+                kind: VertexKind::Camera,
+                point: sample.lens,
+                normal: Vec3::zero(),
+                beta: Vec3::ones() * (sample.importance / sample.pdf),
+                delta: false,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+            };
+            let mut contribution = Vec3::elemul(
+                &Vec3::elemul(&qs.beta, &qs.eval_importance(&lens)),
+                &lens.beta,
+            );
+            if !contribution.near_zero() {
+                contribution *= visibility(world, &qs.point, &lens.point, tm);
+            }
+            *splat = Some((sample.s, sample.t));
+            sampled = Some(lens);
+            contribution
+        } else {
+            let qs = &light_path[s - 1];
+            let pt = &camera_path[t - 1];
+            if !qs.connectible() || !pt.connectible() {
+                return Vec3::zero();
+            }
+            let distance_squared = (pt.point - qs.point).squared_length();
+            if distance_squared == 0.0 {
+                return Vec3::zero();
+            }
+            let mut contribution = Vec3::elemul(
+                &Vec3::elemul(&qs.beta, &qs.eval_importance(pt)),
+                &Vec3::elemul(&pt.eval(qs), &pt.beta),
+            ) / distance_squared;
+            if !contribution.near_zero() {
+                contribution *= visibility(world, &qs.point, &pt.point, tm);
+            }
+            contribution
+        };
+        if contribution.near_zero() {
+            return Vec3::zero();
+        }
+        contribution * self.mis_weight(light_path, camera_path, sampled.as_ref(), s, t, lights, tm)
+    }
+
+    /// Balance heuristic over every other `(s, t)` split of the same path, found by walking
+    /// the ratios of reverse to forward densities out from the connection.
+    #[allow(clippy::too_many_arguments)]
+    fn mis_weight(
+        &self,
+        light_path: &[Vertex],
+        camera_path: &[Vertex],
+        sampled: Option<&Vertex>,
+        s: usize,
+        t: usize,
+        lights: &LightList,
+        tm: f64,
+    ) -> f64 {
+        if s + t == 2 {
+            return 1.0;
+        }
+        let pt = match sampled {
+            Some(lens) if t == 1 => lens,
+            _ => &camera_path[t - 1],
+        };
+        let pt_minus = if t > 1 {
+            Some(&camera_path[t - 2])
+        } else {
+            None
+        };
+        let mut camera_rev: Vec<f64> = camera_path[..t].iter().map(|v| v.pdf_rev).collect();
+        let mut light_rev: Vec<f64> = light_path[..s].iter().map(|v| v.pdf_rev).collect();
+
+        if s > 0 {
+            let qs = &light_path[s - 1];
+            let qs_minus = if s > 1 {
+                Some(&light_path[s - 2])
+            } else {
+                None
+            };
+            camera_rev[t - 1] = qs.pdf(qs_minus, pt, &self.camera);
+            if let Some(pt_minus) = pt_minus {
+                camera_rev[t - 2] = pt.pdf(Some(qs), pt_minus, &self.camera);
+            }
+            light_rev[s - 1] = pt.pdf(pt_minus, qs, &self.camera);
+            if let Some(qs_minus) = qs_minus {
+                light_rev[s - 2] = qs.pdf(Some(pt), qs_minus, &self.camera);
+            }
+        } else if let Some(pt_minus) = pt_minus {
+            // The camera path hit an emitter; a light path could have started there.
+            let r = Ray::new(pt_minus.point, pt.point - pt_minus.point, tm);
+            let (pdf_pos, pdf_dir) = lights.pdf_emission(&r);
+            if pdf_pos <= 0.0 {
+                return 1.0;
+            }
+            camera_rev[t - 1] = pdf_pos;
+            camera_rev[t - 2] = pt.convert_density(pdf_dir, pt_minus);
+        }
+
+        // Delta vertices have zero densities both ways, which cancel.
+        let remap = |pdf: f64| if pdf != 0.0 { pdf } else { 1.0 };
+        // The two vertices being joined are never delta.
+        let camera_delta = |i: usize| camera_path[i].delta && i != t - 1;
+        let light_delta = |i: usize| light_path[i].delta && i + 1 != s;
+        let mut sum = 0.0;
+        let mut ri = 1.0;
+        for i in (1..t).rev() {
+            ri *= remap(camera_rev[i]) / remap(camera_path[i].pdf_fwd);
+            if !camera_delta(i) && !camera_delta(i - 1) {
+                sum += ri;
+            }
+        }
+        ri = 1.0;
+        for i in (0..s).rev() {
+            ri *= remap(light_rev[i]) / remap(light_path[i].pdf_fwd);
+            let delta_before = if i > 0 {
+                light_delta(i - 1)
+            } else {
+                matches!(light_path[0].kind, VertexKind::Light { delta: true, .. })
+            };
+            if !light_delta(i) && !delta_before {
+                sum += ri;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for Bdpt {
+    fn radiance(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        self.radiance_with_splats(r, background, lights, world, &mut Vec::new())
+    }
+
+    fn radiance_with_splats(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+        splats: &mut Vec<(f64, f64, Vec3)>,
+    ) -> Vec3 {
+        let mut camera_path = Vec::with_capacity(self.max_depth + 2);
+        let mut radiance = self.camera_subpath(r, background, lights, world, &mut camera_path);
+        let mut light_path = Vec::with_capacity(self.max_depth + 1);
+        self.light_subpath(r.tm(), lights, world, &mut light_path);
+
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t - 2 > self.max_depth {
+                    continue;
+                }
+                let mut splat = None;
+                let contribution = self.connect(
+                    &light_path,
+                    &camera_path,
+                    s,
+                    t,
+                    lights,
+                    world,
+                    r.tm(),
+                    &mut splat,
+                );
+                match splat {
+                    Some((u, v)) if !contribution.near_zero() => splats.push((u, v, contribution)),
+                    Some(_) => {}
+                    None => radiance += contribution,
+                }
+            }
+        }
+        radiance
+    }
+}
+
+/// Fraction of light getting from `a` to `b`.
+fn visibility(world: &HittableList, a: &Vec3, b: &Vec3, tm: f64) -> f64 {
+    let w = *b - *a;
+    let distance = w.length();
+    if distance <= 0.002 {
+        return 1.0;
+    }
+    world.transmittance(&Ray::new(*a, w / distance, tm), 0.001, distance - 0.001)
+}
+
+/// A shadow ray towards the directional lights of the list, the only ones light paths
+/// cannot start from.
+fn sample_distant_lights(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &dyn Material,
+    lights: &LightList,
+    world: &HittableList,
+) -> Vec3 {
+    let mut sample = LightSample::new();
+    if !lights.sample(&rec.point3, r.tm(), &mut sample)
+        || sample.distance.is_finite()
+        || sample.pdf <= 0.0
+    {
+        return Vec3::zero();
+    }
+    let f = mat.eval(r, rec, &sample.direction);
+    if f.near_zero() {
+        return Vec3::zero();
+    }
+    let shadow = Ray::new(rec.point3, sample.direction, r.tm());
+    let transmittance = world.transmittance(&shadow, 0.001, INFINITY);
+    Vec3::elemul(&f, &sample.radiance) * (transmittance / sample.pdf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xzrect;
+    use crate::camera::Projection;
+    use crate::environment::ConstantEnvironment;
+    use crate::hiitable::Hiitable;
+    use crate::light::PointLight;
+    use crate::material::{Dielectric, DiffLight, FlipFace, Lambertian};
+    use crate::object::Sphere;
+    use crate::path_tracer::{PathSettings, PathTracer};
+    use crate::rtweekend::random_f64;
+
+    const SIZE: usize = 4;
+
+    fn scene(point_light: bool, glass: bool) -> (HittableList, LightList, Camera) {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Xzrect::new(
+            -2.0,
+            2.0,
+            -2.0,
+            2.0,
+            0.0,
+            Some(Arc::new(Lambertian::new1(&(Vec3::ones() * 0.5)))),
+        ))));
+        let ball: Arc<dyn Material> = if glass {
+            Arc::new(Dielectric::new(1.5))
+        } else {
+            Arc::new(Lambertian::new1(&Vec3::new(0.8, 0.3, 0.3)))
+        };
+        world.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(0.3, 0.4, 0.0),
+            0.4,
+            Some(ball),
+        ))));
+        let mut lights = LightList::new();
+        if point_light {
+            lights.add(Some(Arc::new(PointLight::new(
+                Vec3::new(-0.5, 1.5, -0.5),
+                Vec3::ones() * 3.0,
+            ))));
+        } else {
+            let lamp: Option<Arc<dyn Hiitable>> =
+                Some(Arc::new(FlipFace::new(Some(Arc::new(Xzrect::new(
+                    -0.3,
+                    0.3,
+                    -0.3,
+                    0.3,
+                    1.5,
+                    Some(Arc::new(DiffLight::new2(Vec3::ones() * 8.0))),
+                ))))));
+            world.add(lamp.clone());
+            lights.add_area(lamp);
+        }
+        let cam = Camera::new(
+            1.0,
+            &Vec3::new(0.0, 1.0, -3.0),
+            &Vec3::zero(),
+            &Vec3::new(0.0, 1.0, 0.0),
+            50.0,
+            0.0,
+            (3.0, 0.0, 1.0),
+        );
+        (world, lights, cam)
+    }
+
+    /// Red channel of a tiny render, per pixel, with the splats added in.
+    fn render(
+        integrator: &dyn Integrator,
+        world: &HittableList,
+        lights: &LightList,
+        cam: &Camera,
+        spp: usize,
+    ) -> Vec<f64> {
+        let background = ConstantEnvironment::new(Vec3::zero());
+        let mut film = [0.0; SIZE * SIZE];
+        let mut splats = Vec::new();
+        for j in 0..SIZE {
+            for i in 0..SIZE {
+                for _ in 0..spp {
+                    let u = (i as f64 + random_f64()) / SIZE as f64;
+                    let v = (j as f64 + random_f64()) / SIZE as f64;
+                    let r = cam.get_ray(u, v);
+                    film[j * SIZE + i] += integrator
+                        .radiance_with_splats(&r, &background, lights, world, &mut splats)
+                        .x();
+                }
+            }
+        }
+        for (s, t, color) in splats {
+            let x = ((s * SIZE as f64) as usize).min(SIZE - 1);
+            let y = ((t * SIZE as f64) as usize).min(SIZE - 1);
+            film[y * SIZE + x] += color.x();
+        }
+        film.iter().map(|p| p / spp as f64).collect()
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracing() {
+        // Light tracing puts part of the image in splats; only film and splats together match.
+        let reference = PathTracer::new(PathSettings {
+            max_depth: 5,
+            min_depth: 5,
+            ..PathSettings::default()
+        });
+        // Caustics through the glass ball are the noisiest part for both.
+        for (point_light, glass, spp) in [
+            (false, false, 1000),
+            (true, false, 1000),
+            (false, true, 3000),
+        ] {
+            let (world, lights, cam) = scene(point_light, glass);
+            let expected = render(&reference, &world, &lights, &cam, spp);
+            let found = render(&Bdpt::new(&cam, 4), &world, &lights, &cam, spp);
+            // Splats must land on the pixels they belong to, not just somewhere on the film.
+            // Caustic pixels are noisy in the reference, hence the absolute slack.
+            let mean = expected.iter().sum::<f64>() / expected.len() as f64;
+            for (pixel, (f, e)) in found.iter().zip(&expected).enumerate() {
+                assert!(
+                    (f - e).abs() < 0.15 * e + 0.3 * mean,
+                    "pixel {}: {} vs {}",
+                    pixel,
+                    f,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_light_paths_land_on_the_film() {
+        let (world, lights, cam) = scene(true, false);
+        let background = ConstantEnvironment::new(Vec3::zero());
+        let bdpt = Bdpt::new(&cam, 4);
+        let r = cam.get_ray(0.5, 0.5);
+        let mut splats = Vec::new();
+        for _ in 0..100 {
+            bdpt.radiance_with_splats(&r, &background, &lights, &world, &mut splats);
+        }
+        assert!(!splats.is_empty());
+        for (s, t, color) in splats {
+            assert!((0.0..=1.0).contains(&s) && (0.0..=1.0).contains(&t));
+            assert!(color.x() >= 0.0 && color.x().is_finite());
+        }
+    }
+}
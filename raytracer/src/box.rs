@@ -95,4 +95,20 @@ impl Hiitable for Box {
     ) -> bool {
         self.sides.hit(r, t_min, t_max, rec)
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.sides.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.sides.random(o)
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        self.sides.sample_surface(point, normal)
+    }
+
+    fn area(&self) -> f64 {
+        self.sides.area()
+    }
 }
@@ -0,0 +1,292 @@
+pub use crate::hiitable::HitRecord;
+pub use crate::material::Material;
+pub use crate::ray::Ray;
+pub use crate::texture::Texture;
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+const BUMP_DELTA: f64 = 0.0005;
+
+/// A way of bending the surface normal before shading, used by `Perturbed`.
+pub trait NormalPerturbation: Send + Sync {
+    /// Shading normal on the same side as `rec.normal`.
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3;
+}
+
+/// Hands the hit to the wrapped material with `rec.normal` replaced by the perturbed normal.
+pub struct Perturbed<P> {
+    inner: Option<Arc<dyn Material>>,
+    perturbation: P,
+}
+
+/// Perturbs `rec.normal` with a tangent-space normal map (e.g. an `ImageTexture`) before
+/// handing the hit to the wrapped material.
+pub type NormalMapped = Perturbed<NormalMap>;
+
+/// Perturbs `rec.normal` by the gradient of a height field read from any texture, e.g. a
+/// `NoiseTexture`.
+pub type BumpMapped = Perturbed<BumpMap>;
+
+impl<P: NormalPerturbation> Perturbed<P> {
+    pub fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        self.perturbation.shading_normal(rec)
+    }
+
+    fn shading(&self, rec: &HitRecord) -> HitRecord {
+        let mut shading = rec.clone();
+        shading.normal = self.shading_normal(rec);
+        shading
+    }
+}
+
+pub struct NormalMap {
+    map: Option<Arc<dyn Texture>>,
+    strength: f64,
+}
+
+impl NormalMapped {
+    pub fn new(
+        inner: Option<Arc<dyn Material>>,
+        map: Option<Arc<dyn Texture>>,
+        strength: f64,
+    ) -> Self {
+        Self {
+            inner,
+            perturbation: NormalMap { map, strength },
+        }
+    }
+}
+
+impl NormalPerturbation for NormalMap {
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        let n = outward_normal(rec);
+        let t = rec.dpdu - n * (rec.dpdu * n);
+        if t.near_zero() {
+            return rec.normal;
+        }
+        let t = t.unit();
+        let mut b = Vec3::cross(&n, &t);
+        if b * rec.dpdv < 0.0 {
+            b = -b;
+        }
+
+        let m = match &self.map {
+            Some(map) => map.eval(&rec.tex_coord()) * 2.0 - 1.0,
+            None => return rec.normal,
+        };
+        let ns = (t * (m.x() * self.strength) + b * (m.y() * self.strength) + n * m.z()).unit();
+        face_like(rec, &ns)
+    }
+}
+
+/// `scale` is the displacement in world units of a texture value of one.
+pub struct BumpMap {
+    height: Option<Arc<dyn Texture>>,
+    scale: f64,
+}
+
+impl BumpMapped {
+    pub fn new(
+        inner: Option<Arc<dyn Material>>,
+        height: Option<Arc<dyn Texture>>,
+        scale: f64,
+    ) -> Self {
+        Self {
+            inner,
+            perturbation: BumpMap { height, scale },
+        }
+    }
+}
+
+impl BumpMap {
+    /// Displacement at the hit moved by `(du, dv)` in uv and `dp` in space.
+    fn displacement(&self, rec: &HitRecord, du: f64, dv: f64, dp: &Vec3) -> f64 {
+        let mut coord = rec.tex_coord();
+        coord.u += du;
+        coord.v += dv;
+        coord.p += *dp;
+        let h = self
+            .height
+            .as_ref()
+            .map_or(Vec3::zero(), |h| h.eval(&coord));
+        (h.x() + h.y() + h.z()) / 3.0 * self.scale
+    }
+}
+
+impl NormalPerturbation for BumpMap {
+    fn shading_normal(&self, rec: &HitRecord) -> Vec3 {
+        let n = outward_normal(rec);
+        let d = self.displacement(rec, 0.0, 0.0, &Vec3::zero());
+        let du = self.displacement(rec, BUMP_DELTA, 0.0, &(rec.dpdu * BUMP_DELTA));
+        let dv = self.displacement(rec, 0.0, BUMP_DELTA, &(rec.dpdv * BUMP_DELTA));
+
+        let dpdu = rec.dpdu + n * ((du - d) / BUMP_DELTA);
+        let dpdv = rec.dpdv + n * ((dv - d) / BUMP_DELTA);
+        let mut ns = Vec3::cross(&dpdu, &dpdv);
+        if ns.near_zero() {
+            return rec.normal;
+        }
+        ns = ns.unit();
+        if ns * n < 0.0 {
+            ns = -ns;
+        }
+        face_like(rec, &ns)
+    }
+}
+
+impl<P: NormalPerturbation> Material for Perturbed<P> {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let geometric = rec.normal;
+        rec.normal = self.shading_normal(rec);
+        let scatter = self
+            .inner
+            .as_ref()
+            .is_some_and(|m| m.scatter(r_in, rec, attenuation, scattered));
+        rec.normal = geometric;
+        scatter
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.inner
+            .as_ref()
+            .map_or(Vec3::zero(), |m| m.emitted(r_in, rec, u, v, p))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.inner.as_ref().map_or(Vec3::zero(), |m| {
+            m.eval(r_in, &self.shading(rec), direction)
+        })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        self.inner.as_ref().map_or(0.0, |m| {
+            m.scattering_pdf(r_in, &self.shading(rec), direction)
+        })
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.inner.as_ref().is_none_or(|m| m.is_specular(rec))
+    }
+
+    fn is_delta(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> bool {
+        self.inner
+            .as_ref()
+            .is_none_or(|m| m.is_delta(r_in, &self.shading(rec), direction))
+    }
+
+    fn radiance_scale(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        self.inner.as_ref().map_or(1.0, |m| {
+            m.radiance_scale(r_in, &self.shading(rec), direction)
+        })
+    }
+}
+
+fn outward_normal(rec: &HitRecord) -> Vec3 {
+    if rec.front_size {
+        rec.normal
+    } else {
+        -rec.normal
+    }
+}
+
+/// Turns an outward shading normal around to the side the ray arrived from, falling back to
+/// the geometric normal if the perturbation would tip it past the horizon.
+fn face_like(rec: &HitRecord, outward: &Vec3) -> Vec3 {
+    let ns = if rec.front_size { *outward } else { -*outward };
+    if ns * rec.normal <= 0.0 {
+        rec.normal
+    } else {
+        ns
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::SolidColor;
+
+    /// Height equal to `u`, so the surface rises along `dpdu`.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            Vec3::ones() * u
+        }
+    }
+
+    fn flat_hit(front: bool) -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, if front { 1.0 } else { -1.0 });
+        rec.front_size = front;
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.dpdv = Vec3::new(0.0, 1.0, 0.0);
+        rec.u = 0.3;
+        rec.v = 0.6;
+        rec
+    }
+
+    fn normal_map(color: Vec3) -> NormalMapped {
+        NormalMapped::new(None, Some(Arc::new(SolidColor::new(color))), 1.0)
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let flat = normal_map(Vec3::new(0.5, 0.5, 1.0));
+        for front in [true, false] {
+            let rec = flat_hit(front);
+            assert!((flat.shading_normal(&rec) - rec.normal).length() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_normal_map_follows_tangents() {
+        // Red tilts towards dpdu, green towards dpdv.
+        let mut rec = flat_hit(true);
+        let expected = Vec3::new(1.0, 0.0, 1.0).unit();
+        let ns = normal_map(Vec3::new(1.0, 0.5, 1.0)).shading_normal(&rec);
+        assert!((ns - expected).length() < 1e-9, "{:?}", ns);
+
+        let green = normal_map(Vec3::new(0.5, 1.0, 1.0));
+        let ns = green.shading_normal(&rec);
+        assert!((ns - Vec3::new(0.0, 1.0, 1.0).unit()).length() < 1e-9);
+
+        // A mirrored uv layout flips the bitangent with dpdv.
+        rec.dpdv = -rec.dpdv;
+        let ns = green.shading_normal(&rec);
+        assert!((ns - Vec3::new(0.0, -1.0, 1.0).unit()).length() < 1e-9);
+
+        // Seen from behind, the normal flips with the face and keeps its tilt.
+        let rec = flat_hit(false);
+        let ns = normal_map(Vec3::new(1.0, 0.5, 1.0)).shading_normal(&rec);
+        assert!((ns + expected).length() < 1e-9, "{:?}", ns);
+    }
+
+    #[test]
+    fn test_bump_slope_tilts_normal_downhill() {
+        let bump = BumpMapped::new(None, Some(Arc::new(Ramp)), 0.5);
+        let ns = bump.shading_normal(&flat_hit(true));
+        assert!(
+            (ns - Vec3::new(-0.5, 0.0, 1.0).unit()).length() < 1e-6,
+            "{:?}",
+            ns
+        );
+
+        let ns = bump.shading_normal(&flat_hit(false));
+        assert!(
+            (ns + Vec3::new(-0.5, 0.0, 1.0).unit()).length() < 1e-6,
+            "{:?}",
+            ns
+        );
+
+        let flat = BumpMapped::new(None, Some(Arc::new(SolidColor::new(Vec3::ones()))), 0.5);
+        let rec = flat_hit(true);
+        assert!((flat.shading_normal(&rec) - rec.normal).length() < 1e-9);
+    }
+}
@@ -1,14 +1,23 @@
 pub use crate::aabb::AAbb;
 pub use crate::hiitable::{Hiitable, HitRecord};
 pub use crate::hittable_list::HittableList;
-pub use crate::material::Material;
-pub use crate::moving_sphere::MovingSphere;
 pub use crate::ray::Ray;
 use crate::rtweekend::random_i32_1;
 
+use std::cell::Cell;
 use std::cmp::Ordering::{Greater, Less};
 use std::sync::Arc;
 
+thread_local! {
+    /// BVH nodes this thread has visited, for the traversal-cost debug view.
+    static NODE_VISITS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Returns the number of nodes visited on this thread since the last call, and resets it.
+pub fn take_node_visits() -> u64 {
+    NODE_VISITS.with(|visits| visits.replace(0))
+}
+
 pub struct BvhNode {
     left: Option<Arc<dyn Hiitable>>,
     right: Option<Arc<dyn Hiitable>>,
@@ -23,8 +32,12 @@ impl BvhNode {
     ) -> bool {
         let mut box_a = AAbb::new_0();
         let mut box_b = AAbb::new_0();
-        if !a.clone().unwrap().bounding_box(0.0, 0.0, &mut box_a)
-            || !b.clone().unwrap().bounding_box(0.0, 0.0, &mut box_b)
+        if !a
+            .as_ref()
+            .is_some_and(|a| a.bounding_box(0.0, 0.0, &mut box_a))
+            || !b
+                .as_ref()
+                .is_some_and(|b| b.bounding_box(0.0, 0.0, &mut box_b))
         {
             println!("No bounding box in bvh_node constructor.\n");
         }
@@ -108,13 +121,11 @@ impl BvhNode {
         let mut box_right = AAbb::new_0();
 
         if !left_0
-            .clone()
-            .unwrap()
-            .bounding_box(time0, time1, &mut box_left)
+            .as_ref()
+            .is_some_and(|l| l.bounding_box(time0, time1, &mut box_left))
             || !right_0
-                .clone()
-                .unwrap()
-                .bounding_box(time0, time1, &mut box_right)
+                .as_ref()
+                .is_some_and(|r| r.bounding_box(time0, time1, &mut box_right))
         {
             println!("No bounding box in bvh_node constructor.\n");
         }
@@ -134,15 +145,19 @@ impl BvhNode {
 
 impl Hiitable for BvhNode {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        NODE_VISITS.with(|visits| visits.set(visits.get() + 1));
         if !self.box_bvh.clone().hit(r, t_min, t_max) {
             return false;
         }
-        let hit_left = self.left.clone().unwrap().hit(r, t_min, t_max, rec);
-        let hit_right = if hit_left {
-            self.right.clone().unwrap().hit(r, t_min, rec.t, rec)
-        } else {
-            self.right.clone().unwrap().hit(r, t_min, t_max, rec)
-        };
+        let hit_left = self
+            .left
+            .as_ref()
+            .is_some_and(|left| left.hit(r, t_min, t_max, rec));
+        let t_right = if hit_left { rec.t } else { t_max };
+        let hit_right = self
+            .right
+            .as_ref()
+            .is_some_and(|right| right.hit(r, t_min, t_right, rec));
 
         hit_left || hit_right
     }
@@ -151,4 +166,24 @@ impl Hiitable for BvhNode {
         *output_box = self.box_bvh.clone();
         true
     }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if !self.box_bvh.clone().hit(r, t_min, t_max) {
+            return 1.0;
+        }
+        let left = self
+            .left
+            .as_ref()
+            .map_or(1.0, |left| left.transmittance(r, t_min, t_max));
+        if left <= 0.0 {
+            return 0.0;
+        }
+        // Leaves holding a single object store it on both sides.
+        match (&self.left, &self.right) {
+            (Some(l), Some(right)) if !Arc::ptr_eq(l, right) => {
+                left * right.transmittance(r, t_min, t_max)
+            }
+            _ => left,
+        }
+    }
 }
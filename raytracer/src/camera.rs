@@ -1,15 +1,133 @@
-use crate::{random_f64_1, ray, rtweekend, vec3};
+use crate::error::{open_image, Error, Result};
+use crate::sampling::Distribution2D;
+use crate::{random_f64, random_f64_1, ray, rtweekend, vec3};
 
 pub use ray::Ray;
 pub use rtweekend::degrees_to_radians;
 pub use vec3::Vec3;
 
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Anything that turns film coordinates `(s, t)` in `[0, 1]` into a primary ray.
+pub trait Projection: Send + Sync {
+    /// The ray of `get_ray_weighted` without its weight, for projections whose weight is one.
+    fn get_ray(&self, s: f64, t: f64) -> Ray;
+
+    /// Like `get_ray`, plus a per-channel weight the returned radiance must be multiplied by.
+    fn get_ray_weighted(&self, s: f64, t: f64) -> (Ray, Vec3) {
+        (self.get_ray(s, t), Vec3::ones())
+    }
+}
+
+/// Grayscale aperture image; brighter texels let more light through.
+pub struct ApertureMask {
+    weights: Vec<f64>,
+    distribution: Distribution2D,
+}
+
+impl ApertureMask {
+    pub fn new(filename: &str) -> Result<Self> {
+        let photo = open_image(filename)?.into_luma8();
+        let weights = photo.pixels().map(|p| p.0[0] as f64 / 255.0).collect();
+
+        Self::from_weights(weights, photo.width() as usize, photo.height() as usize).ok_or(
+            Error::EmptyApertureMask {
+                path: filename.to_string(),
+            },
+        )
+    }
+
+    /// `weights` holds `height` rows of `width` transmissions, top row first. None when no
+    /// texel lets light through.
+    fn from_weights(weights: Vec<f64>, width: usize, height: usize) -> Option<Self> {
+        if !weights.iter().any(|w| *w > 0.0) {
+            return None;
+        }
+        Some(Self {
+            distribution: Distribution2D::new(&weights, width, height),
+            weights,
+        })
+    }
+
+    /// Samples a point in `[-1, 1]^2` with density proportional to the mask.
+    fn sample(&self) -> Vec3 {
+        let (x, y, _) = self.distribution.sample(random_f64(), random_f64());
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+}
+
+#[derive(Clone)]
+pub enum ApertureShape {
+    Circle,
+    /// Regular polygon inscribed in the unit circle; `rotation` is in degrees.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    Mask(Arc<ApertureMask>),
+}
+
+impl ApertureShape {
+    /// Uniformly samples a point on the aperture. Circles and polygons lie in the unit disk,
+    /// masks cover `[-1, 1]^2`.
+    pub fn sample(&self) -> Vec3 {
+        match self {
+            ApertureShape::Circle => Vec3::random_in_unit_disk(),
+            ApertureShape::Polygon { blades, rotation } => {
+                let n = (*blades).max(3);
+                // Every blade spans one equal-area triangle fanned out from the centre.
+                let k = (random_f64() * n as f64) as u32 % n;
+                let step = 2.0 * PI / n as f64;
+                let a0 = degrees_to_radians(*rotation) + step * k as f64;
+                let p0 = Vec3::new(a0.cos(), a0.sin(), 0.0);
+                let p1 = Vec3::new((a0 + step).cos(), (a0 + step).sin(), 0.0);
+                let mut r1 = random_f64();
+                let mut r2 = random_f64();
+                if r1 + r2 > 1.0 {
+                    r1 = 1.0 - r1;
+                    r2 = 1.0 - r2;
+                }
+                p0 * r1 + p1 * r2
+            }
+            ApertureShape::Mask(mask) => mask.sample(),
+        }
+    }
+
+    /// Area of the aperture in the units of `sample`. Masks count as uniform over their mean
+    /// transmission.
+    fn area(&self) -> f64 {
+        match self {
+            ApertureShape::Circle => PI,
+            ApertureShape::Polygon { blades, .. } => {
+                let n = (*blades).max(3) as f64;
+                0.5 * n * (2.0 * PI / n).sin()
+            }
+            ApertureShape::Mask(mask) => {
+                4.0 * mask.weights.iter().sum::<f64>() / mask.weights.len().max(1) as f64
+            }
+        }
+    }
+}
+
+/// A lens point joined to a point in the scene, as returned by `Camera::sample_wi`.
+#[derive(Clone, Copy, Debug)]
+pub struct ImportanceSample {
+    pub lens: Vec3,
+    /// Film coordinates in `[0, 1]` the connection lands on.
+    pub s: f64,
+    pub t: f64,
+    pub importance: f64,
+    /// Solid-angle density of the connection as seen from the scene point.
+    pub pdf: f64,
+}
+
 #[derive(Clone)]
 pub struct Camera {
-    // aspect_ratio: f64,
-    // vfov: f64,
-    // aperture: f64,
-    // focus_dist: f64,
+    aspect_ratio: f64,
+    vfov: f64,
+    focus_dist: f64,
+    vup: Vec3,
     origin: Vec3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
@@ -20,6 +138,11 @@ pub struct Camera {
     lens_radius: f64,
     time_0: f64,
     time_1: f64,
+    aperture_shape: ApertureShape,
+    cats_eye: f64,
+    lateral_ca: f64,
+    axial_ca: f64,
+    pixel_spread: f64,
 }
 
 impl Camera {
@@ -33,44 +156,566 @@ impl Camera {
         combine: (f64, f64, f64),
     ) -> Self {
         let (focus_dist, _time_0, _time_1) = combine;
-        let theta = degrees_to_radians(vfov);
+        let mut cam = Self {
+            aspect_ratio,
+            vfov,
+            focus_dist,
+            vup: *vup,
+            origin: Vec3::zero(),
+            lower_left_corner: Vec3::zero(),
+            horizontal: Vec3::zero(),
+            vertical: Vec3::zero(),
+            u: Vec3::zero(),
+            v: Vec3::zero(),
+            w: Vec3::zero(),
+            lens_radius: aperture / 2.0,
+            time_0: _time_0,
+            time_1: _time_1,
+            aperture_shape: ApertureShape::Circle,
+            cats_eye: 0.0,
+            lateral_ca: 0.0,
+            axial_ca: 0.0,
+            pixel_spread: 0.0,
+        };
+        cam.look_at(lookfrom, lookat);
+        cam
+    }
+
+    fn look_at(&mut self, lookfrom: &Vec3, lookat: &Vec3) {
+        let theta = degrees_to_radians(self.vfov);
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
+        let viewport_width = self.aspect_ratio * viewport_height;
 
         let w_0 = (*lookfrom - *lookat).unit();
-        let u_0 = Vec3::cross(vup, &w_0).unit();
+        let u_0 = Vec3::cross(&self.vup, &w_0).unit();
         let v_0 = Vec3::cross(&w_0, &u_0);
 
-        Self {
-            // aspect_ratio: 0.0,
-            // vfov: 0.0,
-            // aperture: 0.0,
-            // focus_dist: 0.0,
-            origin: *lookfrom,
-            horizontal: u_0 * viewport_width * focus_dist,
-            vertical: v_0 * viewport_height * focus_dist,
-            lower_left_corner: *lookfrom
-                - u_0 * viewport_width * focus_dist / 2.0
-                - v_0 * viewport_height * focus_dist / 2.0
-                - w_0 * focus_dist,
-            w: w_0,
-            u: u_0,
-            v: v_0,
-            lens_radius: aperture / 2.0,
-            time_0: _time_0,
-            time_1: _time_1,
+        self.origin = *lookfrom;
+        self.horizontal = u_0 * viewport_width * self.focus_dist;
+        self.vertical = v_0 * viewport_height * self.focus_dist;
+        self.lower_left_corner = *lookfrom
+            - u_0 * viewport_width * self.focus_dist / 2.0
+            - v_0 * viewport_height * self.focus_dist / 2.0
+            - w_0 * self.focus_dist;
+        self.w = w_0;
+        self.u = u_0;
+        self.v = v_0;
+    }
+
+    /// Gives primary rays a cone one pixel wide, which drives texture filtering.
+    pub fn set_image_height(&mut self, height: usize) {
+        let h = (degrees_to_radians(self.vfov) / 2.0).tan();
+        self.pixel_spread = 2.0 * h / height as f64;
+    }
+
+    pub fn set_aperture_shape(&mut self, shape: ApertureShape) {
+        self.aperture_shape = shape;
+    }
+
+    /// Clips the aperture by an exit pupil that slides outwards with distance from the
+    /// image centre, giving cat's-eye bokeh and natural vignetting at the corners.
+    pub fn set_cats_eye(&mut self, amount: f64) {
+        self.cats_eye = amount;
+    }
+
+    /// Lateral aberration scales the image per channel, axial aberration moves the
+    /// focal plane per channel. Red and blue move in opposite directions around green.
+    pub fn set_chromatic_aberration(&mut self, lateral: f64, axial: f64) {
+        self.lateral_ca = lateral;
+        self.axial_ca = axial;
+    }
+
+    /// Whether aperture point `rd` passes the cat's-eye exit pupil for film point `(s, t)`.
+    fn inside_pupil(&self, rd: &Vec3, s: f64, t: f64) -> bool {
+        let mut pupil = Vec3::new(s - 0.5, t - 0.5, 0.0) * (2.0 * self.cats_eye);
+        if pupil.length() > 1.0 {
+            pupil = pupil.unit();
         }
+        (*rd - pupil).length() <= 1.0
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+    fn lens_area(&self) -> f64 {
+        if self.lens_radius <= 0.0 {
+            1.0
+        } else {
+            self.lens_radius * self.lens_radius * self.aperture_shape.area()
+        }
+    }
+
+    /// Whether primary rays carry a single colour channel, see `set_chromatic_aberration`.
+    /// `we` has no per-channel answer, so light tracing cannot reproduce the aberration.
+    pub fn has_chromatic_aberration(&self) -> bool {
+        self.lateral_ca != 0.0 || self.axial_ca != 0.0
+    }
+
+    /// Where a ray leaving the lens crosses the film, and the importance it carries. Used to
+    /// trace light towards the camera. The cat's-eye pupil clips it like it clips primary
+    /// rays; chromatic aberration is ignored.
+    pub fn we(&self, r: &Ray, s: &mut f64, t: &mut f64) -> f64 {
+        let direction = r.direc().unit();
+        let cos_theta = -(direction * self.w);
+        if cos_theta <= 0.0 {
+            return 0.0;
+        }
+        let focus = r.ori() + direction * (self.focus_dist / cos_theta);
+        let offset = focus - self.lower_left_corner;
+        *s = offset * self.horizontal / self.horizontal.squared_length();
+        *t = offset * self.vertical / self.vertical.squared_length();
+        if !(0.0..=1.0).contains(s) || !(0.0..=1.0).contains(t) {
+            return 0.0;
+        }
+        if self.cats_eye > 0.0 && self.lens_radius > 0.0 {
+            let lens = r.ori() - self.origin;
+            let rd = Vec3::new(lens * self.u, lens * self.v, 0.0) / self.lens_radius;
+            if !self.inside_pupil(&rd, *s, *t) {
+                return 0.0;
+            }
+        }
+        let film_area =
+            self.horizontal.length() * self.vertical.length() / (self.focus_dist * self.focus_dist);
+        1.0 / (film_area * self.lens_area() * cos_theta.powi(4))
+    }
+
+    /// Area density of the lens point and solid-angle density of the direction with which
+    /// primary rays along `r` are generated.
+    pub fn pdf_we(&self, r: &Ray) -> (f64, f64) {
+        let (mut s, mut t) = (0.0, 0.0);
+        if self.we(r, &mut s, &mut t) <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let cos_theta = -(r.direc().unit() * self.w);
+        let film_area =
+            self.horizontal.length() * self.vertical.length() / (self.focus_dist * self.focus_dist);
+        (
+            1.0 / self.lens_area(),
+            1.0 / (film_area * cos_theta.powi(3)),
+        )
+    }
+
+    /// Joins `p` to a random point on the lens. False when `p` is out of view.
+    pub fn sample_wi(&self, p: &Vec3, sample: &mut ImportanceSample) -> bool {
+        let rd = self.aperture_shape.sample() * self.lens_radius;
+        let lens = self.origin + self.u * rd.x + self.v * rd.y;
+        let to_point = *p - lens;
+        let distance = to_point.length();
+        if distance == 0.0 {
+            return false;
+        }
+        let r = Ray::new(
+            lens,
+            to_point / distance,
+            random_f64_1(self.time_0, self.time_1),
+        );
+        let (mut s, mut t) = (0.0, 0.0);
+        let importance = self.we(&r, &mut s, &mut t);
+        if importance <= 0.0 {
+            return false;
+        }
+        let cos_theta = -(r.direc() * self.w);
+        *sample = ImportanceSample {
+            lens,
+            s,
+            t,
+            importance,
+            pdf: distance * distance / (cos_theta * self.lens_area()),
+        };
+        true
+    }
+}
+
+impl Projection for Camera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        self.get_ray_weighted(s, t).0
+    }
+
+    fn get_ray_weighted(&self, s: f64, t: f64) -> (Ray, Vec3) {
+        let mut weight = Vec3::ones();
+        let mut s = s;
+        let mut t = t;
+        let mut focus = 1.0;
+        if self.lateral_ca != 0.0 || self.axial_ca != 0.0 {
+            // Trace a single channel per sample and weight it up to keep the mean.
+            let channel = ((random_f64() * 3.0) as usize).min(2);
+            let k = channel as f64 - 1.0;
+            weight = Vec3::zero();
+            match channel {
+                0 => weight.x = 3.0,
+                1 => weight.y = 3.0,
+                _ => weight.z = 3.0,
+            }
+            s = 0.5 + (s - 0.5) * (1.0 + self.lateral_ca * k);
+            t = 0.5 + (t - 0.5) * (1.0 + self.lateral_ca * k);
+            focus = 1.0 + self.axial_ca * k;
+        }
+
+        let rd = self.aperture_shape.sample();
+        if self.cats_eye > 0.0 && !self.inside_pupil(&rd, s, t) {
+            weight = Vec3::zero();
+        }
+        let rd = rd * self.lens_radius;
         let offest = self.u * rd.x + self.v * rd.y;
+        let target = self.lower_left_corner + self.horizontal * s + self.vertical * t;
+        let target = self.origin + (target - self.origin) * focus;
 
-        Ray::new(
+        let mut r = Ray::new(
             self.origin + offest,
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offest,
+            target - self.origin - offest,
             random_f64_1(self.time_0, self.time_1),
+        );
+        r.spread = self.pixel_spread;
+        (r, weight)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StereoMode {
+    /// Parallel optical axes, converged by shifting each eye's viewport (off-axis).
+    Parallel,
+    /// Both eyes rotated to look at the convergence point.
+    ToeIn,
+    /// Omni-directional stereo: one equirectangular panorama per eye.
+    Ods,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StereoLayout {
+    /// Left eye on the left half of the image, right eye on the right half.
+    SideBySide,
+    /// Left eye on the top half of the image, right eye on the bottom half.
+    OverUnder,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct StereoRig {
+    pub mode: StereoMode,
+    pub layout: StereoLayout,
+    pub interocular: f64,
+    pub convergence: f64,
+}
+
+impl StereoRig {
+    pub fn new(mode: StereoMode, layout: StereoLayout, interocular: f64, convergence: f64) -> Self {
+        Self {
+            mode,
+            layout,
+            interocular,
+            convergence,
+        }
+    }
+}
+
+/// A pair of eyes packed into one output image according to `StereoLayout`.
+#[derive(Clone)]
+pub struct StereoCamera {
+    rig: StereoRig,
+    center: Camera,
+    left: Camera,
+    right: Camera,
+}
+
+impl StereoCamera {
+    /// Builds both eyes from `base`, whose aspect ratio is that of the whole packed image.
+    pub fn new(base: &Camera, rig: StereoRig) -> Self {
+        let mut eye = base.clone();
+        eye.aspect_ratio = match rig.layout {
+            StereoLayout::SideBySide => base.aspect_ratio / 2.0,
+            StereoLayout::OverUnder => base.aspect_ratio * 2.0,
+        };
+
+        let half = rig.interocular / 2.0;
+        let forward = -base.w;
+        let mut left = eye.clone();
+        let mut right = eye;
+        for (cam, side) in [(&mut left, -1.0), (&mut right, 1.0)] {
+            let offset = base.u * (side * half);
+            let from = base.origin + offset;
+            match rig.mode {
+                StereoMode::ToeIn => {
+                    cam.look_at(&from, &(base.origin + forward * rig.convergence));
+                }
+                _ => {
+                    cam.look_at(&from, &(from + forward));
+                    // Move the zero-parallax plane out to the convergence distance.
+                    if rig.convergence > 0.0 {
+                        cam.lower_left_corner -=
+                            cam.u * (side * half * cam.focus_dist / rig.convergence);
+                    }
+                }
+            }
+        }
+
+        Self {
+            rig,
+            center: base.clone(),
+            left,
+            right,
+        }
+    }
+
+    /// Splits packed film coordinates into (is_left_eye, s, t) for one eye.
+    fn unpack(&self, s: f64, t: f64) -> (bool, f64, f64) {
+        match self.rig.layout {
+            StereoLayout::SideBySide => {
+                if s < 0.5 {
+                    (true, s * 2.0, t)
+                } else {
+                    (false, s * 2.0 - 1.0, t)
+                }
+            }
+            StereoLayout::OverUnder => {
+                if t >= 0.5 {
+                    (true, s, t * 2.0 - 1.0)
+                } else {
+                    (false, s, t * 2.0)
+                }
+            }
+        }
+    }
+
+    fn ods_ray(&self, left: bool, s: f64, t: f64) -> Ray {
+        let cam = &self.center;
+        let theta = (2.0 * s - 1.0) * PI;
+        let phi = (t - 0.5) * PI;
+        let direction = cam.u * (theta.sin() * phi.cos()) + cam.v * phi.sin()
+            - cam.w * (theta.cos() * phi.cos());
+        // Each eye sits on the viewing circle, tangent to the horizontal view direction.
+        let side = if left { -1.0 } else { 1.0 };
+        let offset =
+            (cam.u * theta.cos() + cam.w * theta.sin()) * (side * self.rig.interocular / 2.0);
+
+        let mut r = Ray::new(
+            cam.origin + offset,
+            direction,
+            random_f64_1(cam.time_0, cam.time_1),
+        );
+        r.spread = cam.pixel_spread;
+        r
+    }
+}
+
+impl Projection for StereoCamera {
+    fn get_ray(&self, s: f64, t: f64) -> Ray {
+        self.get_ray_weighted(s, t).0
+    }
+
+    fn get_ray_weighted(&self, s: f64, t: f64) -> (Ray, Vec3) {
+        let (left, s, t) = self.unpack(s, t);
+        match self.rig.mode {
+            StereoMode::Ods => (self.ods_ray(left, s, t), Vec3::ones()),
+            _ if left => self.left.get_ray_weighted(s, t),
+            _ => self.right.get_ray_weighted(s, t),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_polygon_aperture_inside_blades() {
+        let shape = ApertureShape::Polygon {
+            blades: 6,
+            rotation: 0.0,
+        };
+        // The inscribed circle of a hexagon has radius cos(30 deg).
+        let apothem = (PI / 6.0).cos();
+        for _ in 0..1000 {
+            let p = shape.sample();
+            assert!(p.length() <= 1.0 + 1e-9);
+            let angle = p.y.atan2(p.x).rem_euclid(PI / 3.0) - PI / 6.0;
+            assert!(p.length() * angle.cos() <= apothem + 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_mask_samples_lit_texels_only() {
+        assert!(ApertureMask::from_weights(vec![0.0; 4], 2, 2).is_none());
+
+        // Only the top-left texel is open.
+        let mask = ApertureMask::from_weights(vec![1.0, 0.0, 0.0, 0.0], 2, 2).unwrap();
+        let shape = ApertureShape::Mask(Arc::new(mask));
+        for _ in 0..1000 {
+            let p = shape.sample();
+            assert!(
+                (-1.0..=0.0).contains(&p.x) && (0.0..=1.0).contains(&p.y),
+                "{:?}",
+                p
+            );
+        }
+        assert!((shape.area() - 1.0).abs() < 1e-12);
+    }
+
+    fn test_camera(aperture: f64) -> Camera {
+        Camera::new(
+            1.0,
+            &Vec3::zero(),
+            &Vec3::new(0.0, 0.0, -1.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            aperture,
+            (4.0, 0.0, 1.0),
         )
     }
+
+    #[test]
+    fn test_cats_eye_clips_corners_only() {
+        let mut cam = test_camera(0.5);
+        cam.set_cats_eye(1.0);
+        let mut clipped = 0;
+        for _ in 0..1000 {
+            let (_, weight) = cam.get_ray_weighted(0.5, 0.5);
+            assert_eq!(weight, Vec3::ones());
+
+            let (r, weight) = cam.get_ray_weighted(0.95, 0.95);
+            let (mut s, mut t) = (0.0, 0.0);
+            let importance = cam.we(&r, &mut s, &mut t);
+            // Light tracing sees the same pupil as the primary rays.
+            assert_eq!(weight == Vec3::zero(), importance == 0.0);
+            if importance == 0.0 {
+                clipped += 1;
+            }
+        }
+        assert!(clipped > 100 && clipped < 900, "{}", clipped);
+    }
+
+    #[test]
+    fn test_chromatic_aberration_splits_channels() {
+        let mut cam = test_camera(0.0);
+        cam.set_chromatic_aberration(0.1, 0.0);
+        assert!(cam.has_chromatic_aberration());
+        let mut mean = Vec3::zero();
+        let n = 3000;
+        for _ in 0..n {
+            let (r, weight) = cam.get_ray_weighted(0.9, 0.5);
+            mean += weight / n as f64;
+            // One channel per ray, scaled up by three.
+            let channels = [weight.x, weight.y, weight.z];
+            assert_eq!(channels.iter().filter(|w| **w == 3.0).count(), 1);
+            assert_eq!(channels.iter().filter(|w| **w == 0.0).count(), 2);
+
+            // Red is pulled towards the centre and blue pushed out, around green.
+            let k = if weight.x > 0.0 {
+                -1.0
+            } else if weight.y > 0.0 {
+                0.0
+            } else {
+                1.0
+            };
+            let (mut s, mut t) = (0.0, 0.0);
+            assert!(cam.we(&r, &mut s, &mut t) > 0.0);
+            assert!((s - (0.5 + 0.4 * (1.0 + 0.1 * k))).abs() < 1e-9, "{}", s);
+            assert!((t - 0.5).abs() < 1e-9);
+        }
+        assert!((mean - Vec3::ones()).length() < 0.2, "{:?}", mean);
+    }
+
+    #[test]
+    fn test_importance_matches_primary_rays() {
+        let cam = Camera::new(
+            1.5,
+            &Vec3::zero(),
+            &Vec3::new(0.0, 0.0, -1.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            0.2,
+            (4.0, 0.0, 1.0),
+        );
+        // A primary ray comes back to its film coordinates.
+        let r = cam.get_ray(0.25, 0.7);
+        let (mut s, mut t) = (0.0, 0.0);
+        assert!(cam.we(&r, &mut s, &mut t) > 0.0);
+        assert!((s - 0.25).abs() < 1e-9 && (t - 0.7).abs() < 1e-9);
+
+        // The directional density integrates to one over the field of view.
+        let n = 400;
+        let mut integral = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let target = cam.lower_left_corner
+                    + cam.horizontal * ((i as f64 + 0.5) / n as f64)
+                    + cam.vertical * ((j as f64 + 0.5) / n as f64);
+                let d = target - cam.origin;
+                let cos_theta = -(d.unit() * cam.w);
+                let area = cam.horizontal.length() * cam.vertical.length() / (n * n) as f64;
+                let solid_angle = area * cos_theta / d.squared_length();
+                integral += cam.pdf_we(&Ray::new(cam.origin, d, 0.0)).1 * solid_angle;
+            }
+        }
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+
+        let mut sample = ImportanceSample {
+            lens: Vec3::zero(),
+            s: 0.0,
+            t: 0.0,
+            importance: 0.0,
+            pdf: 0.0,
+        };
+        assert!(cam.sample_wi(&Vec3::new(0.0, 0.0, -4.0), &mut sample));
+        assert!((sample.s - 0.5).abs() < 0.05 && (sample.t - 0.5).abs() < 0.05);
+        assert!(!cam.sample_wi(&Vec3::new(0.0, 0.0, 4.0), &mut sample));
+    }
+
+    fn stereo(mode: StereoMode, layout: StereoLayout) -> StereoCamera {
+        let base = Camera::new(
+            2.0,
+            &Vec3::zero(),
+            &Vec3::new(0.0, 0.0, -1.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+            60.0,
+            0.0,
+            (4.0, 0.0, 1.0),
+        );
+        StereoCamera::new(&base, StereoRig::new(mode, layout, 0.064, 2.0))
+    }
+
+    #[test]
+    fn test_stereo_unpack_layouts() {
+        let side = stereo(StereoMode::Parallel, StereoLayout::SideBySide);
+        assert_eq!(side.unpack(0.25, 0.3), (true, 0.5, 0.3));
+        assert_eq!(side.unpack(0.75, 0.3), (false, 0.5, 0.3));
+        assert!((side.left.aspect_ratio - 1.0).abs() < 1e-12);
+
+        let over = stereo(StereoMode::Parallel, StereoLayout::OverUnder);
+        assert_eq!(over.unpack(0.3, 0.75), (true, 0.3, 0.5));
+        assert_eq!(over.unpack(0.3, 0.25), (false, 0.3, 0.5));
+        assert!((over.left.aspect_ratio - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_stereo_eyes_converge() {
+        // Both eyes' centre rays meet on the axis at the convergence distance.
+        let target = Vec3::new(0.0, 0.0, -2.0);
+        for mode in [StereoMode::Parallel, StereoMode::ToeIn] {
+            let cam = stereo(mode, StereoLayout::SideBySide);
+            for s in [0.25, 0.75] {
+                let r = cam.get_ray(s, 0.5);
+                assert!((r.ori().x().abs() - 0.032).abs() < 1e-12);
+                let to_target = (target - r.ori()).unit();
+                assert!((r.direc().unit() - to_target).length() < 1e-9, "{:?}", mode);
+            }
+            // Parallel rigs keep the optical axes parallel and only shift the film.
+            if mode == StereoMode::Parallel {
+                assert!((cam.left.w - cam.right.w).length() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ods_eye_offset_is_tangent() {
+        let cam = stereo(StereoMode::Ods, StereoLayout::OverUnder);
+        for _ in 0..100 {
+            let (s, t) = (random_f64(), random_f64() * 0.5);
+            let left = cam.get_ray(s, t + 0.5);
+            let right = cam.get_ray(s, t);
+            for r in [&left, &right] {
+                let offset = r.ori() - cam.center.origin;
+                assert!((offset.length() - 0.032).abs() < 1e-12);
+                assert!((offset * r.direc().unit()).abs() < 1e-9);
+            }
+            // Both eyes look the same way from opposite sides of the viewing circle.
+            assert!((left.direc() - right.direc()).length() < 1e-12);
+            assert!((left.ori() + right.ori()).length() < 1e-12);
+        }
+    }
 }
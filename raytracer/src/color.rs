@@ -28,3 +28,48 @@ pub fn write_color(color: &Vec3, img: &mut RgbImage, i: usize, j: usize, samples
     *pixel = image::Rgb(pixel_color);
     // Write the translated [0,255] value of each color component.
 }
+
+/// Linear sRGB colour of a black body at `kelvin`, scaled to unit luminance. Planck's law is
+/// integrated against Wyman, Sloan and Shirley's fit of the CIE 1931 matching functions.
+pub fn blackbody(kelvin: f64) -> Vec3 {
+    let lobe = |x: f64, mu: f64, sigma_lo: f64, sigma_hi: f64| {
+        let sigma = if x < mu { sigma_lo } else { sigma_hi };
+        (-(x - mu) * (x - mu) / (2.0 * sigma * sigma)).exp()
+    };
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for step in 0..=80 {
+        let nm = 380.0 + 5.0 * step as f64;
+        let lambda = nm * 1e-9;
+        let radiance = lambda.powi(-5) / ((1.4388e-2 / (lambda * kelvin.max(1.0))).exp() - 1.0);
+        x += radiance
+            * (1.056 * lobe(nm, 599.8, 37.9, 31.0) + 0.362 * lobe(nm, 442.0, 16.0, 26.7)
+                - 0.065 * lobe(nm, 501.1, 20.4, 26.2));
+        y += radiance * (0.821 * lobe(nm, 568.8, 46.9, 40.5) + 0.286 * lobe(nm, 530.9, 16.3, 31.1));
+        z += radiance * (1.217 * lobe(nm, 437.0, 11.8, 36.0) + 0.681 * lobe(nm, 459.0, 26.0, 13.8));
+    }
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+    let (x, z) = (x / y, z / y);
+    Vec3::new(
+        (3.2406 * x - 1.5372 - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 + 1.0570 * z).max(0.0),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blackbody_colours() {
+        let candle = blackbody(1900.0);
+        let daylight = blackbody(6504.0);
+        let sky = blackbody(12000.0);
+        assert!(candle.x() > candle.y() && candle.y() > candle.z());
+        assert!(sky.z() > sky.x());
+        // D65 is close to, though not exactly, a 6500 K black body.
+        assert!((daylight.x() - daylight.z()).abs() < 0.15, "{:?}", daylight);
+    }
+}
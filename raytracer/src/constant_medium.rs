@@ -1,8 +1,10 @@
+use crate::error::{Error, Result};
 pub use crate::hiitable::Hiitable;
 pub use crate::hittable_list::HitRecord;
 pub use crate::material::{Isotropic, Material};
+pub use crate::phase::PhaseFunction;
 use crate::random_f64;
-pub use crate::texture::Texture;
+pub use crate::texture::{SolidColor, Texture};
 pub use crate::vec3::Vec3;
 
 use std::sync::Arc;
@@ -11,33 +13,73 @@ const INFINITY: f64 = f64::INFINITY;
 
 pub struct ConstantMedium {
     boundary: Option<Arc<dyn Hiitable>>,
+    albedo: Option<Arc<dyn Texture>>,
     phase_function: Option<Arc<dyn Material>>,
     neg_inv_density: f64,
+    extent: f64,
 }
 
 impl ConstantMedium {
-    pub fn new1(b: Option<Arc<dyn Hiitable>>, d: f64, a: Option<Arc<dyn Texture>>) -> Self {
-        Self {
+    pub fn new1(b: Option<Arc<dyn Hiitable>>, d: f64, a: Option<Arc<dyn Texture>>) -> Result<Self> {
+        Ok(Self {
             boundary: b,
-            neg_inv_density: -1.0 / d,
-            phase_function: Some(Arc::new(Isotropic::new2(a))),
-        }
+            neg_inv_density: neg_inv_density(d)?,
+            phase_function: Some(Arc::new(Isotropic::new2(a.clone()))),
+            albedo: a,
+            extent: INFINITY,
+        })
     }
-    pub fn new2(b: Option<Arc<dyn Hiitable>>, d: f64, c: Vec3) -> Self {
-        Self {
-            boundary: b,
-            neg_inv_density: -1.0 / d,
-            phase_function: Some(Arc::new(Isotropic::new1(c))),
+    pub fn new2(b: Option<Arc<dyn Hiitable>>, d: f64, c: Vec3) -> Result<Self> {
+        ConstantMedium::new1(b, d, Some(Arc::new(SolidColor::new(c))))
+    }
+
+    /// Fog filling all of space, camera included, for scene-wide haze. Add it to the
+    /// top-level list rather than a `BvhNode`, since it has no bounding box.
+    ///
+    /// Every ray sees fog only up to `extent` from its origin, so rays that get that far
+    /// reach the environment attenuated instead of always scattering. Pick something around
+    /// the size of the scene.
+    pub fn new_global(d: f64, c: Vec3, extent: f64) -> Result<Self> {
+        let mut fog = ConstantMedium::new2(None, d, c)?;
+        fog.extent = extent.max(0.0);
+        Ok(fog)
+    }
+
+    /// Replaces the isotropic phase function, e.g. with `HenyeyGreenstein` for haze.
+    pub fn set_phase(&mut self, phase: Option<Arc<dyn PhaseFunction>>) {
+        let mut phase_function = Isotropic::new2(self.albedo.clone());
+        phase_function.set_phase(phase);
+        self.phase_function = Some(Arc::new(phase_function));
+    }
+
+    fn segments(&self, r: &crate::Ray, t_min: f64, t_max: f64) -> Vec<(f64, f64)> {
+        match &self.boundary {
+            Some(b) => boundary_segments(b.as_ref(), r, t_min, t_max),
+            None => {
+                let t_end = t_max.min(self.extent / r.direc().length());
+                if t_min < t_end {
+                    vec![(t_min, t_end)]
+                } else {
+                    Vec::new()
+                }
+            }
         }
     }
 }
 
+fn neg_inv_density(d: f64) -> Result<f64> {
+    if d > 0.0 && d.is_finite() {
+        Ok(-1.0 / d)
+    } else {
+        Err(Error::InvalidDensity(d))
+    }
+}
+
 impl Hiitable for ConstantMedium {
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut crate::r#box::AAbb) -> bool {
         self.boundary
-            .clone()
-            .unwrap()
-            .bounding_box(time0, time1, output_box)
+            .as_ref()
+            .is_some_and(|b| b.bounding_box(time0, time1, output_box))
     }
 
     fn hit(
@@ -47,76 +89,160 @@ impl Hiitable for ConstantMedium {
         t_max: f64,
         rec: &mut crate::material::HitRecord,
     ) -> bool {
-        let enabledebug = false;
-        let debugging = enabledebug && random_f64() < 0.00001;
-
-        let mut rec1: HitRecord = HitRecord::new();
-        let mut rec2: HitRecord = HitRecord::new();
-
-        if !self
-            .boundary
-            .clone()
-            .unwrap()
-            .hit(r, -INFINITY, INFINITY, &mut rec1)
-        {
-            return false;
-        }
-
-        if !self
-            .boundary
-            .clone()
-            .unwrap()
-            .hit(r, rec1.t + 0.0001, INFINITY, &mut rec2)
-        {
-            return false;
-        }
-
-        if debugging {
-            println!("\nt_min={},\nt_max={}", rec1.t, rec2.t);
-        }
-
-        if rec1.t < t_min {
-            rec1.t = t_min;
-        }
-
-        if rec2.t > t_max {
-            rec2.t = t_max;
+        let ray_length = r.direc().length();
+        let mut hit_distance = self.neg_inv_density * random_f64().ln();
+
+        for (t0, t1) in self.segments(r, t_min, t_max) {
+            let distance_inside_boundary = (t1 - t0) * ray_length;
+            if hit_distance > distance_inside_boundary {
+                hit_distance -= distance_inside_boundary;
+                continue;
+            }
+            rec.t = t0 + hit_distance / ray_length;
+            rec.point3 = r.at(rec.t);
+            rec.normal = Vec3::new(1.0, 0.0, 0.0);
+            rec.front_size = true;
+            rec.mat = self.phase_function.clone();
+            return true;
         }
+        false
+    }
 
-        if rec1.t >= rec2.t {
-            return false;
-        }
+    fn transmittance(&self, r: &crate::Ray, t_min: f64, t_max: f64) -> f64 {
+        let distance: f64 = self
+            .segments(r, t_min, t_max)
+            .iter()
+            .map(|(t0, t1)| (t1 - t0) * r.direc().length())
+            .sum();
+        // Beer-Lambert: exp(-density * distance).
+        (distance / self.neg_inv_density).exp()
+    }
+}
 
-        if rec1.t < 0.0 {
-            rec1.t = 0.0;
+/// Most crossings of a boundary followed along one ray.
+const MAX_CROSSINGS: usize = 64;
+
+/// The parts of `r` inside a closed `boundary`, clipped to `[t_min, t_max]`. Crossings are
+/// counted from the far past of the line, so the boundary may be non-convex, made of several
+/// pieces, or contain the ray origin, and the orientation of its normals does not matter.
+pub fn boundary_segments(
+    boundary: &dyn Hiitable,
+    r: &crate::Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Vec<(f64, f64)> {
+    let mut segments = Vec::new();
+    let mut entered = -INFINITY;
+    for_each_crossing(boundary, r, |inside, rec| {
+        if inside {
+            let (t0, t1) = (entered.max(t_min), rec.t.min(t_max));
+            if t0 < t1 {
+                segments.push((t0, t1));
+            }
+        } else {
+            entered = rec.t;
         }
+        rec.t < t_max
+    });
+    segments
+}
 
-        let ray_length = r.direc().length();
-        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
-        let hit_distance = self.neg_inv_density * random_f64().ln();
-
-        if hit_distance > distance_inside_boundary {
+/// The first crossing after `t_min` where `r` leaves a closed `boundary`, counted like
+/// `boundary_segments`. Its normal faces back inside.
+pub fn boundary_exit(boundary: &dyn Hiitable, r: &crate::Ray, t_min: f64) -> Option<HitRecord> {
+    let mut exit = None;
+    for_each_crossing(boundary, r, |inside, rec| {
+        if inside && rec.t > t_min {
+            exit = Some(rec.clone());
             return false;
         }
+        true
+    });
+    exit
+}
 
-        rec.t = rec1.t + hit_distance / ray_length;
-        rec.point3 = r.at(rec.t);
-
-        if debugging {
-            println!("hit_distance = {}\n", hit_distance);
-            println!("rec.t = {}\n", rec.t);
-            println!(
-                "rec.p.x = {},rec.p.y = {}. rec.p.z = {}.",
-                rec.point3.x(),
-                rec.point3.y(),
-                rec.point3.z()
-            );
+/// Calls `visit` with each crossing of `boundary` along the whole line of `r` in order, and
+/// whether the line was inside before it, until `visit` returns false.
+fn for_each_crossing(
+    boundary: &dyn Hiitable,
+    r: &crate::Ray,
+    mut visit: impl FnMut(bool, &HitRecord) -> bool,
+) {
+    let mut inside = false;
+    let mut t = -INFINITY;
+    for _ in 0..MAX_CROSSINGS {
+        let mut rec: HitRecord = HitRecord::new();
+        if !boundary.hit(r, t, INFINITY, &mut rec) || !visit(inside, &rec) {
+            break;
         }
+        inside = !inside;
+        t = rec.t + 0.0001;
+    }
+}
 
-        rec.normal = Vec3::new(1.0, 0.0, 0.0);
-        rec.front_size = true;
-        rec.mat = self.phase_function.clone();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::BvhNode;
+    use crate::environment::ConstantEnvironment;
+    use crate::hittable_list::HittableList;
+    use crate::integrator::Integrator;
+    use crate::light::LightList;
+    use crate::object::Sphere;
+    use crate::path_tracer::{PathSettings, PathTracer};
+    use crate::Ray;
+
+    #[test]
+    fn test_transmittance_through_disjoint_and_surrounding_boundaries() {
+        let mut spheres = HittableList::new();
+        spheres.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(-3.0, 0.0, 0.0),
+            1.0,
+            None,
+        ))));
+        spheres.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(3.0, 0.0, 0.0),
+            1.0,
+            None,
+        ))));
+        let boundary: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(BvhNode::new2(&mut spheres, 0.0, 1.0)));
+        let medium = ConstantMedium::new2(boundary, 0.5, Vec3::ones()).unwrap();
+
+        // Both spheres: two units of medium in each.
+        let through = Ray::new(Vec3::new(-10.0, 0.0, 0.0), Vec3::new(1.0, 0.0, 0.0), 0.0);
+        let expected = (-2.0f64).exp();
+        assert!((medium.transmittance(&through, 0.001, INFINITY) - expected).abs() < 1e-9);
+
+        // Starting at the centre of the second sphere.
+        let inside = Ray::new(Vec3::new(3.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0), 0.0);
+        let expected = (-0.5f64).exp();
+        assert!((medium.transmittance(&inside, 0.0, INFINITY) - expected).abs() < 1e-9);
+
+        let fog = ConstantMedium::new_global(0.5, Vec3::ones(), 6.0).unwrap();
+        assert!((fog.transmittance(&through, 0.0, 2.0) - (-1.0f64).exp()).abs() < 1e-9);
+        let expected = (-3.0f64).exp();
+        assert!((fog.transmittance(&through, 0.0, INFINITY) - expected).abs() < 1e-9);
+        assert_eq!(fog.transmittance(&through, 7.0, INFINITY), 1.0);
+    }
 
-        true
+    #[test]
+    fn test_environment_through_global_fog() {
+        // Black fog only absorbs, so the sky comes through dimmed by the transmittance.
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(
+            ConstantMedium::new_global(0.1, Vec3::zero(), 5.0).unwrap(),
+        )));
+        let background = ConstantEnvironment::new(Vec3::ones());
+        let tracer = PathTracer::new(PathSettings::default());
+        let lights = LightList::new();
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let n = 20_000;
+        let mean = (0..n)
+            .map(|_| tracer.radiance(&r, &background, &lights, &world).x())
+            .sum::<f64>()
+            / n as f64;
+        let expected = (-0.5f64).exp();
+        assert!((mean - expected).abs() < 0.02, "{} {}", mean, expected);
     }
 }
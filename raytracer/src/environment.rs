@@ -0,0 +1,194 @@
+use crate::error::Result;
+use crate::rtweekend::{degrees_to_radians, random_f64};
+pub use crate::sampling::Distribution2D;
+use crate::texture::{ImageTexture, Texture, TextureFilter, WrapMode};
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+
+/// Light arriving from infinitely far away, seen by rays that leave the scene.
+pub trait Environment: Send + Sync {
+    fn radiance(&self, direction: &Vec3) -> Vec3;
+
+    /// Picks a unit direction towards the environment and its solid-angle pdf. The default
+    /// samples the whole sphere uniformly.
+    fn sample(&self) -> (Vec3, f64) {
+        (Vec3::random_unit_vector(), 1.0 / (4.0 * PI))
+    }
+
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+}
+
+pub struct ConstantEnvironment {
+    color: Vec3,
+}
+
+impl ConstantEnvironment {
+    pub fn new(color: Vec3) -> Self {
+        Self { color }
+    }
+}
+
+impl Environment for ConstantEnvironment {
+    fn radiance(&self, _direction: &Vec3) -> Vec3 {
+        self.color
+    }
+
+    fn sample(&self) -> (Vec3, f64) {
+        (Vec3::random_unit_vector(), self.pdf(&Vec3::zero()))
+    }
+
+    // A black background is not worth a shadow ray.
+    fn pdf(&self, _direction: &Vec3) -> f64 {
+        if self.color.near_zero() {
+            0.0
+        } else {
+            1.0 / (4.0 * PI)
+        }
+    }
+}
+
+/// Blends from `horizon` at the nadir to `zenith` straight up, like the sky in the book.
+pub struct GradientEnvironment {
+    horizon: Vec3,
+    zenith: Vec3,
+}
+
+impl GradientEnvironment {
+    pub fn new(horizon: Vec3, zenith: Vec3) -> Self {
+        Self { horizon, zenith }
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let t = 0.5 * (direction.unit().y() + 1.0);
+        self.horizon * (1.0 - t) + self.zenith * t
+    }
+}
+
+/// An equirectangular (latitude-longitude) image, usually HDR, importance sampled by
+/// luminance so bright features such as the sun are found by shadow rays.
+pub struct ImageEnvironment {
+    image: ImageTexture,
+    distribution: Option<Distribution2D>,
+    intensity: f64,
+    rotation: f64,
+}
+
+impl ImageEnvironment {
+    pub fn new(filename: &str) -> Result<Self> {
+        Ok(ImageEnvironment::from_texture(ImageTexture::new(filename)?))
+    }
+
+    pub fn from_texture(mut image: ImageTexture) -> Self {
+        image.set_wrap(WrapMode::Repeat);
+        image.set_filter(TextureFilter::Bilinear);
+        image.set_mipmap(false);
+        let distribution = image.base_level().map(|(width, height, texels)| {
+            let (width, height) = (width as usize, height as usize);
+            let mut func = Vec::with_capacity(width * height);
+            for j in 0..height {
+                // Rows near the poles cover less solid angle.
+                let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+                for i in 0..width {
+                    func.push(texels[j * width + i].luminance() * sin_theta);
+                }
+            }
+            Distribution2D::new(&func, width, height)
+        });
+        Self {
+            image,
+            distribution,
+            intensity: 1.0,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn set_intensity(&mut self, intensity: f64) {
+        self.intensity = intensity;
+    }
+
+    /// Turns the map about the vertical axis, in degrees.
+    pub fn set_rotation(&mut self, degrees: f64) {
+        self.rotation = degrees;
+    }
+
+    fn rotate(&self, d: &Vec3, degrees: f64) -> Vec3 {
+        let (sin, cos) = degrees_to_radians(degrees).sin_cos();
+        Vec3::new(cos * d.x() + sin * d.z(), d.y(), -sin * d.x() + cos * d.z())
+    }
+
+    /// Image coordinates of a direction: `u` around the horizon, `v` from the zenith down.
+    fn direction_to_uv(&self, direction: &Vec3) -> (f64, f64) {
+        let d = self.rotate(&direction.unit(), -self.rotation);
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = (-d.z()).atan2(d.x()) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = (PI * v).sin_cos();
+        let (sin_phi, cos_phi) = (2.0 * PI * u).sin_cos();
+        let d = Vec3::new(-sin_theta * cos_phi, cos_theta, sin_theta * sin_phi);
+        self.rotate(&d, self.rotation)
+    }
+}
+
+impl Environment for ImageEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let (u, v) = self.direction_to_uv(direction);
+        self.image.value(u, 1.0 - v, direction) * self.intensity
+    }
+
+    fn sample(&self) -> (Vec3, f64) {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return (Vec3::random_unit_vector(), 1.0 / (4.0 * PI)),
+        };
+        let (u, v, pdf) = distribution.sample(random_f64(), random_f64());
+        let sin_theta = (PI * v).sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return (Vec3::new(0.0, 1.0, 0.0), 0.0);
+        }
+        (
+            self.uv_to_direction(u, v),
+            pdf / (2.0 * PI * PI * sin_theta),
+        )
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let distribution = match &self.distribution {
+            Some(distribution) => distribution,
+            None => return 1.0 / (4.0 * PI),
+        };
+        let (u, v) = self.direction_to_uv(direction);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_environment_pdf_matches_samples() {
+        let mut texels = vec![Vec3::ones() * 0.1; 16 * 8];
+        texels[2 * 16 + 5] = Vec3::ones() * 50.0;
+        let mut env = ImageEnvironment::from_texture(ImageTexture::from_texels(16, 8, texels));
+        env.set_rotation(30.0);
+        for _ in 0..100 {
+            let (d, pdf) = env.sample();
+            assert!((d.length() - 1.0).abs() < 1e-9);
+            assert!((env.pdf(&d) - pdf).abs() < 1e-6 * pdf.max(1.0), "{}", pdf);
+        }
+        let (u, v) = env.direction_to_uv(&env.uv_to_direction(0.3, 0.7));
+        assert!((u - 0.3).abs() < 1e-9 && (v - 0.7).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+
+/// Everything that can go wrong while building a scene. Rendering itself does not fail.
+#[derive(Debug)]
+pub enum Error {
+    /// A texture or aperture mask could not be opened or decoded.
+    Image {
+        path: String,
+        source: image::ImageError,
+    },
+    /// An aperture mask image is black everywhere, so no light gets through.
+    EmptyApertureMask { path: String },
+    /// The image decoded to a pixel layout the texture code does not understand.
+    UnsupportedPixelFormat {
+        path: String,
+        color: image::ColorType,
+    },
+    /// Participating media need a positive, finite density.
+    InvalidDensity(f64),
+    /// Lights given by their power need a positive, finite emitting area.
+    InvalidArea(f64),
+    /// A scene input file could not be read.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// A texture graph description could not be parsed.
+    Graph { line: usize, message: String },
+    /// A volume grid file is malformed.
+    Volume { path: String, message: String },
+    /// An IES photometric file could not be parsed.
+    Photometry { path: String, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Image { path, source } => {
+                write!(f, "cannot load image \"{}\": {}", path, source)
+            }
+            Error::UnsupportedPixelFormat { path, color } => {
+                write!(
+                    f,
+                    "image \"{}\" has unsupported pixel format {:?}",
+                    path, color
+                )
+            }
+            Error::EmptyApertureMask { path } => {
+                write!(f, "aperture mask \"{}\" lets no light through", path)
+            }
+            Error::InvalidDensity(d) => {
+                write!(f, "medium density must be positive and finite, got {}", d)
+            }
+            Error::InvalidArea(a) => {
+                write!(f, "emitter area must be positive and finite, got {}", a)
+            }
+            Error::Io { path, source } => write!(f, "cannot read \"{}\": {}", path, source),
+            Error::Graph { line, message } => {
+                write!(f, "texture graph, line {}: {}", line, message)
+            }
+            Error::Volume { path, message } => {
+                write!(f, "volume grid \"{}\": {}", path, message)
+            }
+            Error::Photometry { path, message } => {
+                write!(f, "photometric file \"{}\": {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Image { source, .. } => Some(source),
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Opens an image file, attaching the path to any failure.
+pub fn open_image(path: &str) -> Result<image::DynamicImage> {
+    image::open(path).map_err(|source| Error::Image {
+        path: path.to_string(),
+        source,
+    })
+}
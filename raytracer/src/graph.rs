@@ -0,0 +1,550 @@
+use crate::error::{Error, Result};
+use crate::nodes::{
+    AddTexture, Channel, ChannelTexture, ClampTexture, HueSaturationTexture, InvertTexture,
+    LerpTexture, MultiplyTexture, RemapTexture, TriplanarTexture, UvTransformTexture,
+};
+use crate::procedural::{
+    ColorRamp, FbmTexture, GradientKind, GradientTexture, MarbleTexture, SimplexTexture,
+    UvCheckerTexture, WoodTexture, WorleyMode, WorleyTexture,
+};
+pub use crate::texture::{
+    CheckerTexture, NoiseTexture, SolidColor, Texture, TextureCache, WrapMode,
+};
+pub use crate::vec3::Vec3;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Reads a texture graph from a file, see `parse_texture_graph`.
+pub fn load_texture_graph(path: &str, cache: &TextureCache) -> Result<Option<Arc<dyn Texture>>> {
+    let src = std::fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    parse_texture_graph(&src, cache)
+}
+
+/// Builds a texture from a small expression language, one statement per line:
+///
+/// ```text
+/// # comments run to the end of the line
+/// blend = remap(fbm(4, 6, 2, 0.5), 0.45, 0.55, 0, 1)
+/// mix(image("a.jpg"), image("b.jpg"), blend)
+/// ```
+///
+/// Numbers and `[r, g, b]` colours are accepted wherever a texture is. Statements may span
+/// lines inside brackets. The value of the last statement is the result.
+pub fn parse_texture_graph(src: &str, cache: &TextureCache) -> Result<Option<Arc<dyn Texture>>> {
+    let mut parser = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+        bindings: HashMap::new(),
+        cache,
+    };
+    parser.program()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Equals,
+    Newline,
+}
+
+fn graph_error(line: usize, message: String) -> Error {
+    Error::Graph { line, message }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut depth = 0;
+    for (index, text) in src.lines().enumerate() {
+        let line = index + 1;
+        let mut chars = text.chars().peekable();
+        while let Some(&ch) = chars.peek() {
+            match ch {
+                '#' => break,
+                ' ' | '\t' | '\r' => {
+                    chars.next();
+                }
+                '(' | '[' => {
+                    depth += 1;
+                    chars.next();
+                    tokens.push((
+                        if ch == '(' {
+                            Token::LParen
+                        } else {
+                            Token::LBracket
+                        },
+                        line,
+                    ));
+                }
+                ')' | ']' => {
+                    if depth == 0 {
+                        return Err(graph_error(line, format!("unmatched `{}`", ch)));
+                    }
+                    depth -= 1;
+                    chars.next();
+                    tokens.push((
+                        if ch == ')' {
+                            Token::RParen
+                        } else {
+                            Token::RBracket
+                        },
+                        line,
+                    ));
+                }
+                ',' => {
+                    chars.next();
+                    tokens.push((Token::Comma, line));
+                }
+                '=' => {
+                    chars.next();
+                    tokens.push((Token::Equals, line));
+                }
+                '"' => {
+                    chars.next();
+                    let mut s = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c) => s.push(c),
+                            None => return Err(graph_error(line, "unterminated string".into())),
+                        }
+                    }
+                    tokens.push((Token::Str(s), line));
+                }
+                c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                    let mut s = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_ascii_digit()
+                            || c == '.'
+                            || c == 'e'
+                            || (c == '-' && (s.is_empty() || s.ends_with('e')))
+                        {
+                            s.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    let n = s
+                        .parse::<f64>()
+                        .map_err(|_| graph_error(line, format!("bad number `{}`", s)))?;
+                    tokens.push((Token::Number(n), line));
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut s = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c.is_alphanumeric() || c == '_' {
+                            s.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    tokens.push((Token::Ident(s), line));
+                }
+                c => return Err(graph_error(line, format!("unexpected character `{}`", c))),
+            }
+        }
+        if depth == 0 {
+            tokens.push((Token::Newline, line));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone)]
+enum Value {
+    Number(f64),
+    Color(Vec3),
+    Str(String),
+    Texture(Arc<dyn Texture>),
+}
+
+struct Parser<'a> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    bindings: HashMap<String, Value>,
+    cache: &'a TextureCache,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.pos.min(self.tokens.len().saturating_sub(1)))
+            .map_or(0, |(_, l)| *l)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        t
+    }
+
+    fn expect(&mut self, want: Token) -> Result<()> {
+        let line = self.line();
+        match self.next() {
+            Some(t) if t == want => Ok(()),
+            Some(t) => Err(graph_error(
+                line,
+                format!("expected {:?}, found {:?}", want, t),
+            )),
+            None => Err(graph_error(
+                line,
+                format!("expected {:?} at end of input", want),
+            )),
+        }
+    }
+
+    fn program(&mut self) -> Result<Option<Arc<dyn Texture>>> {
+        let mut last = None;
+        while self.pos < self.tokens.len() {
+            if self.peek() == Some(&Token::Newline) {
+                self.pos += 1;
+                continue;
+            }
+            let line = self.line();
+            let value = match (self.peek().cloned(), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Ident(name)), Some((Token::Equals, _))) => {
+                    self.pos += 2;
+                    let value = self.expr()?;
+                    self.bindings.insert(name, value.clone());
+                    value
+                }
+                _ => self.expr()?,
+            };
+            match self.next() {
+                None | Some(Token::Newline) => {}
+                Some(t) => {
+                    return Err(graph_error(
+                        line,
+                        format!("unexpected {:?} after statement", t),
+                    ))
+                }
+            }
+            last = Some((value, line));
+        }
+        match last {
+            Some((value, line)) => Ok(Some(as_texture(value, line, "result", 0)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Value> {
+        let line = self.line();
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Value::Number(n)),
+            Some(Token::Str(s)) => Ok(Value::Str(s)),
+            Some(Token::LBracket) => {
+                let mut c = [0.0; 3];
+                for (i, x) in c.iter_mut().enumerate() {
+                    if i > 0 {
+                        self.expect(Token::Comma)?;
+                    }
+                    *x = match self.next() {
+                        Some(Token::Number(n)) => n,
+                        _ => return Err(graph_error(line, "colours are `[r, g, b]`".into())),
+                    };
+                }
+                self.expect(Token::RBracket)?;
+                Ok(Value::Color(Vec3::new(c[0], c[1], c[2])))
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    let mut args = Vec::new();
+                    if self.peek() != Some(&Token::RParen) {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.peek() == Some(&Token::Comma) {
+                                self.pos += 1;
+                            } else {
+                                break;
+                            }
+                        }
+                    }
+                    self.expect(Token::RParen)?;
+                    self.call(&name, &args, line)
+                } else {
+                    self.bindings
+                        .get(&name)
+                        .cloned()
+                        .ok_or_else(|| graph_error(line, format!("unknown name `{}`", name)))
+                }
+            }
+            Some(t) => Err(graph_error(line, format!("unexpected {:?}", t))),
+            None => Err(graph_error(line, "unexpected end of input".into())),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Value], line: usize) -> Result<Value> {
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(graph_error(
+                    line,
+                    format!("`{}` takes {} arguments, got {}", name, n, args.len()),
+                ))
+            }
+        };
+        let tex = |i: usize| as_texture(args[i].clone(), line, name, i).map(Some);
+        let num = |i: usize| match &args[i] {
+            Value::Number(n) => Ok(*n),
+            _ => Err(graph_error(
+                line,
+                format!("argument {} of `{}` must be a number", i + 1, name),
+            )),
+        };
+        let string = |i: usize| match &args[i] {
+            Value::Str(s) => Ok(s.clone()),
+            _ => Err(graph_error(
+                line,
+                format!("argument {} of `{}` must be a string", i + 1, name),
+            )),
+        };
+
+        let texture: Arc<dyn Texture> = match name {
+            "color" => {
+                arity(3)?;
+                return Ok(Value::Color(Vec3::new(num(0)?, num(1)?, num(2)?)));
+            }
+            "image" | "image_linear" => {
+                if args.is_empty() || args.len() > 2 {
+                    return Err(graph_error(
+                        line,
+                        format!("`{}` takes a path and an optional wrap mode", name),
+                    ));
+                }
+                let mut image = if name == "image" {
+                    self.cache.load(&string(0)?)?
+                } else {
+                    self.cache.load_linear(&string(0)?)?
+                };
+                if args.len() == 2 {
+                    image.set_wrap(match string(1)?.as_str() {
+                        "repeat" => WrapMode::Repeat,
+                        "clamp" => WrapMode::Clamp,
+                        "mirror" => WrapMode::Mirror,
+                        other => {
+                            return Err(graph_error(line, format!("unknown wrap mode `{}`", other)))
+                        }
+                    });
+                }
+                Arc::new(image)
+            }
+            "checker" => {
+                arity(2)?;
+                Arc::new(CheckerTexture::new_1(tex(0)?, tex(1)?))
+            }
+            "uv_checker" => {
+                arity(4)?;
+                Arc::new(UvCheckerTexture::new_1(tex(0)?, tex(1)?, num(2)?, num(3)?))
+            }
+            "noise" => {
+                arity(1)?;
+                Arc::new(NoiseTexture::new_0(num(0)?))
+            }
+            "fbm" | "turbulence" => {
+                arity(4)?;
+                let (scale, octaves, lacunarity, gain) =
+                    (num(0)?, num(1)? as i32, num(2)?, num(3)?);
+                Arc::new(if name == "fbm" {
+                    FbmTexture::new(scale, octaves, lacunarity, gain)
+                } else {
+                    FbmTexture::new_turbulence(scale, octaves, lacunarity, gain)
+                })
+            }
+            "simplex" => {
+                arity(5)?;
+                Arc::new(SimplexTexture::new(
+                    num(0)?,
+                    num(1)? as i32,
+                    num(2)?,
+                    num(3)?,
+                    num(4)?,
+                ))
+            }
+            "marble" => {
+                arity(2)?;
+                Arc::new(MarbleTexture::new(num(0)?, num(1)?))
+            }
+            "wood" => {
+                arity(3)?;
+                Arc::new(WoodTexture::new(num(0)?, num(1)?, num(2)?))
+            }
+            "worley" | "worley_f2" | "worley_border" => {
+                arity(2)?;
+                let mode = match name {
+                    "worley" => WorleyMode::F1,
+                    "worley_f2" => WorleyMode::F2,
+                    _ => WorleyMode::F2MinusF1,
+                };
+                Arc::new(WorleyTexture::new(num(0)?, num(1)? as u32, mode))
+            }
+            "gradient_u" | "gradient_v" => {
+                arity(2)?;
+                let kind = if name == "gradient_u" {
+                    GradientKind::U
+                } else {
+                    GradientKind::V
+                };
+                Arc::new(GradientTexture::new(tex(0)?, tex(1)?, kind))
+            }
+            "add" => {
+                arity(2)?;
+                Arc::new(AddTexture::new(tex(0)?, tex(1)?))
+            }
+            "mul" => {
+                arity(2)?;
+                Arc::new(MultiplyTexture::new(tex(0)?, tex(1)?))
+            }
+            "mix" => {
+                arity(3)?;
+                Arc::new(LerpTexture::new(tex(0)?, tex(1)?, tex(2)?))
+            }
+            "invert" => {
+                arity(1)?;
+                Arc::new(InvertTexture::new(tex(0)?))
+            }
+            "clamp" => {
+                arity(3)?;
+                Arc::new(ClampTexture::new(tex(0)?, num(1)?, num(2)?))
+            }
+            "remap" => {
+                arity(5)?;
+                Arc::new(RemapTexture::new(
+                    tex(0)?,
+                    (num(1)?, num(2)?),
+                    (num(3)?, num(4)?),
+                ))
+            }
+            "ramp" => {
+                if args.len() < 3 || args.len().is_multiple_of(2) {
+                    return Err(graph_error(
+                        line,
+                        "`ramp` takes an input then position, colour pairs".into(),
+                    ));
+                }
+                let mut stops = Vec::new();
+                for i in (1..args.len()).step_by(2) {
+                    let color = match &args[i + 1] {
+                        Value::Color(c) => *c,
+                        Value::Number(n) => Vec3::ones() * *n,
+                        _ => {
+                            return Err(graph_error(
+                                line,
+                                format!("argument {} of `ramp` must be a colour", i + 2),
+                            ))
+                        }
+                    };
+                    stops.push((num(i)?, color));
+                }
+                Arc::new(ColorRamp::new(tex(0)?, stops))
+            }
+            "hsv" => {
+                arity(4)?;
+                Arc::new(HueSaturationTexture::new(
+                    tex(0)?,
+                    num(1)?,
+                    num(2)?,
+                    num(3)?,
+                ))
+            }
+            "uv" => {
+                arity(6)?;
+                Arc::new(UvTransformTexture::new(
+                    tex(0)?,
+                    (num(1)?, num(2)?),
+                    (num(3)?, num(4)?),
+                    num(5)?,
+                ))
+            }
+            "triplanar" => {
+                arity(3)?;
+                Arc::new(TriplanarTexture::new1(tex(0)?, num(1)?, num(2)?))
+            }
+            "channel" => {
+                arity(2)?;
+                let channel = match string(1)?.as_str() {
+                    "r" => Channel::R,
+                    "g" => Channel::G,
+                    "b" => Channel::B,
+                    "luminance" => Channel::Luminance,
+                    other => return Err(graph_error(line, format!("unknown channel `{}`", other))),
+                };
+                Arc::new(ChannelTexture::new(tex(0)?, channel))
+            }
+            _ => return Err(graph_error(line, format!("unknown function `{}`", name))),
+        };
+        Ok(Value::Texture(texture))
+    }
+}
+
+fn as_texture(value: Value, line: usize, name: &str, i: usize) -> Result<Arc<dyn Texture>> {
+    match value {
+        Value::Texture(t) => Ok(t),
+        Value::Color(c) => Ok(Arc::new(SolidColor::new(c))),
+        Value::Number(n) => Ok(Arc::new(SolidColor::new(Vec3::ones() * n))),
+        Value::Str(_) => Err(graph_error(
+            line,
+            format!("argument {} of `{}` must be a texture", i + 1, name),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_graph() {
+        let src = "
+            # a contrast-curved blend of two colours
+            t = remap(0.6, 0.5, 0.7, 0, 1)
+            mix([1, 0, 0],
+                [0, 0, 1], t)
+        ";
+        let tex = parse_texture_graph(src, &TextureCache::new())
+            .unwrap()
+            .unwrap();
+        let c = tex.value(0.0, 0.0, &Vec3::zero());
+        assert!((c.x() - 0.5).abs() < 1e-12 && (c.z() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_graph_errors_name_the_line() {
+        let err = parse_texture_graph("a = 1\nfrobnicate(a)", &TextureCache::new());
+        assert!(matches!(err, Err(Error::Graph { line: 2, .. })));
+    }
+
+    #[test]
+    fn test_unmatched_paren_is_reported_where_it_is() {
+        let err = parse_texture_graph(
+            "a = 1)
+b = 2
+mix(a, b, 0.5)",
+            &TextureCache::new(),
+        );
+        match err {
+            Err(Error::Graph { line, message }) => {
+                assert_eq!(line, 1);
+                assert!(message.contains("unmatched"), "{}", message);
+            }
+            _ => panic!("an unmatched `)` must not parse"),
+        }
+    }
+}
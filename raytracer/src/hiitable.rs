@@ -1,6 +1,7 @@
 pub use crate::aabb::AAbb;
 pub use crate::material::Material;
 pub use crate::ray::Ray;
+pub use crate::texture::TexCoord;
 pub use crate::vec3::Vec3;
 use std::sync::Arc;
 
@@ -8,10 +9,20 @@ use std::sync::Arc;
 pub struct HitRecord {
     pub point3: Vec3,
     pub normal: Vec3,
+    /// Surface tangents, the partial derivatives of the hit point with respect to u and v.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub t: f64,
     pub u: f64,
     pub v: f64,
     pub front_size: bool,
+    /// World-space width of the ray cone at the hit point.
+    pub footprint: f64,
+    /// Time of the ray that made the hit, for animated textures.
+    pub tm: f64,
+    /// Left by the last `scatter` of a material that picks between parts, e.g. `MixMaterial`:
+    /// whether the part it used scattered into a delta lobe.
+    pub scattered_delta: bool,
     pub mat: Option<Arc<dyn Material>>,
 }
 
@@ -26,13 +37,39 @@ impl HitRecord {
         Self {
             point3: Vec3::new(0.0, 0.0, 0.0),
             normal: Vec3::new(0.0, 0.0, 0.0),
+            dpdu: Vec3::new(0.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 0.0),
             t: (0.0),
             u: (0.0),
             v: (0.0),
             front_size: (false),
+            footprint: (0.0),
+            tm: (0.0),
+            scattered_delta: (false),
             mat: (None),
         }
     }
+    /// The ray footprint measured in uv units, for `Texture::value_filtered`.
+    pub fn uv_footprint(&self) -> f64 {
+        let du = self.dpdu.length();
+        let dv = self.dpdv.length();
+        if self.footprint <= 0.0 || du == 0.0 || dv == 0.0 {
+            return 0.0;
+        }
+        (self.footprint / du).max(self.footprint / dv)
+    }
+
+    pub fn tex_coord(&self) -> TexCoord {
+        TexCoord {
+            u: self.u,
+            v: self.v,
+            p: self.point3,
+            normal: self.normal,
+            width: self.uv_footprint(),
+            tm: self.tm,
+        }
+    }
+
     pub fn set_front_size(&mut self, r: &Ray, outward_normal: &Vec3) {
         self.front_size = r.direc() * *outward_normal < 0.0;
         if self.front_size {
@@ -46,4 +83,37 @@ pub trait Hiitable: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AAbb) -> bool;
+
+    /// Solid-angle density with which `random` picks direction `v` from `o`. Shapes that
+    /// cannot be sampled return zero and are only found by chance as area lights.
+    fn pdf_value(&self, _o: &Vec3, _v: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Direction from `o` towards a random point on the shape.
+    fn random(&self, _o: &Vec3) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+
+    /// A point spread uniformly over the surface and the outward normal there, for emitting
+    /// light from area lights. Shapes that cannot be sampled return false.
+    fn sample_surface(&self, _point: &mut Vec3, _normal: &mut Vec3) -> bool {
+        false
+    }
+
+    /// Surface area, the inverse of the density of `sample_surface`.
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    /// Fraction of light that gets through along `r` between `t_min` and `t_max`, for shadow
+    /// rays. Surfaces block everything; media return a transmittance estimate instead.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut rec = HitRecord::new();
+        if self.hit(r, t_min, t_max, &mut rec) {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
@@ -1,6 +1,8 @@
 pub use crate::aabb::AAbb;
 pub use crate::hiitable::{Hiitable, HitRecord};
 pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::vec3::Vec3;
 
 use std::sync::Arc;
 
@@ -27,12 +29,8 @@ impl HittableList {
         let mut hit_anything: bool = false;
         let mut closest_so_far = t_max;
 
-        for object in (*self).clone().objects {
-            if object
-                .clone()
-                .unwrap()
-                .hit(r, t_min, closest_so_far, &mut temp_rec)
-            {
+        for object in self.objects.iter().flatten() {
+            if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.clone().t;
                 *rec = temp_rec.clone();
@@ -41,6 +39,64 @@ impl HittableList {
         hit_anything
     }
 
+    /// Product of the transmittance of every object, for shadow rays through media.
+    pub fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.0;
+        for object in self.objects.iter().flatten() {
+            transmittance *= object.transmittance(r, t_min, t_max);
+            if transmittance <= 0.0 {
+                return 0.0;
+            }
+        }
+        transmittance
+    }
+
+    /// Average of the objects' `Hiitable::pdf_value`, matching `random`.
+    pub fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .flatten()
+            .map(|object| object.pdf_value(o, v))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    /// Direction from `o` towards a random point on an object picked uniformly.
+    pub fn random(&self, o: &Vec3) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let i = ((random_f64() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+        self.objects[i]
+            .as_ref()
+            .map_or(Vec3::new(1.0, 0.0, 0.0), |object| object.random(o))
+    }
+
+    /// A point spread uniformly over all the objects, picking each in proportion to its area.
+    pub fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        let mut xi = random_f64() * self.area();
+        for object in self.objects.iter().flatten() {
+            let area = object.area();
+            if area > 0.0 && xi < area {
+                return object.sample_surface(point, normal);
+            }
+            xi -= area;
+        }
+        false
+    }
+
+    pub fn area(&self) -> f64 {
+        self.objects
+            .iter()
+            .flatten()
+            .map(|object| object.area())
+            .sum()
+    }
+
     pub fn add(&mut self, object: Option<Arc<dyn Hiitable>>) {
         self.objects.push(object);
     }
@@ -51,12 +107,8 @@ impl HittableList {
         }
         let mut temp_box: AAbb = AAbb::new_0();
         let mut first_box = true;
-        for object in (*self).clone().objects {
-            if object
-                .clone()
-                .unwrap()
-                .bounding_box(time0, time1, &mut temp_box)
-            {
+        for object in self.objects.iter().flatten() {
+            if object.bounding_box(time0, time1, &mut temp_box) {
                 return false;
             }
             *output_box = if first_box {
@@ -0,0 +1,367 @@
+pub use crate::bvh::take_node_visits;
+pub use crate::environment::Environment;
+pub use crate::hiitable::HitRecord;
+pub use crate::hittable_list::HittableList;
+pub use crate::light::LightList;
+pub use crate::path_tracer::estimate_direct;
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64_1;
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+const INFINITY: f64 = f64::INFINITY;
+
+/// A rendering algorithm: turns a camera ray into the radiance, or debug colour, it sees.
+pub trait Integrator: Send + Sync {
+    fn radiance(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3;
+
+    /// Like `radiance`, but may also send light to other parts of the film, pushed onto
+    /// `splats` as film coordinates and radiance. Splats are summed per pixel and divided by
+    /// the sample count like any other sample.
+    fn radiance_with_splats(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+        _splats: &mut Vec<(f64, f64, Vec3)>,
+    ) -> Vec3 {
+        self.radiance(r, background, lights, world)
+    }
+}
+
+/// Emission plus one bounce of light: direct lighting only, with no interreflection. Mirrors
+/// and glass are followed up to `max_depth` so lights seen in them still show.
+pub struct DirectLighting {
+    max_depth: u32,
+}
+
+impl DirectLighting {
+    pub fn new(max_depth: u32) -> Self {
+        Self { max_depth }
+    }
+}
+
+impl Integrator for DirectLighting {
+    fn radiance(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        let mut radiance = Vec3::zero();
+        let mut throughput = Vec3::ones();
+        let mut ray = r.clone();
+        for _ in 0..self.max_depth {
+            let mut rec = HitRecord::new();
+            if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+                return radiance + Vec3::elemul(&throughput, &background.radiance(&ray.direc()));
+            }
+            let mat = match rec.mat.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            rec.footprint = ray.footprint(rec.t);
+            rec.tm = ray.tm();
+            let emitted = mat.emitted(&ray, &rec, rec.u, rec.v, &rec.point3);
+            radiance += Vec3::elemul(&throughput, &emitted);
+
+            let specular = mat.is_specular(&rec);
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), random_f64_1(0.0, 1.0));
+            if !specular {
+                let direct =
+                    estimate_direct(&ray, &mut rec, mat.as_ref(), background, lights, world);
+                return radiance + Vec3::elemul(&throughput, &direct);
+            }
+            if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+            throughput = Vec3::elemul(&throughput, &attenuation);
+            ray = scattered;
+        }
+        radiance
+    }
+}
+
+/// White where the hemisphere above the first hit is open within `distance`, darker where
+/// nearby geometry occludes it. One cosine-weighted ray per sample.
+pub struct AmbientOcclusion {
+    distance: f64,
+}
+
+impl AmbientOcclusion {
+    pub fn new(distance: f64) -> Self {
+        Self { distance }
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        r: &Ray,
+        _background: &dyn Environment,
+        _lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        let mut rec = HitRecord::new();
+        if !world.hit(r, 0.001, INFINITY, &mut rec) {
+            return Vec3::ones();
+        }
+        let mut direction = rec.normal + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = rec.normal;
+        }
+        let probe = Ray::new(rec.point3, direction.unit(), r.tm());
+        let mut blocker = HitRecord::new();
+        if world.hit(&probe, 0.001, self.distance, &mut blocker) {
+            Vec3::zero()
+        } else {
+            Vec3::ones()
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugView {
+    /// Outward geometric normal mapped from [-1, 1] to [0, 1].
+    Normals,
+    /// `u` in red, `v` in green.
+    Uvs,
+    /// Distance to the first hit, white near and black at `max_distance`.
+    Depth { max_distance: f64 },
+    /// A colour per material instance.
+    MaterialId,
+    /// A colour per entry of the top-level object list.
+    ObjectId,
+    /// BVH nodes visited by the camera ray, blue for none through red for `max_nodes`.
+    TraversalCost { max_nodes: u64 },
+    /// Green where the ray hits the front of a surface, red where it hits the back.
+    FrontBack,
+}
+
+/// False-colour views of the first hit, for diagnosing geometry, UVs and materials.
+pub struct DebugIntegrator {
+    view: DebugView,
+}
+
+impl DebugIntegrator {
+    pub fn new(view: DebugView) -> Self {
+        Self { view }
+    }
+}
+
+impl Integrator for DebugIntegrator {
+    fn radiance(
+        &self,
+        r: &Ray,
+        _background: &dyn Environment,
+        _lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        let mut rec = HitRecord::new();
+        let mut object = None;
+        let mut closest = INFINITY;
+        take_node_visits();
+        for (i, o) in world.objects.iter().enumerate() {
+            if let Some(o) = o {
+                if o.hit(r, 0.001, closest, &mut rec) {
+                    closest = rec.t;
+                    object = Some(i);
+                }
+            }
+        }
+        let visits = take_node_visits();
+        if let DebugView::TraversalCost { max_nodes } = self.view {
+            return heat(visits as f64 / max_nodes.max(1) as f64);
+        }
+        let object = match object {
+            Some(object) => object,
+            None => return Vec3::zero(),
+        };
+        match self.view {
+            DebugView::Normals => {
+                let outward = if rec.front_size {
+                    rec.normal
+                } else {
+                    -rec.normal
+                };
+                (outward.unit() + Vec3::ones()) * 0.5
+            }
+            DebugView::Uvs => Vec3::new(rec.u.fract(), rec.v.fract(), 0.0),
+            DebugView::Depth { max_distance } => {
+                Vec3::ones() * (1.0 - rec.t * r.direc().length() / max_distance).clamp(0.0, 1.0)
+            }
+            DebugView::MaterialId => rec.mat.as_ref().map_or(Vec3::zero(), |m| {
+                id_color(Arc::as_ptr(m) as *const () as usize as u64)
+            }),
+            DebugView::ObjectId => id_color(object as u64 + 1),
+            DebugView::FrontBack => {
+                if rec.front_size {
+                    Vec3::new(0.1, 0.8, 0.1)
+                } else {
+                    Vec3::new(0.8, 0.1, 0.1)
+                }
+            }
+            DebugView::TraversalCost { .. } => unreachable!(),
+        }
+    }
+}
+
+/// A stable, well-spread colour for an identifier.
+fn id_color(id: u64) -> Vec3 {
+    let h = id.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    let channel = |shift: u32| 0.2 + 0.8 * ((h >> shift) & 0xff) as f64 / 255.0;
+    Vec3::new(channel(40), channel(48), channel(56))
+}
+
+/// Blue through green to red as `t` goes from 0 to 1.
+fn heat(t: f64) -> Vec3 {
+    let t = t.clamp(0.0, 1.0);
+    if t < 0.5 {
+        Vec3::new(0.0, 2.0 * t, 1.0 - 2.0 * t)
+    } else {
+        Vec3::new(2.0 * t - 1.0, 2.0 - 2.0 * t, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xzrect;
+    use crate::bvh::BvhNode;
+    use crate::environment::ConstantEnvironment;
+    use crate::hiitable::Hiitable;
+    use crate::material::{DiffLight, FlipFace, Lambertian};
+    use crate::object::Sphere;
+
+    use std::f64::consts::PI;
+
+    fn plane(y: f64, size: f64) -> Option<Arc<dyn Hiitable>> {
+        Some(Arc::new(Xzrect::new(
+            -size,
+            size,
+            -size,
+            size,
+            y,
+            Some(Arc::new(Lambertian::new1(&(Vec3::ones() * 0.5)))),
+        )))
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        let env = ConstantEnvironment::new(Vec3::zero());
+        let lights = LightList::new();
+        let ao = AmbientOcclusion::new(1e4);
+        let r = Ray::new(Vec3::new(0.0, 0.25, 0.0), Vec3::new(0.3, -1.0, 0.2), 0.0);
+
+        let mut world = HittableList::new();
+        world.add(plane(0.0, 1000.0));
+        for _ in 0..1000 {
+            assert_eq!(ao.radiance(&r, &env, &lights, &world), Vec3::ones());
+        }
+
+        world.add(plane(0.5, 1000.0));
+        for _ in 0..1000 {
+            assert_eq!(ao.radiance(&r, &env, &lights, &world), Vec3::zero());
+        }
+    }
+
+    #[test]
+    fn test_direct_lighting_matches_estimate_direct() {
+        let mut world = HittableList::new();
+        world.add(plane(0.0, 2.0));
+        let lamp: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(FlipFace::new(Some(Arc::new(Xzrect::new(
+                -0.3,
+                0.3,
+                -0.3,
+                0.3,
+                1.5,
+                Some(Arc::new(DiffLight::new2(Vec3::ones() * 8.0))),
+            ))))));
+        world.add(lamp.clone());
+        let mut lights = LightList::new();
+        lights.add_area(lamp);
+        let env = ConstantEnvironment::new(Vec3::zero());
+        let r = Ray::new(Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, 1.0), 0.0);
+
+        let n = 20_000;
+        let integrator = DirectLighting::new(4);
+        let direct = (0..n)
+            .map(|_| integrator.radiance(&r, &env, &lights, &world).x())
+            .sum::<f64>()
+            / n as f64;
+        let estimated = (0..n)
+            .map(|_| {
+                let mut rec = HitRecord::new();
+                assert!(world.hit(&r, 0.001, INFINITY, &mut rec));
+                rec.footprint = r.footprint(rec.t);
+                let mat = rec.mat.clone().unwrap();
+                estimate_direct(&r, &mut rec, mat.as_ref(), &env, &lights, &world).x()
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!(
+            (direct - estimated).abs() < 0.03 * estimated,
+            "{} {}",
+            direct,
+            estimated
+        );
+
+        // Exact: the light's form factor from the point straight below its centre, four
+        // corner rectangles of half-width 0.3 at height 1.5.
+        let x: f64 = 0.3 / 1.5;
+        let s = x / (1.0 + x * x).sqrt();
+        let form_factor = 4.0 * s * s.atan() / PI;
+        let expected = 0.5 * 8.0 * form_factor;
+        assert!(
+            (direct - expected).abs() < 0.03 * expected,
+            "{} {}",
+            direct,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let mut spheres = HittableList::new();
+        for x in [-2.0, 0.0, 2.0] {
+            spheres.add(Some(Arc::new(Sphere::new(
+                &Vec3::new(x, 0.0, 0.0),
+                0.5,
+                Some(Arc::new(Lambertian::new1(&Vec3::ones()))),
+            ))));
+        }
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(BvhNode::new2(&mut spheres, 0.0, 1.0))));
+        let env = ConstantEnvironment::new(Vec3::zero());
+        let lights = LightList::new();
+        let r = Ray::new(Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+
+        let normal = DebugIntegrator::new(DebugView::Normals).radiance(&r, &env, &lights, &world);
+        assert!((normal - Vec3::new(0.5, 0.5, 1.0)).length() < 1e-9);
+        let face = DebugIntegrator::new(DebugView::FrontBack).radiance(&r, &env, &lights, &world);
+        assert!(face.y() > face.x());
+        let cost = DebugIntegrator::new(DebugView::TraversalCost { max_nodes: 100 })
+            .radiance(&r, &env, &lights, &world);
+        assert!(cost.z() > 0.0 && cost.z() < 1.0);
+
+        // From inside a sphere the back face is hit and the outward normal points away.
+        let inside = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let face =
+            DebugIntegrator::new(DebugView::FrontBack).radiance(&inside, &env, &lights, &world);
+        assert!(face.x() > face.y());
+        let normal =
+            DebugIntegrator::new(DebugView::Normals).radiance(&inside, &env, &lights, &world);
+        assert!((normal - Vec3::new(0.5, 0.5, 1.0)).length() < 1e-9);
+    }
+}
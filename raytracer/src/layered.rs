@@ -0,0 +1,499 @@
+pub use crate::hiitable::HitRecord;
+pub use crate::material::Material;
+use crate::microfacet::fresnel_dielectric;
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::texture::{SolidColor, Texture};
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+/// Stochastically picks `second` with probability `amount` (its `x` channel), else `first`.
+pub struct MixMaterial {
+    first: Option<Arc<dyn Material>>,
+    second: Option<Arc<dyn Material>>,
+    amount: Option<Arc<dyn Texture>>,
+}
+
+impl MixMaterial {
+    pub fn new1(
+        first: Option<Arc<dyn Material>>,
+        second: Option<Arc<dyn Material>>,
+        amount: f64,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            amount: Some(Arc::new(SolidColor::new(Vec3::ones() * amount))),
+        }
+    }
+
+    pub fn new2(
+        first: Option<Arc<dyn Material>>,
+        second: Option<Arc<dyn Material>>,
+        amount: Option<Arc<dyn Texture>>,
+    ) -> Self {
+        Self {
+            first,
+            second,
+            amount,
+        }
+    }
+
+    fn weight(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        self.amount
+            .as_ref()
+            .map_or(0.0, |a| a.value(u, v, p).x())
+            .clamp(0.0, 1.0)
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let chosen = if random_f64() < self.weight(rec.u, rec.v, &rec.point3) {
+            &self.second
+        } else {
+            &self.first
+        };
+        let chosen = match chosen {
+            Some(m) => m,
+            None => return false,
+        };
+        if !chosen.scatter(r_in, rec, attenuation, scattered) {
+            return false;
+        }
+        rec.scattered_delta = chosen.is_delta(r_in, rec, &scattered.direc());
+        if !rec.scattered_delta {
+            // Weigh a smooth sample against both parts, as `scattering_pdf` does.
+            let pdf = self.scattering_pdf(r_in, rec, &scattered.direc());
+            if pdf > 0.0 {
+                *attenuation = self.eval(r_in, rec, &scattered.direc()) / pdf;
+            }
+        }
+        true
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let w = self.weight(u, v, p);
+        let emitted = |m: &Option<Arc<dyn Material>>| {
+            m.as_ref()
+                .map_or(Vec3::zero(), |m| m.emitted(r_in, rec, u, v, p))
+        };
+        emitted(&self.first) * (1.0 - w) + emitted(&self.second) * w
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let w = self.weight(rec.u, rec.v, &rec.point3);
+        let eval = |m: &Option<Arc<dyn Material>>| {
+            m.as_ref()
+                .map_or(Vec3::zero(), |m| m.eval(r_in, rec, direction))
+        };
+        eval(&self.first) * (1.0 - w) + eval(&self.second) * w
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let w = self.weight(rec.u, rec.v, &rec.point3);
+        let pdf = |m: &Option<Arc<dyn Material>>| {
+            m.as_ref()
+                .map_or(0.0, |m| m.scattering_pdf(r_in, rec, direction))
+        };
+        pdf(&self.first) * (1.0 - w) + pdf(&self.second) * w
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        let specular =
+            |m: &Option<Arc<dyn Material>>| m.as_ref().is_none_or(|m| m.is_specular(rec));
+        specular(&self.first) && specular(&self.second)
+    }
+
+    /// Answers for the part the last `scatter` on `rec` picked.
+    fn is_delta(&self, _r_in: &Ray, rec: &HitRecord, _direction: &Vec3) -> bool {
+        rec.scattered_delta
+    }
+
+    /// At most one part is expected to refract towards `direction`; its scale is the mix's.
+    fn radiance_scale(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        [&self.first, &self.second]
+            .into_iter()
+            .flatten()
+            .map(|m| m.radiance_scale(r_in, rec, direction))
+            .find(|&scale| scale != 1.0)
+            .unwrap_or(1.0)
+    }
+}
+
+/// A smooth dielectric coat of the given thickness and absorption over any base material.
+/// The coat mirrors light with its Fresnel reflectance; the rest refracts in, scatters once
+/// off the base and refracts out. Light reflected back down inside the coat is dropped.
+/// Hits on the back face see only the base.
+pub struct Coated {
+    base: Option<Arc<dyn Material>>,
+    ir: f64,
+    absorption: Vec3,
+    thickness: f64,
+}
+
+impl Coated {
+    pub fn new(base: Option<Arc<dyn Material>>, index_of_refraction: f64) -> Self {
+        Self {
+            base,
+            ir: index_of_refraction,
+            absorption: Vec3::zero(),
+            thickness: 0.0,
+        }
+    }
+
+    /// Tints the coat with Beer-Lambert absorption; `absorption` is per unit length.
+    pub fn new_tinted(
+        base: Option<Arc<dyn Material>>,
+        index_of_refraction: f64,
+        absorption: Vec3,
+        thickness: f64,
+    ) -> Self {
+        Self {
+            base,
+            ir: index_of_refraction,
+            absorption,
+            thickness,
+        }
+    }
+
+    fn transmittance(&self, cos: f64) -> Vec3 {
+        let d = self.thickness / cos.abs().max(1e-4);
+        Vec3::new(
+            (-self.absorption.x() * d).exp(),
+            (-self.absorption.y() * d).exp(),
+            (-self.absorption.z() * d).exp(),
+        )
+    }
+
+    /// Follows light leaving along `direction` back through the coat. Returns the ray that
+    /// reaches the base from `r_in`, the direction inside the coat that refracts out along
+    /// `direction`, the fraction of light the coat lets through both ways, and the solid angle
+    /// change from refracting out, which the base's `eval` and `scattering_pdf` are scaled by.
+    fn through_coat(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: &Vec3,
+    ) -> Option<(Ray, Vec3, Vec3, f64)> {
+        let n = rec.normal;
+        let unit_direction = r_in.direc().unit();
+        let out = direction.unit();
+        let cos_i = out * n;
+        if cos_i <= 0.0 {
+            return None;
+        }
+        let down = Vec3::refract(&unit_direction, &n, 1.0 / self.ir).unit();
+        let inner = -Vec3::refract(&(-out), &n, 1.0 / self.ir).unit();
+        let cos_inner = inner * n;
+        let passed = (1.0 - fresnel_dielectric(-(unit_direction * n), self.ir))
+            * (1.0 - fresnel_dielectric(cos_inner, 1.0 / self.ir));
+        let coat = Vec3::elemul(
+            &self.transmittance(down * n),
+            &self.transmittance(cos_inner),
+        ) * passed;
+        let spread = cos_i / (self.ir * self.ir * cos_inner);
+        Some((Ray::new(rec.point3, down, r_in.tm()), inner, coat, spread))
+    }
+}
+
+impl Material for Coated {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let base = match &self.base {
+            Some(b) => b,
+            None => return false,
+        };
+        if !rec.front_size {
+            return base.scatter(r_in, rec, attenuation, scattered);
+        }
+        let n = rec.normal;
+        let unit_direction = r_in.direc().unit();
+        let cos_o = -(unit_direction * n);
+
+        if random_f64() < fresnel_dielectric(cos_o, self.ir) {
+            *attenuation = Vec3::ones();
+            *scattered = Ray::new(rec.point3, Vec3::reflect(&unit_direction, &n), r_in.tm());
+            rec.scattered_delta = true;
+            return true;
+        }
+
+        let down = Vec3::refract(&unit_direction, &n, 1.0 / self.ir).unit();
+        let inner = Ray::new(rec.point3, down, r_in.tm());
+        let mut base_attenuation = Vec3::zero();
+        let mut base_scattered = Ray::new(rec.point3, down, r_in.tm());
+        if !base.scatter(&inner, rec, &mut base_attenuation, &mut base_scattered) {
+            return false;
+        }
+        let up = base_scattered.direc().unit();
+        rec.scattered_delta = base.is_delta(&inner, rec, &up);
+        let cos_inner = up * n;
+        if cos_inner <= 0.0 {
+            // The base transmitted the light into the object; a coat over a surface has
+            // nowhere for it to go.
+            return false;
+        }
+        // Weighting by the coat's transmission rather than choosing at random keeps
+        // `attenuation` equal to `eval / scattering_pdf`.
+        let escape = 1.0 - fresnel_dielectric(cos_inner, 1.0 / self.ir);
+        if escape <= 0.0 {
+            return false;
+        }
+        let coat = Vec3::elemul(
+            &self.transmittance(down * n),
+            &self.transmittance(cos_inner),
+        );
+        *attenuation = Vec3::elemul(&coat, &base_attenuation) * escape;
+        *scattered = Ray::new(rec.point3, Vec3::refract(&up, &(-n), self.ir), r_in.tm());
+        true
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.base
+            .as_ref()
+            .map_or(Vec3::zero(), |b| b.emitted(r_in, rec, u, v, p))
+    }
+
+    /// The base seen through the coat; the coat's mirror reflection is a delta lobe.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let base = match &self.base {
+            Some(b) => b,
+            None => return Vec3::zero(),
+        };
+        if !rec.front_size {
+            return base.eval(r_in, rec, direction);
+        }
+        self.through_coat(r_in, rec, direction)
+            .map_or(Vec3::zero(), |(inner, up, coat, spread)| {
+                Vec3::elemul(&coat, &base.eval(&inner, rec, &up)) * spread
+            })
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let base = match &self.base {
+            Some(b) => b,
+            None => return 0.0,
+        };
+        if !rec.front_size {
+            return base.scattering_pdf(r_in, rec, direction);
+        }
+        let reflect = fresnel_dielectric(-(r_in.direc().unit() * rec.normal), self.ir);
+        self.through_coat(r_in, rec, direction)
+            .map_or(0.0, |(inner, up, _, spread)| {
+                (1.0 - reflect) * base.scattering_pdf(&inner, rec, &up) * spread
+            })
+    }
+
+    fn is_specular(&self, rec: &HitRecord) -> bool {
+        self.base.as_ref().is_none_or(|b| b.is_specular(rec))
+    }
+
+    /// On the front, answers for the lobe the last `scatter` on `rec` picked: the coat's
+    /// mirror or whatever the base did underneath it.
+    fn is_delta(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> bool {
+        match &self.base {
+            Some(base) if !rec.front_size => base.is_delta(r_in, rec, direction),
+            Some(_) => rec.scattered_delta,
+            None => true,
+        }
+    }
+
+    /// Light leaving through the coat crossed it both ways, so only the back face, which is
+    /// the base alone, can scale radiance.
+    fn radiance_scale(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        match &self.base {
+            Some(base) if !rec.front_size => base.radiance_scale(r_in, rec, direction),
+            _ => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xzrect;
+    use crate::environment::ConstantEnvironment;
+    use crate::hittable_list::HittableList;
+    use crate::integrator::Integrator;
+    use crate::light::{LightList, PointLight};
+    use crate::material::{Lambertian, Metal};
+    use crate::microfacet::Conductor;
+    use crate::path_tracer::{PathSettings, PathTracer};
+    use std::f64::consts::PI;
+
+    fn front_hit() -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.front_size = true;
+        rec
+    }
+
+    #[test]
+    fn test_coated_eval_matches_scatter() {
+        let coated = Coated::new_tinted(
+            Some(Arc::new(Lambertian::new1(&Vec3::new(0.8, 0.8, 0.8)))),
+            1.5,
+            Vec3::new(0.5, 0.5, 0.5),
+            0.2,
+        );
+        let r_in = Ray::new(Vec3::new(-0.6, 0.0, 0.8), Vec3::new(0.6, 0.0, -0.8), 0.0);
+        let mut rec = front_hit();
+        let n = 200_000;
+
+        // Mean weight of a scatter, with the mirror lobe split out.
+        let mut scattered_total = 0.0;
+        let mut mirror = 0.0;
+        for _ in 0..n {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            if !coated.scatter(&r_in, &mut rec, &mut attenuation, &mut scattered) {
+                continue;
+            }
+            if coated.is_delta(&r_in, &rec, &scattered.direc()) {
+                mirror += attenuation.x();
+                continue;
+            }
+            let expected = coated.eval(&r_in, &rec, &scattered.direc()).x()
+                / coated.scattering_pdf(&r_in, &rec, &scattered.direc());
+            assert!((attenuation.x() - expected).abs() < 1e-9 * expected.max(1.0));
+            scattered_total += attenuation.x();
+        }
+        let fresnel = fresnel_dielectric(0.8, 1.5);
+        assert!((mirror / n as f64 - fresnel).abs() < 0.01);
+
+        // The same by integrating eval over the hemisphere.
+        let mut integral = 0.0;
+        let mut pdf_integral = 0.0;
+        for _ in 0..n {
+            let direction = Vec3::random_in_hemisphere(&rec.normal);
+            integral += coated.eval(&r_in, &rec, &direction).x() * 2.0 * PI;
+            pdf_integral += coated.scattering_pdf(&r_in, &rec, &direction) * 2.0 * PI;
+        }
+        let integral = integral / n as f64;
+        assert!((scattered_total / n as f64 - integral).abs() < 0.01 * integral.max(0.1));
+        // Only base directions inside the critical cone get out, 1 / ir^2 of a cosine lobe.
+        let escaping = (1.0 - fresnel) / (1.5 * 1.5);
+        assert!((pdf_integral / n as f64 - escaping).abs() < 0.02);
+        assert!(!coated.is_specular(&rec));
+    }
+
+    #[test]
+    fn test_smooth_mix_weighs_both_parts() {
+        let mix = MixMaterial::new1(
+            Some(Arc::new(Lambertian::new1(&Vec3::new(0.8, 0.5, 0.2)))),
+            Some(Arc::new(Conductor::gold(0.4))),
+            0.3,
+        );
+        let r_in = Ray::new(Vec3::new(-0.6, 0.0, 0.8), Vec3::new(0.6, 0.0, -0.8), 0.0);
+        let mut rec = front_hit();
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        assert!(!mix.is_specular(&rec));
+        for _ in 0..1000 {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            if !mix.scatter(&r_in, &mut rec, &mut attenuation, &mut scattered) {
+                continue;
+            }
+            assert!(!mix.is_delta(&r_in, &rec, &scattered.direc()));
+            let expected = mix.eval(&r_in, &rec, &scattered.direc())
+                / mix.scattering_pdf(&r_in, &rec, &scattered.direc());
+            assert!((attenuation - expected).length() < 1e-9 * expected.length().max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_mix_with_mirror_still_samples_lights() {
+        let mix: Arc<dyn Material> = Arc::new(MixMaterial::new1(
+            Some(Arc::new(Lambertian::new1(&(Vec3::ones() * 0.5)))),
+            Some(Arc::new(Metal::new(&Vec3::ones(), 0.0))),
+            0.5,
+        ));
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Xzrect::new(
+            -100.0,
+            100.0,
+            -100.0,
+            100.0,
+            0.0,
+            Some(mix.clone()),
+        ))));
+        let mut lights = LightList::new();
+        lights.add(Some(Arc::new(PointLight::new(
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::ones(),
+        ))));
+        let background = ConstantEnvironment::new(Vec3::zero());
+        let r = Ray::new(Vec3::new(0.0, 1.0, -1.0), Vec3::new(0.0, -1.0, 1.0), 0.0);
+
+        let mut rec = HitRecord::new();
+        assert!(world.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        assert!(!mix.is_specular(&rec));
+        let (mut mirror, mut diffuse) = (0, 0);
+        for _ in 0..1000 {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            assert!(mix.scatter(&r, &mut rec, &mut attenuation, &mut scattered));
+            let along_mirror =
+                scattered.direc().unit() * Vec3::reflect(&r.direc().unit(), &rec.normal);
+            if mix.is_delta(&r, &rec, &scattered.direc()) {
+                assert!(along_mirror > 1.0 - 1e-9);
+                mirror += 1;
+            } else {
+                diffuse += 1;
+            }
+        }
+        assert!(mirror > 400 && diffuse > 400);
+
+        // Only a shadow ray can find a point light: half of a 0.5 albedo, one unit below it.
+        let tracer = PathTracer::new(PathSettings::default());
+        let n = 200;
+        let radiance = (0..n)
+            .map(|_| tracer.radiance(&r, &background, &lights, &world).x())
+            .sum::<f64>()
+            / n as f64;
+        let expected = 0.5 * 0.5 / PI;
+        assert!(
+            (radiance - expected).abs() < 1e-6,
+            "{} {}",
+            radiance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_coated_back_face_is_base() {
+        let base = Arc::new(Lambertian::new1(&Vec3::new(0.5, 0.5, 0.5)));
+        let coated = Coated::new(Some(base.clone()), 1.5);
+        let r_in = Ray::new(Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let mut rec = front_hit();
+        rec.front_size = false;
+        let direction = Vec3::new(0.0, 0.6, 0.8);
+        assert_eq!(
+            coated.eval(&r_in, &rec, &direction),
+            base.eval(&r_in, &rec, &direction)
+        );
+        assert_eq!(
+            coated.scattering_pdf(&r_in, &rec, &direction),
+            base.scattering_pdf(&r_in, &rec, &direction)
+        );
+        for _ in 0..100 {
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            assert!(coated.scatter(&r_in, &mut rec, &mut attenuation, &mut scattered));
+            assert_eq!(attenuation, Vec3::new(0.5, 0.5, 0.5));
+            assert!(!coated.is_delta(&r_in, &rec, &scattered.direc()));
+        }
+    }
+}
@@ -0,0 +1,743 @@
+use crate::error::{Error, Result};
+pub use crate::hiitable::{Hiitable, HitRecord};
+pub use crate::onb::Onb;
+pub use crate::ray::Ray;
+use crate::rtweekend::{degrees_to_radians, random_f64};
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Incident light at a shading point, as returned by `Light::sample`.
+#[derive(Clone, Copy)]
+pub struct LightSample {
+    /// Unit direction from the shading point towards the light.
+    pub direction: Vec3,
+    /// Distance to the light along `direction`; infinite for directional lights.
+    pub distance: f64,
+    pub radiance: Vec3,
+    /// Solid-angle density of the sample. Delta lights report the probability of having
+    /// been chosen instead.
+    pub pdf: f64,
+    pub delta: bool,
+}
+
+impl Default for LightSample {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LightSample {
+    pub fn new() -> Self {
+        Self {
+            direction: Vec3::zero(),
+            distance: 0.0,
+            radiance: Vec3::zero(),
+            pdf: 0.0,
+            delta: false,
+        }
+    }
+}
+
+/// Light leaving a light, as returned by `Light::sample_emission`.
+#[derive(Clone)]
+pub struct EmissionSample {
+    /// Starts on the light and heads along a unit direction.
+    pub ray: Ray,
+    /// Normal of the emitting surface; zero for point-like lights.
+    pub normal: Vec3,
+    /// Radiance along `ray`, or intensity for point-like lights.
+    pub radiance: Vec3,
+    /// Area density of the starting point; one for point-like lights.
+    pub pdf_pos: f64,
+    /// Solid-angle density of the direction.
+    pub pdf_dir: f64,
+    /// Whether the light sits at a single point and so can never be hit.
+    pub delta: bool,
+}
+
+impl Default for EmissionSample {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmissionSample {
+    pub fn new() -> Self {
+        Self {
+            ray: Ray::new(Vec3::zero(), Vec3::zero(), 0.0),
+            normal: Vec3::zero(),
+            radiance: Vec3::zero(),
+            pdf_pos: 0.0,
+            pdf_dir: 0.0,
+            delta: false,
+        }
+    }
+}
+
+/// Anything that can be sampled for direct lighting. Lights live in a `LightList`, apart
+/// from the geometry in the `HittableList`.
+pub trait Light: Send + Sync {
+    fn sample(&self, p: &Vec3, tm: f64, sample: &mut LightSample) -> bool;
+
+    /// Density with which `sample` picks `direction` from `p`; zero for delta lights, which
+    /// rays can never hit.
+    fn pdf(&self, _p: &Vec3, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Samples light leaving the light, for tracing paths from it. Lights that cannot start
+    /// paths, such as directional ones, return false.
+    fn sample_emission(&self, _tm: f64, _sample: &mut EmissionSample) -> bool {
+        false
+    }
+
+    /// Light leaving `point` with surface `normal` along `direction`, and the solid-angle
+    /// density with which `sample_emission` picks that direction.
+    fn emission(
+        &self,
+        _point: &Vec3,
+        _normal: &Vec3,
+        _direction: &Vec3,
+        _tm: f64,
+        _radiance: &mut Vec3,
+    ) -> f64 {
+        0.0
+    }
+
+    /// The `(pdf_pos, pdf_dir)` with which `sample_emission` produces the light that `r`
+    /// reaches travelling backwards, or zeros if `r` misses this light. `t_hit` is set to the
+    /// ray parameter of the point reached.
+    fn pdf_emission(&self, _r: &Ray, _t_hit: &mut f64) -> (f64, f64) {
+        (0.0, 0.0)
+    }
+}
+
+/// Light from a single point, falling off with the square of the distance.
+pub struct PointLight {
+    position: Vec3,
+    intensity: Vec3,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3, intensity: Vec3) -> Self {
+        Self {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: &Vec3, _tm: f64, sample: &mut LightSample) -> bool {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return false;
+        }
+        *sample = LightSample {
+            direction: to_light.unit(),
+            distance: distance_squared.sqrt(),
+            radiance: self.intensity / distance_squared,
+            pdf: 1.0,
+            delta: true,
+        };
+        true
+    }
+
+    fn sample_emission(&self, tm: f64, sample: &mut EmissionSample) -> bool {
+        *sample = EmissionSample {
+            ray: Ray::new(self.position, Vec3::random_unit_vector(), tm),
+            normal: Vec3::zero(),
+            radiance: self.intensity,
+            pdf_pos: 1.0,
+            pdf_dir: 1.0 / (4.0 * PI),
+            delta: true,
+        };
+        true
+    }
+
+    fn emission(
+        &self,
+        _point: &Vec3,
+        _normal: &Vec3,
+        _direction: &Vec3,
+        _tm: f64,
+        radiance: &mut Vec3,
+    ) -> f64 {
+        *radiance = self.intensity;
+        1.0 / (4.0 * PI)
+    }
+}
+
+/// A point light restricted to a cone, with a smooth falloff towards the edge and an
+/// optional measured intensity profile.
+pub struct SpotLight {
+    position: Vec3,
+    axis: Vec3,
+    intensity: Vec3,
+    cos_total: f64,
+    cos_falloff: f64,
+    profile: Vec<(f64, f64)>,
+}
+
+impl SpotLight {
+    /// `total_width` is the cone half-angle and `falloff_start` the half-angle at which the
+    /// intensity starts to drop, both in degrees.
+    pub fn new(
+        position: Vec3,
+        target: Vec3,
+        intensity: Vec3,
+        total_width: f64,
+        falloff_start: f64,
+    ) -> Self {
+        Self {
+            position,
+            axis: (target - position).unit(),
+            intensity,
+            cos_total: degrees_to_radians(total_width).cos(),
+            cos_falloff: degrees_to_radians(falloff_start.min(total_width)).cos(),
+            profile: Vec::new(),
+        }
+    }
+
+    /// Relative intensity against the angle from the axis in degrees, e.g. from
+    /// `load_ies_profile`. Values are normalised to a peak of one.
+    pub fn set_profile(&mut self, mut profile: Vec<(f64, f64)>) {
+        profile.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let peak = profile.iter().map(|s| s.1).fold(0.0, f64::max);
+        if peak > 0.0 {
+            for s in profile.iter_mut() {
+                s.1 /= peak;
+            }
+        }
+        self.profile = profile;
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta < self.cos_total {
+            return 0.0;
+        }
+        let edge = if cos_theta >= self.cos_falloff {
+            1.0
+        } else {
+            let delta = (cos_theta - self.cos_total) / (self.cos_falloff - self.cos_total);
+            delta * delta * (3.0 - 2.0 * delta)
+        };
+        edge * self.profile_at(cos_theta.clamp(-1.0, 1.0).acos().to_degrees())
+    }
+
+    fn cone_pdf(&self) -> f64 {
+        1.0 / (2.0 * PI * (1.0 - self.cos_total).max(1e-12))
+    }
+
+    fn profile_at(&self, angle: f64) -> f64 {
+        let i = self.profile.partition_point(|s| s.0 < angle);
+        match (i, self.profile.len()) {
+            (_, 0) => 1.0,
+            (0, _) => self.profile[0].1,
+            (i, n) if i == n => self.profile[n - 1].1,
+            (i, _) => {
+                let (a0, v0) = self.profile[i - 1];
+                let (a1, v1) = self.profile[i];
+                v0 + (v1 - v0) * (angle - a0) / (a1 - a0)
+            }
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: &Vec3, _tm: f64, sample: &mut LightSample) -> bool {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.squared_length();
+        if distance_squared == 0.0 {
+            return false;
+        }
+        let direction = to_light.unit();
+        let falloff = self.falloff(-direction * self.axis);
+        if falloff <= 0.0 {
+            return false;
+        }
+        *sample = LightSample {
+            direction,
+            distance: distance_squared.sqrt(),
+            radiance: self.intensity * (falloff / distance_squared),
+            pdf: 1.0,
+            delta: true,
+        };
+        true
+    }
+
+    /// Directions are spread uniformly over the cone.
+    fn sample_emission(&self, tm: f64, sample: &mut EmissionSample) -> bool {
+        let cos_theta = 1.0 - random_f64() * (1.0 - self.cos_total);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let direction = Onb::build_from_w(&self.axis).local(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        *sample = EmissionSample {
+            ray: Ray::new(self.position, direction, tm),
+            normal: Vec3::zero(),
+            radiance: self.intensity * self.falloff(cos_theta),
+            pdf_pos: 1.0,
+            pdf_dir: self.cone_pdf(),
+            delta: true,
+        };
+        true
+    }
+
+    fn emission(
+        &self,
+        _point: &Vec3,
+        _normal: &Vec3,
+        direction: &Vec3,
+        _tm: f64,
+        radiance: &mut Vec3,
+    ) -> f64 {
+        let cos_theta = direction.unit() * self.axis;
+        *radiance = self.intensity * self.falloff(cos_theta);
+        if cos_theta < self.cos_total {
+            return 0.0;
+        }
+        self.cone_pdf()
+    }
+}
+
+/// Parallel light from infinitely far away, e.g. the sun without a visible disk.
+pub struct DirectionalLight {
+    direction: Vec3,
+    radiance: Vec3,
+}
+
+impl DirectionalLight {
+    /// `direction` points from the scene towards the light.
+    pub fn new(direction: Vec3, radiance: Vec3) -> Self {
+        Self {
+            direction: direction.unit(),
+            radiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: &Vec3, _tm: f64, sample: &mut LightSample) -> bool {
+        *sample = LightSample {
+            direction: self.direction,
+            distance: f64::INFINITY,
+            radiance: self.radiance,
+            pdf: 1.0,
+            delta: true,
+        };
+        true
+    }
+}
+
+/// Emissive geometry registered for direct sampling. The shape must also be in the world,
+/// and must implement `Hiitable::random`, `pdf_value`, `sample_surface` and `area`. Spheres,
+/// rectangles and boxes do, and so do `Translate`, `Rotatey` and `FlipFace` around them;
+/// BVHs, media and alpha-masked shapes do not.
+pub struct AreaLight {
+    shape: Option<Arc<dyn Hiitable>>,
+}
+
+impl AreaLight {
+    pub fn new(shape: Option<Arc<dyn Hiitable>>) -> Self {
+        debug_assert!(
+            shape.as_ref().is_none_or(|s| s.area() > 0.0),
+            "area light shape cannot be sampled"
+        );
+        Self { shape }
+    }
+
+    /// Radiance leaving `point` along `direction`, found by looking back at the shape.
+    fn radiance_towards(
+        &self,
+        shape: &dyn Hiitable,
+        point: &Vec3,
+        direction: &Vec3,
+        tm: f64,
+    ) -> Vec3 {
+        let eps = 1e-4 * (1.0 + point.length());
+        let r = Ray::new(*point + *direction * eps, -*direction, tm);
+        let mut rec = HitRecord::new();
+        if !shape.hit(&r, 0.0, 2.0 * eps, &mut rec) {
+            return Vec3::zero();
+        }
+        rec.mat.as_ref().map_or(Vec3::zero(), |mat| {
+            mat.emitted(&r, &rec, rec.u, rec.v, &rec.point3)
+        })
+    }
+
+    /// Chance of emitting from the side `normal` points to. Lights that glow on one side only
+    /// send every path out of that side.
+    fn front_weight(&self, shape: &dyn Hiitable, point: &Vec3, normal: &Vec3, tm: f64) -> f64 {
+        let front = self.radiance_towards(shape, point, normal, tm).luminance();
+        let back = self
+            .radiance_towards(shape, point, &-*normal, tm)
+            .luminance();
+        if front + back <= 0.0 {
+            return 0.0;
+        }
+        front / (front + back)
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, p: &Vec3, tm: f64, sample: &mut LightSample) -> bool {
+        let shape = match &self.shape {
+            Some(shape) => shape,
+            None => return false,
+        };
+        let to_light = shape.random(p);
+        let pdf = shape.pdf_value(p, &to_light);
+        if pdf <= 0.0 {
+            return false;
+        }
+        let r = Ray::new(*p, to_light, tm);
+        let mut rec = HitRecord::new();
+        if !shape.hit(&r, 0.001, f64::INFINITY, &mut rec) {
+            return false;
+        }
+        let radiance = match &rec.mat {
+            Some(mat) => mat.emitted(&r, &rec, rec.u, rec.v, &rec.point3),
+            None => return false,
+        };
+        *sample = LightSample {
+            direction: to_light.unit(),
+            distance: rec.t * to_light.length(),
+            radiance,
+            pdf,
+            delta: false,
+        };
+        true
+    }
+
+    fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        self.shape
+            .as_ref()
+            .map_or(0.0, |shape| shape.pdf_value(p, direction))
+    }
+
+    /// Cosine-weighted directions out of one side, picked in proportion to its brightness.
+    fn sample_emission(&self, tm: f64, sample: &mut EmissionSample) -> bool {
+        let shape = match &self.shape {
+            Some(shape) => shape.as_ref(),
+            None => return false,
+        };
+        let mut point = Vec3::zero();
+        let mut normal = Vec3::zero();
+        let area = shape.area();
+        if !shape.sample_surface(&mut point, &mut normal) || area <= 0.0 {
+            return false;
+        }
+        let normal = normal.unit();
+        let front = self.front_weight(shape, &point, &normal, tm);
+        let side = if random_f64() < front {
+            normal
+        } else {
+            -normal
+        };
+        let mut direction = side + Vec3::random_unit_vector();
+        if direction.near_zero() {
+            direction = side;
+        }
+        let direction = direction.unit();
+        let mut radiance = Vec3::zero();
+        let pdf_dir = self.emission(&point, &normal, &direction, tm, &mut radiance);
+        if pdf_dir <= 0.0 {
+            return false;
+        }
+        *sample = EmissionSample {
+            ray: Ray::new(point, direction, tm),
+            normal,
+            radiance,
+            pdf_pos: 1.0 / area,
+            pdf_dir,
+            delta: false,
+        };
+        true
+    }
+
+    fn emission(
+        &self,
+        point: &Vec3,
+        normal: &Vec3,
+        direction: &Vec3,
+        tm: f64,
+        radiance: &mut Vec3,
+    ) -> f64 {
+        let shape = match &self.shape {
+            Some(shape) => shape.as_ref(),
+            None => return 0.0,
+        };
+        let direction = direction.unit();
+        let normal = normal.unit();
+        *radiance = self.radiance_towards(shape, point, &direction, tm);
+        let front = self.front_weight(shape, point, &normal, tm);
+        let cos_theta = normal * direction;
+        let side = if cos_theta > 0.0 { front } else { 1.0 - front };
+        side * cos_theta.abs() / PI
+    }
+
+    fn pdf_emission(&self, r: &Ray, t_hit: &mut f64) -> (f64, f64) {
+        let shape = match &self.shape {
+            Some(shape) => shape,
+            None => return (0.0, 0.0),
+        };
+        let area = shape.area();
+        let mut rec = HitRecord::new();
+        if area <= 0.0 || !shape.hit(r, 0.001, f64::INFINITY, &mut rec) {
+            return (0.0, 0.0);
+        }
+        let mut radiance = Vec3::zero();
+        let pdf_dir = self.emission(&rec.point3, &rec.normal, &-r.direc(), r.tm(), &mut radiance);
+        *t_hit = rec.t;
+        (1.0 / area, pdf_dir)
+    }
+}
+
+/// The lights of a scene. Each shadow ray goes to one light picked uniformly.
+#[derive(Clone, Default)]
+pub struct LightList {
+    pub lights: Vec<Option<Arc<dyn Light>>>,
+}
+
+impl LightList {
+    pub fn new() -> Self {
+        Self { lights: Vec::new() }
+    }
+
+    pub fn add(&mut self, light: Option<Arc<dyn Light>>) {
+        self.lights.push(light);
+    }
+
+    /// Registers an emissive shape that is also added to the world.
+    pub fn add_area(&mut self, shape: Option<Arc<dyn Hiitable>>) {
+        self.add(Some(Arc::new(AreaLight::new(shape))));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Samples one light; `sample.pdf` includes the chance of picking it.
+    pub fn sample(&self, p: &Vec3, tm: f64, sample: &mut LightSample) -> bool {
+        if self.lights.is_empty() {
+            return false;
+        }
+        let n = self.lights.len();
+        let i = ((random_f64() * n as f64) as usize).min(n - 1);
+        let picked = self.lights[i]
+            .as_ref()
+            .is_some_and(|l| l.sample(p, tm, sample));
+        if picked {
+            sample.pdf /= n as f64;
+        }
+        picked
+    }
+
+    /// Picks a light uniformly and samples light leaving it; `sample.pdf_pos` includes the
+    /// chance of picking it.
+    pub fn sample_emission(&self, tm: f64, sample: &mut EmissionSample) -> Option<Arc<dyn Light>> {
+        if self.lights.is_empty() {
+            return None;
+        }
+        let n = self.lights.len();
+        let i = ((random_f64() * n as f64) as usize).min(n - 1);
+        let light = self.lights[i].clone()?;
+        if !light.sample_emission(tm, sample) {
+            return None;
+        }
+        sample.pdf_pos /= n as f64;
+        Some(light)
+    }
+
+    /// `Light::pdf_emission` of the nearest light `r` reaches, including the chance of
+    /// picking it in `sample_emission`.
+    pub fn pdf_emission(&self, r: &Ray) -> (f64, f64) {
+        let mut nearest = f64::INFINITY;
+        let mut pdf = (0.0, 0.0);
+        for light in self.lights.iter().flatten() {
+            let mut t_hit = f64::INFINITY;
+            let (pdf_pos, pdf_dir) = light.pdf_emission(r, &mut t_hit);
+            if pdf_pos > 0.0 && t_hit < nearest {
+                nearest = t_hit;
+                pdf = (pdf_pos / self.lights.len() as f64, pdf_dir);
+            }
+        }
+        pdf
+    }
+
+    /// Density of reaching `direction` from `p` through `sample`, over all area lights.
+    pub fn pdf(&self, p: &Vec3, direction: &Vec3) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .lights
+            .iter()
+            .flatten()
+            .map(|l| l.pdf(p, direction))
+            .sum();
+        sum / self.lights.len() as f64
+    }
+}
+
+/// Reads the vertical angles and candela values of an IESNA LM-63 photometric file,
+/// averaging over the horizontal planes, for `SpotLight::set_profile`.
+pub fn load_ies_profile(path: &str) -> Result<Vec<(f64, f64)>> {
+    let src = std::fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    parse_ies_profile(&src).map_err(|message| Error::Photometry {
+        path: path.to_string(),
+        message,
+    })
+}
+
+fn parse_ies_profile(src: &str) -> std::result::Result<Vec<(f64, f64)>, String> {
+    let start = src
+        .find("TILT=")
+        .ok_or_else(|| "missing TILT line".to_string())?;
+    let mut lines = src[start..].lines();
+    let tilt = lines.next().unwrap_or_default();
+    if tilt.trim() != "TILT=NONE" {
+        return Err("only TILT=NONE is supported".to_string());
+    }
+    let numbers = lines
+        .flat_map(|l| l.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            t.parse::<f64>()
+                .map_err(|_| format!("expected a number, found \"{}\"", t))
+        })
+        .collect::<std::result::Result<Vec<f64>, String>>()?;
+    if numbers.len() < 13 {
+        return Err("truncated header".to_string());
+    }
+    let multiplier = numbers[2];
+    let vertical = numbers[3] as usize;
+    let horizontal = numbers[4] as usize;
+    // Ten lamp and ballast numbers, then the angle tables, then the candela grid.
+    let angles = 13;
+    let candela = angles + vertical + horizontal;
+    if numbers.len() < candela + vertical * horizontal || vertical == 0 || horizontal == 0 {
+        return Err("truncated candela table".to_string());
+    }
+    Ok((0..vertical)
+        .map(|v| {
+            let sum: f64 = (0..horizontal)
+                .map(|h| numbers[candela + h * vertical + v])
+                .sum();
+            (numbers[angles + v], multiplier * sum / horizontal as f64)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xyrect;
+    use crate::material::{DiffLight, Material, Rotatey};
+    use crate::r#box::Box;
+
+    fn glowing() -> Option<Arc<dyn Material>> {
+        Some(Arc::new(DiffLight::new2(Vec3::ones())))
+    }
+
+    #[test]
+    fn test_pdf_emission_takes_nearest_light() {
+        // The far light is registered first; the ray reaches the near one before it.
+        let mut lights = LightList::new();
+        lights.add_area(Some(Arc::new(Xyrect::new(
+            -2.0,
+            2.0,
+            -2.0,
+            2.0,
+            -3.0,
+            glowing(),
+        ))));
+        lights.add_area(Some(Arc::new(Xyrect::new(
+            -1.0,
+            1.0,
+            -1.0,
+            1.0,
+            -1.0,
+            glowing(),
+        ))));
+        let r = Ray::new(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0), 0.0);
+        let (pdf_pos, pdf_dir) = lights.pdf_emission(&r);
+        assert!((pdf_pos - 1.0 / (4.0 * 2.0)).abs() < 1e-12, "{}", pdf_pos);
+        assert!((pdf_dir - 1.0 / PI).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rotated_box_light_solid_angle() {
+        let shape: Arc<dyn Hiitable> = Arc::new(Rotatey::new(
+            Some(Arc::new(Box::new(
+                Vec3::new(-1.0, -1.0, -1.0),
+                Vec3::new(1.0, 1.0, 1.0),
+                glowing(),
+            ))),
+            30.0,
+        ));
+        let light = AreaLight::new(Some(shape.clone()));
+        let p = Vec3::new(1.0, 2.0, 5.0);
+        let n = 200_000;
+
+        // Sampling the light estimates the solid angle it covers ...
+        let mut sampled = 0.0;
+        let mut s = LightSample::new();
+        for _ in 0..n {
+            if light.sample(&p, 0.0, &mut s) {
+                assert!((light.pdf(&p, &s.direction) - s.pdf).abs() < 1e-9 * s.pdf);
+                sampled += 1.0 / s.pdf;
+            }
+        }
+        // ... which shooting rays in every direction measures too.
+        let mut hits = 0;
+        for _ in 0..n {
+            let r = Ray::new(p, Vec3::random_unit_vector(), 0.0);
+            let mut rec = HitRecord::new();
+            if shape.hit(&r, 0.001, f64::INFINITY, &mut rec) {
+                hits += 1;
+            }
+        }
+        let sampled = sampled / n as f64;
+        let shot = 4.0 * PI * hits as f64 / n as f64;
+        assert!((sampled - shot).abs() < 0.03 * shot, "{} {}", sampled, shot);
+    }
+
+    #[test]
+    fn test_spot_cone_and_profile() {
+        let mut spot = SpotLight::new(
+            Vec3::new(0.0, 2.0, 0.0),
+            Vec3::zero(),
+            Vec3::ones() * 4.0,
+            30.0,
+            20.0,
+        );
+        let mut s = LightSample::new();
+        assert!(spot.sample(&Vec3::zero(), 0.0, &mut s));
+        assert!((s.radiance.x() - 1.0).abs() < 1e-12);
+        assert!(!spot.sample(&Vec3::new(3.0, 0.0, 0.0), 0.0, &mut s));
+
+        spot.set_profile(vec![(0.0, 200.0), (10.0, 100.0), (90.0, 0.0)]);
+        assert!(spot.sample(&Vec3::zero(), 0.0, &mut s));
+        assert!((s.radiance.x() - 1.0).abs() < 1e-12);
+        assert!((spot.profile_at(5.0) - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_ies() {
+        let src = "IESNA:LM-63-2002\n[TEST] spot\nTILT=NONE\n1 1000 1.0 3 2 1 2 0 0 0\n1.0 1.0 0\n0 45 90\n0 180\n100 50 0\n300 150 0\n";
+        let profile = parse_ies_profile(src).unwrap();
+        assert_eq!(profile, vec![(0.0, 200.0), (45.0, 100.0), (90.0, 0.0)]);
+        assert!(parse_ies_profile("TILT=INCLUDE\n").is_err());
+    }
+}
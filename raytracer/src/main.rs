@@ -1,90 +1,111 @@
 mod aabb;
 mod aarect;
+mod alpha_mask;
+mod bdpt;
 mod r#box;
+mod bump;
 mod bvh;
 mod camera;
 mod color;
 mod constant_medium;
+mod environment;
+mod error;
+mod graph;
 mod hiitable;
 mod hittable_list;
+mod integrator;
+mod layered;
+mod light;
 mod material;
+mod microfacet;
 mod moving_sphere;
+mod nodes;
 mod object;
+mod onb;
+mod path_tracer;
 mod perlin;
+mod phase;
+mod photon_map;
+mod principled;
+mod procedural;
 mod ray;
 mod rtweekend;
+mod sampling;
+mod simplex;
+mod sky;
+mod sppm;
+mod subsurface;
 mod texture;
 mod vec3;
+mod volume;
 
 pub use crate::aarect::{Xyrect, Xzrect, Yzrect};
+pub use alpha_mask::AlphaMask;
+pub use bdpt::Bdpt;
+pub use bump::{BumpMapped, NormalMapped};
 pub use bvh::BvhNode;
-pub use camera::Camera;
+pub use camera::{
+    ApertureMask, ApertureShape, Camera, ImportanceSample, Projection, StereoCamera, StereoLayout,
+    StereoMode, StereoRig,
+};
+pub use color::blackbody;
 use color::write_color;
 pub use constant_medium::ConstantMedium;
+pub use environment::{ConstantEnvironment, Environment, GradientEnvironment, ImageEnvironment};
+pub use error::Error;
+pub use graph::{load_texture_graph, parse_texture_graph};
 pub use hiitable::Hiitable;
 pub use hittable_list::HittableList;
 use image::ImageBuffer;
 use indicatif::ProgressBar;
-pub use material::{Dielectric, DiffLight, Lambertian, Material, Metal, Rotatey, Translate};
+pub use integrator::{AmbientOcclusion, DebugIntegrator, DebugView, DirectLighting, Integrator};
+pub use layered::{Coated, MixMaterial};
+pub use light::{
+    load_ies_profile, AreaLight, DirectionalLight, EmissionSample, Light, LightList, LightSample,
+    PointLight, SpotLight,
+};
+pub use material::{
+    beer_lambert, Dielectric, DiffLight, FlipFace, Lambertian, Material, Metal, PowerUnit, Rotatey,
+    Translate,
+};
+pub use microfacet::{Conductor, RoughDielectric, TrowbridgeReitz};
 pub use moving_sphere::MovingSphere;
-use object::HitRecord;
+pub use nodes::{
+    AddTexture, Channel, ChannelTexture, ClampTexture, HueSaturationTexture, InvertTexture,
+    LerpTexture, MultiplyTexture, RemapTexture, TriplanarTexture, UvTransformTexture,
+};
 pub use object::Sphere;
-pub use perlin::Perlin;
+pub use onb::Onb;
+pub use path_tracer::{PathSettings, PathTracer};
+pub use perlin::{Fade, Perlin};
+pub use phase::{
+    DoubleHenyeyGreenstein, HenyeyGreenstein, IsotropicPhase, PhaseFunction, Rayleigh,
+};
+pub use photon_map::{Photon, PhotonMap, PhotonMapping, PhotonSettings};
+pub use principled::{Principled, PrincipledParams};
+pub use procedural::{
+    ColorRamp, FbmTexture, GradientKind, GradientTexture, MarbleTexture, SimplexTexture,
+    UvCheckerTexture, WoodTexture, WorleyMode, WorleyTexture,
+};
 pub use r#box::Box;
 pub use ray::Ray;
 pub use rtweekend::{degrees_to_radians, random_f64, random_f64_1};
+pub use sampling::{Distribution1D, Distribution2D};
+pub use simplex::Simplex;
+pub use sky::{sun_direction, SkyEnvironment};
+pub use sppm::{Sppm, SppmSettings};
 use std::fs::File;
 use std::sync::{Arc, Mutex};
 use std::thread;
-pub use texture::{CheckerTexture, ImageTexture, NoiseTexture, Texture};
+pub use subsurface::Subsurface;
+pub use texture::{
+    CheckerTexture, ColorSpace, ImageTexture, NoiseTexture, SolidColor, Texture, TextureCache,
+    TextureFilter, WrapMode,
+};
 pub use vec3::Vec3;
+pub use volume::{GridTexture, HeterogeneousMedium};
 
 const AUTHOR: &str = "Zhang Tongcheng";
-const INFINITY: f64 = f64::INFINITY;
-
-fn ray_color(r: &Ray, background: &Vec3, world: &mut HittableList, depth: i32) -> Vec3 {
-    let mut rec: HitRecord = HitRecord::new();
-    if depth <= 0 {
-        return Vec3::new(0.0, 0.0, 0.0);
-    }
-    if !world.hit(r, 0.001, INFINITY, &mut rec) {
-        return *background;
-    }
-    let mut scattered = Ray::new(
-        Vec3::new(0.0, 0.0, 0.0),
-        Vec3::new(0.0, 0.0, 0.0),
-        random_f64_1(0.0, 1.0),
-    );
-    let mut attenuation = Vec3::new(0.0, 0.0, 0.0);
-    let emitter = rec.mat.clone().unwrap().emitted(rec.u, rec.v, &rec.point3);
-    if !rec
-        .mat
-        .clone()
-        .unwrap()
-        .scatter(r, &mut rec, &mut attenuation, &mut scattered)
-    {
-        return emitter;
-    }
-    //println!("x:{}",attenuation.x());
-    emitter
-        + Vec3::elemul(
-            &attenuation,
-            &ray_color(&scattered, background, world, depth - 1),
-        )
-    // } else {
-    //     let unit_direction = r.direc.unit();
-    //     let t = 0.5 * (unit_direction.y() + 1.0);
-
-    //     let tmp1 = Vec3::new(1.0, 1.0, 1.0);
-    //     let tmp2 = Vec3::new(0.5, 0.7, 1.0);
-
-    //     let x1 = tmp1.x * (1.0 - t) + tmp2.x * t;
-    //     let y1 = tmp1.y * (1.0 - t) + tmp2.y * t;
-    //     let z1 = tmp1.z * (1.0 - t) + tmp2.z * t;
-    //     //println!("tmp={}{}{}", tmp[0], tmp[1], tmp[2]);
-    //     Vec3::new(x1, y1, z1)
-    // }
-}
 
 fn random_scene() -> HittableList {
     let mut world = HittableList::new();
@@ -201,8 +222,10 @@ fn two_perlin_spheres() -> HittableList {
     objects
 }
 
-fn earth() -> HittableList {
-    let earth_texture: Option<Arc<dyn Texture>> = Some(Arc::new(ImageTexture::new("earthmap.jpg")));
+fn earth() -> Result<HittableList, Error> {
+    let mut earth_image = ImageTexture::new("earthmap.jpg")?;
+    earth_image.set_wrap(WrapMode::Repeat);
+    let earth_texture: Option<Arc<dyn Texture>> = Some(Arc::new(earth_image));
     let earth_surface: Option<Arc<dyn Material>> = Some(Arc::new(Lambertian::new2(&earth_texture)));
     let globe: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
         &Vec3::new(0.0, 0.0, 0.0),
@@ -211,10 +234,10 @@ fn earth() -> HittableList {
     )));
     let mut world_0: HittableList = HittableList::new();
     world_0.add(globe);
-    world_0
+    Ok(world_0)
 }
 
-fn simple_silght() -> HittableList {
+fn simple_silght(lights: &mut LightList) -> HittableList {
     let mut objects: HittableList = HittableList::new();
     let pertext: Option<Arc<dyn Texture>> = Some(Arc::new(NoiseTexture::new_0(4.0)));
     objects.add(Some(Arc::new(Sphere::new(
@@ -230,23 +253,27 @@ fn simple_silght() -> HittableList {
 
     let difflight: Option<Arc<dyn Material>> =
         Some(Arc::new(DiffLight::new2(Vec3::new(4.0, 4.0, 4.0))));
-    objects.add(Some(Arc::new(Xyrect::new(
+    let rect: Option<Arc<dyn Hiitable>> = Some(Arc::new(Xyrect::new(
         3.0,
         5.0,
         1.0,
         3.0,
         -2.0,
         difflight.clone(),
-    ))));
-    objects.add(Some(Arc::new(Sphere::new(
+    )));
+    let bulb: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
         &Vec3::new(0.0, 7.0, 0.0),
         2.0,
         difflight.clone(),
-    ))));
+    )));
+    lights.add_area(rect.clone());
+    lights.add_area(bulb.clone());
+    objects.add(rect);
+    objects.add(bulb);
     objects
 }
 
-fn cornell_box() -> HittableList {
+fn cornell_box(lights: &mut LightList) -> HittableList {
     let mut objects: HittableList = HittableList::new();
 
     let red: Option<Arc<dyn Material>> =
@@ -264,9 +291,12 @@ fn cornell_box() -> HittableList {
     objects.add(Some(Arc::new(Yzrect::new(
         0.0, 555.0, 0.0, 555.0, 0.0, red,
     ))));
-    objects.add(Some(Arc::new(Xzrect::new(
-        213.0, 343.0, 227.0, 332.0, 554.0, light,
-    ))));
+    // The rect faces up; flip it so the one-sided light shines into the room.
+    let lamp: Option<Arc<dyn Hiitable>> = Some(Arc::new(FlipFace::new(Some(Arc::new(
+        Xzrect::new(213.0, 343.0, 227.0, 332.0, 554.0, light),
+    )))));
+    lights.add_area(lamp.clone());
+    objects.add(lamp);
     objects.add(Some(Arc::new(Xzrect::new(
         0.0,
         555.0,
@@ -323,7 +353,7 @@ fn cornell_box() -> HittableList {
     objects
 }
 
-fn cornell_smoke() -> HittableList {
+fn cornell_smoke(lights: &mut LightList) -> Result<HittableList, Error> {
     let mut objects = HittableList::new();
 
     let red: Option<Arc<dyn Material>> =
@@ -340,9 +370,12 @@ fn cornell_smoke() -> HittableList {
     objects.add(Some(Arc::new(Yzrect::new(
         0.0, 555.0, 0.0, 555.0, 0.0, red,
     ))));
-    objects.add(Some(Arc::new(Xzrect::new(
-        113.0, 443.0, 127.0, 432.0, 554.0, light,
-    ))));
+    // The rect faces up; flip it so the one-sided light shines into the room.
+    let lamp: Option<Arc<dyn Hiitable>> = Some(Arc::new(FlipFace::new(Some(Arc::new(
+        Xzrect::new(113.0, 443.0, 127.0, 432.0, 554.0, light),
+    )))));
+    lights.add_area(lamp.clone());
+    objects.add(lamp);
     objects.add(Some(Arc::new(Xzrect::new(
         0.0,
         555.0,
@@ -379,7 +412,7 @@ fn cornell_smoke() -> HittableList {
         box1,
         0.01,
         Vec3::new(0.0, 0.0, 0.0),
-    ))));
+    )?)));
 
     let mut box2: Option<Arc<dyn Hiitable>> = Some(Arc::new(Box::new(
         Vec3::new(0.0, 0.0, 0.0),
@@ -392,12 +425,12 @@ fn cornell_smoke() -> HittableList {
         box2,
         0.01,
         Vec3::new(1.0, 1.0, 1.0),
-    ))));
+    )?)));
 
-    objects
+    Ok(objects)
 }
 
-fn final_scene() -> HittableList {
+fn final_scene(lights: &mut LightList) -> Result<HittableList, Error> {
     let mut boxes1 = HittableList::new();
     let ground: Option<Arc<dyn Material>> =
         Some(Arc::new(Lambertian::new1(&Vec3::new(0.48, 0.83, 0.53))));
@@ -424,9 +457,12 @@ fn final_scene() -> HittableList {
 
     let light: Option<Arc<dyn Material>> =
         Some(Arc::new(DiffLight::new2(Vec3::new(7.0, 7.0, 7.0))));
-    objects.add(Some(Arc::new(Xzrect::new(
-        123.0, 423.0, 147.0, 412.0, 554.0, light,
-    ))));
+    // The rect faces up; flip it so the one-sided light shines into the room.
+    let lamp: Option<Arc<dyn Hiitable>> = Some(Arc::new(FlipFace::new(Some(Arc::new(
+        Xzrect::new(123.0, 423.0, 147.0, 412.0, 554.0, light),
+    )))));
+    lights.add_area(lamp.clone());
+    objects.add(lamp);
 
     let center1 = Vec3::new(400.0, 400.0, 200.0);
     let center2 = center1 + Vec3::new(30.0, 0.0, 0.0);
@@ -452,7 +488,7 @@ fn final_scene() -> HittableList {
         Some(Arc::new(Metal::new(&Vec3::new(0.8, 0.8, 0.9), 1.0))),
     ))));
 
-    let mut boundary: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
+    let boundary: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
         &Vec3::new(360.0, 150.0, 145.0),
         70.0,
         Some(Arc::new(Dielectric::new(1.5))),
@@ -462,20 +498,15 @@ fn final_scene() -> HittableList {
         boundary.clone(),
         0.2,
         Vec3::new(0.2, 0.4, 0.9),
-    ))));
-    boundary = Some(Arc::new(Sphere::new(
-        &Vec3::new(0.0, 0.0, 0.0),
-        5000.0,
-        Some(Arc::new(Dielectric::new(1.5))),
-    )));
-    objects.add(Some(Arc::new(ConstantMedium::new2(
-        boundary.clone(),
+    )?)));
+    objects.add(Some(Arc::new(ConstantMedium::new_global(
         0.0001,
         Vec3::new(1.0, 1.0, 1.0),
-    ))));
+        2000.0,
+    )?)));
 
     let emat: Option<Arc<dyn Material>> = Some(Arc::new(Lambertian::new2(&Some(Arc::new(
-        ImageTexture::new("earthmap.jpg"),
+        ImageTexture::new("earthmap.jpg")?,
     )))));
     objects.add(Some(Arc::new(Sphere::new(
         &Vec3::new(400.0, 200.0, 400.0),
@@ -509,9 +540,670 @@ fn final_scene() -> HittableList {
         Vec3::new(-100.0, 270.0, 395.0),
     ))));
 
+    Ok(objects)
+}
+
+fn microfacet_spheres() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    let checker: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&checker))),
+    ))));
+
+    let materials: Vec<Option<Arc<dyn Material>>> = vec![
+        Some(Arc::new(Conductor::gold(0.2))),
+        Some(Arc::new(Conductor::copper(0.4))),
+        Some(Arc::new(RoughDielectric::new(1.5, 0.2, 0.2))),
+        Some(Arc::new(Conductor::new(
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+            0.1,
+            0.6,
+        ))),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(-3.3 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            mat,
+        ))));
+    }
+
+    objects
+}
+
+/// Metal, plastic and matte spheres lit only by an equirectangular map.
+fn environment_spheres() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.5, 0.5, 0.5)))),
+    ))));
+
+    let plastic = PrincipledParams {
+        base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.8, 0.1, 0.1)))),
+        roughness: Some(Arc::new(SolidColor::new(Vec3::ones() * 0.3))),
+        ..PrincipledParams::default()
+    };
+    let materials: Vec<Option<Arc<dyn Material>>> = vec![
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.8, 0.8, 0.8)))),
+        Some(Arc::new(Principled::new(plastic))),
+        Some(Arc::new(Conductor::silver(0.15))),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(-2.2 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            mat,
+        ))));
+    }
+
+    objects
+}
+
+fn earth_environment() -> Result<ImageEnvironment, Error> {
+    let mut env = ImageEnvironment::new("earthmap.jpg")?;
+    env.set_intensity(1.5);
+    env.set_rotation(90.0);
+    Ok(env)
+}
+
+/// A few blocks and spheres on a plaza, for judging daylight.
+fn daylight_plaza() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    let paving: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.55, 0.52, 0.48),
+        Vec3::new(0.4, 0.38, 0.35),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&paving))),
+    ))));
+
+    let concrete: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.7, 0.68, 0.65))));
+    objects.add(Some(Arc::new(Rotatey::new(
+        Some(Arc::new(Box::new(
+            Vec3::new(-1.0, 0.0, -1.0),
+            Vec3::new(1.0, 4.0, 1.0),
+            concrete.clone(),
+        ))),
+        20.0,
+    ))));
+    objects.add(Some(Arc::new(Box::new(
+        Vec3::new(2.0, 0.0, -3.0),
+        Vec3::new(5.0, 2.0, -1.5),
+        concrete,
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(-3.0, 1.0, 1.5),
+        1.0,
+        Some(Arc::new(Conductor::aluminium(0.1))),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(3.0, 0.7, 1.5),
+        0.7,
+        Some(Arc::new(Dielectric::new(1.5))),
+    ))));
+
     objects
 }
 
+/// A dark room lit by a spotlight, a bare bulb and moonlight through the open side.
+fn lamp_lit(lights: &mut LightList) -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    let floor: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.2, 0.2, 0.2),
+        Vec3::new(0.8, 0.8, 0.8),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&floor))),
+    ))));
+    let white: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.73, 0.73, 0.73))));
+    objects.add(Some(Arc::new(Xyrect::new(
+        -8.0,
+        8.0,
+        0.0,
+        8.0,
+        -4.0,
+        white.clone(),
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(-2.0, 1.0, 0.0),
+        1.0,
+        white,
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(2.0, 1.0, 0.0),
+        1.0,
+        Some(Arc::new(Conductor::gold(0.3))),
+    ))));
+
+    // A warm softbox on the back wall, given by its power so resizing it keeps the output.
+    let mut softbox = DiffLight::with_power(blackbody(3200.0), 12.0, PowerUnit::Watts, 4.0)?;
+    softbox.set_strength(Some(Arc::new(GradientTexture::new(
+        Some(Arc::new(SolidColor::new(Vec3::ones() * 0.2))),
+        Some(Arc::new(SolidColor::new(Vec3::ones()))),
+        GradientKind::V,
+    ))));
+    let softbox: Option<Arc<dyn Hiitable>> = Some(Arc::new(Xyrect::new(
+        3.0,
+        5.0,
+        2.0,
+        4.0,
+        -3.99,
+        Some(Arc::new(softbox)),
+    )));
+    lights.add_area(softbox.clone());
+    objects.add(softbox);
+
+    let mut spot = SpotLight::new(
+        Vec3::new(-2.0, 6.0, 1.0),
+        Vec3::new(-2.0, 0.0, 0.0),
+        Vec3::new(60.0, 55.0, 45.0),
+        30.0,
+        20.0,
+    );
+    // A downlight whose beam is brightest in a ring around the axis.
+    spot.set_profile(vec![(0.0, 0.6), (15.0, 1.0), (30.0, 0.2)]);
+    lights.add(Some(Arc::new(spot)));
+    lights.add(Some(Arc::new(PointLight::new(
+        Vec3::new(2.5, 3.0, 2.5),
+        Vec3::new(6.0, 6.0, 8.0),
+    ))));
+    lights.add(Some(Arc::new(DirectionalLight::new(
+        Vec3::new(1.0, 1.0, 0.5),
+        Vec3::new(0.05, 0.06, 0.1),
+    ))));
+
+    Ok(objects)
+}
+
+/// A spotlight cutting through scene-wide fog onto tinted glass and an L-shaped plume of
+/// smoke, whose boundary is neither convex nor axis-aligned.
+fn foggy_spotlight(lights: &mut LightList) -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.5, 0.5, 0.5)))),
+    ))));
+
+    let mut amber = Dielectric::new(1.5);
+    amber.set_absorption(Vec3::new(0.1, 0.6, 1.8));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(1.5, 1.0, 0.0),
+        1.0,
+        Some(Arc::new(amber)),
+    ))));
+
+    let mut plume = HittableList::new();
+    plume.add(Some(Arc::new(Box::new(
+        Vec3::new(-0.5, 0.0, -0.5),
+        Vec3::new(0.5, 3.0, 0.5),
+        None,
+    ))));
+    plume.add(Some(Arc::new(Box::new(
+        Vec3::new(0.5, 0.0, -0.5),
+        Vec3::new(2.0, 1.0, 0.5),
+        None,
+    ))));
+    let plume: Option<Arc<dyn Hiitable>> = Some(Arc::new(Translate::new(
+        Some(Arc::new(Rotatey::new(
+            Some(Arc::new(BvhNode::new2(&mut plume, 0.0, 1.0))),
+            150.0,
+        ))),
+        Vec3::new(-1.5, 0.0, 0.0),
+    )));
+    objects.add(Some(Arc::new(ConstantMedium::new2(
+        plume,
+        1.5,
+        Vec3::new(0.8, 0.8, 0.8),
+    )?)));
+
+    let mut fog = ConstantMedium::new_global(0.03, Vec3::new(1.0, 1.0, 1.0), 50.0)?;
+    fog.set_phase(Some(Arc::new(HenyeyGreenstein::new(0.6))));
+    objects.add(Some(Arc::new(fog)));
+
+    lights.add(Some(Arc::new(SpotLight::new(
+        Vec3::new(0.0, 7.0, 2.0),
+        Vec3::new(0.0, 0.0, 0.0),
+        Vec3::new(150.0, 140.0, 120.0),
+        35.0,
+        25.0,
+    ))));
+
+    Ok(objects)
+}
+
+/// Wax, marble, milk and skin-toned objects under a single softbox, where the light bleeding
+/// through thin parts shows off the random walk.
+fn translucent_objects(lights: &mut LightList) -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.3, 0.3, 0.35)))),
+    ))));
+
+    let candle: Option<Arc<dyn Hiitable>> =
+        Some(Arc::new(Sphere::new(&Vec3::new(-2.4, 1.0, 0.0), 1.0, None)));
+    objects.add(Some(Arc::new(Subsurface::new(
+        candle,
+        Vec3::new(0.95, 0.8, 0.55),
+        Vec3::new(0.4, 0.25, 0.12),
+        1.45,
+    )?)));
+
+    let block: Option<Arc<dyn Hiitable>> = Some(Arc::new(Translate::new(
+        Some(Arc::new(Rotatey::new(
+            Some(Arc::new(Box::new(
+                Vec3::new(-0.7, 0.0, -0.7),
+                Vec3::new(0.7, 1.6, 0.7),
+                None,
+            ))),
+            30.0,
+        ))),
+        Vec3::new(0.0, 0.0, -0.5),
+    )));
+    objects.add(Some(Arc::new(Subsurface::new(
+        block,
+        Vec3::new(0.9, 0.9, 0.88),
+        Vec3::new(0.15, 0.15, 0.15),
+        1.5,
+    )?)));
+
+    // Skin scatters red light much further than blue.
+    let skin: Option<Arc<dyn Hiitable>> =
+        Some(Arc::new(Sphere::new(&Vec3::new(2.4, 1.0, 0.0), 1.0, None)));
+    objects.add(Some(Arc::new(Subsurface::with_phase(
+        skin,
+        Vec3::new(0.85, 0.6, 0.5),
+        Vec3::new(0.37, 0.14, 0.08),
+        1.4,
+        Some(Arc::new(HenyeyGreenstein::new(0.8))),
+    )?)));
+
+    let softbox: Option<Arc<dyn Hiitable>> =
+        Some(Arc::new(FlipFace::new(Some(Arc::new(Xzrect::new(
+            -3.0,
+            3.0,
+            -1.0,
+            3.0,
+            5.0,
+            Some(Arc::new(DiffLight::new2(Vec3::new(2.0, 2.0, 2.0)))),
+        ))))));
+    lights.add_area(softbox.clone());
+    objects.add(softbox);
+
+    Ok(objects)
+}
+
+/// Glass balls throwing caustics from a small lamp, and a bulb hidden behind a screen that
+/// only lights the wall. Both converge far faster with `Bdpt` than with path tracing.
+fn glass_caustics(lights: &mut LightList) -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.6, 0.6, 0.6)))),
+    ))));
+    objects.add(Some(Arc::new(Xyrect::new(
+        -8.0,
+        8.0,
+        0.0,
+        6.0,
+        -3.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.7, 0.7, 0.65)))),
+    ))));
+
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(-1.2, 0.8, 0.0),
+        0.8,
+        Some(Arc::new(Dielectric::new(1.5))),
+    ))));
+    let mut amber = Dielectric::new(1.5);
+    amber.set_absorption(Vec3::new(0.1, 0.6, 1.8));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(1.1, 0.6, 0.8),
+        0.6,
+        Some(Arc::new(amber)),
+    ))));
+
+    let lamp: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
+        &Vec3::new(2.5, 4.0, 2.0),
+        0.15,
+        Some(Arc::new(DiffLight::new2(Vec3::new(300.0, 290.0, 270.0)))),
+    )));
+    lights.add_area(lamp.clone());
+    objects.add(lamp);
+
+    objects.add(Some(Arc::new(Xyrect::new(
+        -4.4,
+        -2.6,
+        0.0,
+        1.0,
+        -2.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.2, 0.2, 0.2)))),
+    ))));
+    let bulb: Option<Arc<dyn Hiitable>> = Some(Arc::new(Sphere::new(
+        &Vec3::new(-3.5, 0.4, -2.5),
+        0.1,
+        Some(Arc::new(DiffLight::new2(Vec3::new(200.0, 120.0, 60.0)))),
+    )));
+    lights.add_area(bulb.clone());
+    objects.add(bulb);
+
+    objects
+}
+
+/// A procedural cloud bank over a fireball built from density and emission grids.
+fn cloud_and_fire() -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.35, 0.33, 0.3)))),
+    ))));
+
+    let cloud_density: Option<Arc<dyn Texture>> = Some(Arc::new(RemapTexture::new(
+        Some(Arc::new(FbmTexture::new(0.8, 5, 2.0, 0.5))),
+        (0.5, 0.75),
+        (0.0, 1.0),
+    )));
+    let mut cloud = HeterogeneousMedium::new(
+        Some(Arc::new(Box::new(
+            Vec3::new(-6.0, 3.5, -6.0),
+            Vec3::new(6.0, 5.5, -1.0),
+            None,
+        ))),
+        cloud_density,
+        4.0,
+        4.0,
+        Vec3::new(0.95, 0.95, 0.95),
+    )?;
+    // Mostly forward scattering with a little back-scatter, as in real clouds.
+    cloud.set_phase(Some(Arc::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.85))));
+    objects.add(Some(Arc::new(cloud)));
+
+    // Hot, thin at the core and cooler, denser towards the rim.
+    let n = 32;
+    let mut density = Vec::with_capacity(n * n * n);
+    let mut emission = Vec::with_capacity(n * n * n);
+    for k in 0..n {
+        for j in 0..n {
+            for i in 0..n {
+                let p = Vec3::new(i as f64, j as f64, k as f64) * (2.0 / n as f64)
+                    - Vec3::ones() * (1.0 - 1.0 / n as f64);
+                let falloff = (1.0 - p.length()).max(0.0);
+                density.push(Vec3::ones() * falloff.powf(0.5));
+                emission.push(blackbody(1200.0 + 2500.0 * falloff) * (6.0 * falloff * falloff));
+            }
+        }
+    }
+    let (min, max) = (Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 2.0, 1.0));
+    let mut fire = HeterogeneousMedium::from_grid(
+        Some(Arc::new(Sphere::new(&Vec3::new(0.0, 1.0, 0.0), 1.0, None))),
+        GridTexture::from_voxels(n, n, n, density, min, max),
+        3.0,
+        Vec3::new(0.3, 0.3, 0.3),
+    )?;
+    fire.set_emission(
+        Vec3::new(0.3, 0.3, 0.3),
+        Some(Arc::new(GridTexture::from_voxels(
+            n, n, n, emission, min, max,
+        ))),
+    );
+    objects.add(Some(Arc::new(fire)));
+
+    Ok(objects)
+}
+
+fn principled_spheres() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    let checker: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&checker))),
+    ))));
+
+    let car_paint = PrincipledParams {
+        base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.6, 0.05, 0.05)))),
+        metallic: PrincipledParams::constant(0.6),
+        roughness: PrincipledParams::constant(0.4),
+        clearcoat: PrincipledParams::constant(1.0),
+        ..Default::default()
+    };
+    let plastic = PrincipledParams {
+        base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.1, 0.3, 0.7)))),
+        roughness: Some(Arc::new(NoiseTexture::new_0(4.0))),
+        ..Default::default()
+    };
+    let velvet = PrincipledParams {
+        base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.4, 0.1, 0.5)))),
+        roughness: PrincipledParams::constant(1.0),
+        specular: PrincipledParams::constant(0.0),
+        sheen: PrincipledParams::constant(1.0),
+        ..Default::default()
+    };
+    let frosted = PrincipledParams {
+        base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.9, 1.0, 0.9)))),
+        roughness: PrincipledParams::constant(0.15),
+        transmission: PrincipledParams::constant(1.0),
+        ..Default::default()
+    };
+    for (i, params) in [car_paint, plastic, velvet, frosted]
+        .into_iter()
+        .enumerate()
+    {
+        objects.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(-3.3 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            Some(Arc::new(Principled::new(params))),
+        ))));
+    }
+
+    objects
+}
+
+fn layered_spheres() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    let checker: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    )));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&checker))),
+    ))));
+
+    let rust: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.45, 0.2, 0.08))));
+    let paint: Option<Arc<dyn Material>> =
+        Some(Arc::new(Metal::new(&Vec3::new(0.8, 0.8, 0.85), 0.1)));
+    let mask: Option<Arc<dyn Texture>> = Some(Arc::new(NoiseTexture::new_0(4.0)));
+    let varnish_base: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.6, 0.1, 0.1))));
+
+    let materials: Vec<Option<Arc<dyn Material>>> = vec![
+        Some(Arc::new(MixMaterial::new2(rust, paint, mask))),
+        Some(Arc::new(Coated::new(varnish_base, 1.5))),
+        Some(Arc::new(Coated::new_tinted(
+            Some(Arc::new(Lambertian::new1(&Vec3::new(0.9, 0.9, 0.9)))),
+            1.5,
+            Vec3::new(0.1, 1.0, 3.0),
+            0.5,
+        ))),
+    ];
+    for (i, mat) in materials.into_iter().enumerate() {
+        objects.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(-2.2 + 2.2 * i as f64, 1.0, 0.0),
+            1.0,
+            mat,
+        ))));
+    }
+
+    objects
+}
+
+fn bump_mapped() -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    let pertext: Option<Arc<dyn Texture>> = Some(Arc::new(NoiseTexture::new_0(4.0)));
+    let checker: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(0.2, 0.3, 0.1),
+        Vec3::new(0.9, 0.9, 0.9),
+    )));
+
+    let ground: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.6, 0.6, 0.6))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(BumpMapped::new(ground, checker, 0.05))),
+    ))));
+
+    let mut textures = TextureCache::new();
+    textures.set_missing_fallback(true);
+    let earth_texture: Option<Arc<dyn Texture>> = Some(Arc::new(textures.load("earthmap.jpg")?));
+    let earth_surface: Option<Arc<dyn Material>> = Some(Arc::new(Lambertian::new2(&earth_texture)));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, 2.0, 0.0),
+        2.0,
+        Some(Arc::new(BumpMapped::new(earth_surface, pertext, 0.02))),
+    ))));
+
+    Ok(objects)
+}
+
+fn alpha_masked() -> HittableList {
+    let mut objects: HittableList = HittableList::new();
+    let ground: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.48, 0.83, 0.53))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        ground,
+    ))));
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, 1.0, -2.0),
+        1.0,
+        Some(Arc::new(Metal::new(&Vec3::new(0.7, 0.6, 0.5), 0.0))),
+    ))));
+
+    // A fence of cut-out planks, rotated and moved through a BVH to exercise every wrapper.
+    let wood: Option<Arc<dyn Material>> =
+        Some(Arc::new(Lambertian::new1(&Vec3::new(0.5, 0.3, 0.15))));
+    let slats: Option<Arc<dyn Texture>> = Some(Arc::new(CheckerTexture::new_2(
+        Vec3::new(1.0, 1.0, 1.0),
+        Vec3::new(0.0, 0.0, 0.0),
+    )));
+    let mut fence = HittableList::new();
+    for i in 0..3 {
+        fence.add(Some(Arc::new(AlphaMask::new(
+            Some(Arc::new(Xyrect::new(
+                -3.0 + 2.0 * i as f64,
+                -1.1 + 2.0 * i as f64,
+                0.0,
+                1.5,
+                0.15,
+                wood.clone(),
+            ))),
+            slats.clone(),
+        ))));
+    }
+    objects.add(Some(Arc::new(Translate::new(
+        Some(Arc::new(Rotatey::new(
+            Some(Arc::new(BvhNode::new2(&mut fence, 0.0, 1.0))),
+            10.0,
+        ))),
+        Vec3::new(0.0, 0.0, 1.0),
+    ))));
+
+    objects
+}
+
+fn procedural_textures() -> Result<HittableList, Error> {
+    let mut objects: HittableList = HittableList::new();
+    let tiles = load_texture_graph("textures/flagstones.graph", &TextureCache::new())?;
+    objects.add(Some(Arc::new(Sphere::new(
+        &Vec3::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Some(Arc::new(Lambertian::new2(&tiles))),
+    ))));
+
+    let mut seeded = Perlin::with_seed(7);
+    seeded.set_fade(Fade::Quintic);
+    let mut veins = MarbleTexture::new(2.0, 6.0);
+    veins.set_noise(seeded);
+    let veins: Option<Arc<dyn Texture>> = Some(Arc::new(veins));
+    let marble: Option<Arc<dyn Texture>> = Some(Arc::new(ColorRamp::new(
+        veins,
+        vec![
+            (0.0, Vec3::new(0.15, 0.15, 0.2)),
+            (0.3, Vec3::new(0.85, 0.85, 0.8)),
+            (1.0, Vec3::new(0.95, 0.95, 0.95)),
+        ],
+    )));
+    let rings: Option<Arc<dyn Texture>> = Some(Arc::new(WoodTexture::new(6.0, 0.15, 2.0)));
+    let wood: Option<Arc<dyn Texture>> = Some(Arc::new(ColorRamp::new(
+        rings,
+        vec![
+            (0.0, Vec3::new(0.55, 0.35, 0.18)),
+            (1.0, Vec3::new(0.3, 0.15, 0.06)),
+        ],
+    )));
+    // Drifts over the shutter interval, so the clouds come out motion blurred.
+    let clouds: Option<Arc<dyn Texture>> =
+        Some(Arc::new(SimplexTexture::new(1.5, 6, 2.0, 0.5, 0.3)));
+    let fire: Option<Arc<dyn Texture>> = Some(Arc::new(ColorRamp::new(
+        Some(Arc::new(FbmTexture::new_turbulence(2.0, 6, 2.0, 0.5))),
+        vec![
+            (0.0, Vec3::new(0.1, 0.0, 0.0)),
+            (0.3, Vec3::new(0.9, 0.3, 0.0)),
+            (0.6, Vec3::new(1.0, 0.9, 0.3)),
+        ],
+    )));
+    let checker: Option<Arc<dyn Texture>> = Some(Arc::new(UvCheckerTexture::new_1(
+        marble.clone(),
+        wood.clone(),
+        8.0,
+        4.0,
+    )));
+    let gradient: Option<Arc<dyn Texture>> = Some(Arc::new(GradientTexture::new(
+        fire.clone(),
+        clouds.clone(),
+        GradientKind::V,
+    )));
+
+    let textures = [marble, wood, clouds, fire, checker, gradient];
+    for (i, tex) in textures.iter().enumerate() {
+        let x = -2.2 + 2.2 * (i % 3) as f64;
+        let z = if i < 3 { -1.2 } else { 1.2 };
+        objects.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(x, 0.9, z),
+            0.9,
+            Some(Arc::new(Lambertian::new2(tex))),
+        ))));
+    }
+
+    Ok(objects)
+}
+
 fn is_ci() -> bool {
     option_env!("CI").unwrap_or_default() == "true"
 }
@@ -528,13 +1220,13 @@ fn main() {
     let path = "output/test.jpg";
     let quality = 60; // From 0 to 100, suggested value: 60
     let samples_per_pixel = 5000;
-    let max_depth = 50;
+    // e.g. Some(StereoRig::new(StereoMode::Ods, StereoLayout::OverUnder, 0.064, 0.0))
+    let stereo: Option<StereoRig> = None;
+    // e.g. Some(Sppm::new(SppmSettings::default())) to render in passes of photons instead
+    let progressive: Option<Sppm> = None;
 
-    // Create image data
-    let img = Arc::new(Mutex::new(ImageBuffer::new(
-        width.try_into().unwrap(),
-        height.try_into().unwrap(),
-    )));
+    // Summed samples per pixel, row by row from the bottom; light tracing adds to any of them
+    let film = Arc::new(Mutex::new(vec![Vec3::zero(); width * height]));
 
     // World
     // let mut world: HittableList = HittableList::new();
@@ -569,14 +1261,15 @@ fn main() {
     let lookfrom: Vec3;
     let lookat: Vec3;
     let mut vup = Vec3::new(0.0, 1.0, 0.0);
-    let background: Vec3;
+    let background: Arc<dyn Environment>;
+    let mut lights = LightList::new();
     let time_start = 0.0;
     let time_end = 1.0;
 
     match 0 {
         1 => {
-            world = random_scene();
-            background = Vec3::new(0.7, 0.8, 1.0);
+            world = Ok(random_scene());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vup = Vec3::new(0.0, 1.0, 0.0);
@@ -584,51 +1277,164 @@ fn main() {
             aperture = 0.1;
         }
         2 => {
-            world = two_sphere();
-            background = Vec3::new(0.7, 0.8, 1.0);
+            world = Ok(two_sphere());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vup = Vec3::new(0.0, 1.0, 0.0);
             vfov = 20.0;
         }
         3 => {
-            world = two_perlin_spheres();
-            background = Vec3::new(0.7, 0.8, 1.0);
+            world = Ok(two_perlin_spheres());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
         }
         4 => {
             world = earth();
-            background = Vec3::new(0.7, 0.8, 1.0);
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
             lookfrom = Vec3::new(13.0, 2.0, 3.0);
             lookat = Vec3::new(0.0, 0.0, 0.0);
             vfov = 20.0;
         }
         5 => {
-            world = simple_silght();
-            background = Vec3::new(0.0, 0.0, 0.0);
+            world = Ok(simple_silght(&mut lights));
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
             lookfrom = Vec3::new(26.0, 3.0, 6.0);
             lookat = Vec3::new(0.0, 2.0, 0.0);
             vfov = 20.0;
         }
         6 => {
-            world = cornell_box();
-            background = Vec3::new(0.0, 0.0, 0.0);
+            world = Ok(cornell_box(&mut lights));
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
             lookfrom = Vec3::new(278.0, 278.0, -800.0);
             lookat = Vec3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
         }
         7 => {
-            world = cornell_smoke();
-            background = Vec3::new(0.0, 0.0, 0.0);
+            world = cornell_smoke(&mut lights);
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
             lookfrom = Vec3::new(278.0, 278.0, -800.0);
             lookat = Vec3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
         }
+        8 => {
+            world = Ok(microfacet_spheres());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        9 => {
+            world = Ok(principled_spheres());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        10 => {
+            world = Ok(layered_spheres());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        11 => {
+            world = bump_mapped();
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(13.0, 2.0, 3.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        12 => {
+            world = Ok(alpha_masked());
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(0.0, 2.0, 10.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 30.0;
+        }
+        13 => {
+            world = procedural_textures();
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.7, 0.8, 1.0)));
+            lookfrom = Vec3::new(0.0, 4.0, 12.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 35.0;
+        }
+        14 => {
+            world = Ok(environment_spheres());
+            background = match earth_environment() {
+                Ok(env) => Arc::new(env),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            lookfrom = Vec3::new(0.0, 2.5, 9.0);
+            lookat = Vec3::new(0.0, 1.0, 0.0);
+            vfov = 35.0;
+        }
+        15 => {
+            world = Ok(daylight_plaza());
+            // Mid-afternoon in early summer at about the latitude of Beijing.
+            background = Arc::new(SkyEnvironment::from_time(
+                160,
+                15.5,
+                40.0,
+                3.0,
+                Vec3::new(0.3, 0.3, 0.3),
+            ));
+            lookfrom = Vec3::new(-4.0, 3.0, 14.0);
+            lookat = Vec3::new(0.0, 1.5, 0.0);
+            vfov = 40.0;
+        }
+        16 => {
+            world = lamp_lit(&mut lights);
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
+            lookfrom = Vec3::new(0.0, 3.0, 12.0);
+            lookat = Vec3::new(0.0, 1.5, 0.0);
+            vfov = 35.0;
+        }
+        17 => {
+            world = cloud_and_fire();
+            background = Arc::new(SkyEnvironment::from_time(
+                250,
+                17.0,
+                40.0,
+                4.0,
+                Vec3::new(0.3, 0.3, 0.3),
+            ));
+            lookfrom = Vec3::new(0.0, 2.0, 10.0);
+            lookat = Vec3::new(0.0, 2.5, 0.0);
+            vfov = 45.0;
+        }
+        18 => {
+            world = foggy_spotlight(&mut lights);
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
+            lookfrom = Vec3::new(0.0, 2.5, 10.0);
+            lookat = Vec3::new(0.0, 1.2, 0.0);
+            vfov = 40.0;
+        }
+        19 => {
+            world = translucent_objects(&mut lights);
+            background = Arc::new(GradientEnvironment::new(
+                Vec3::new(0.02, 0.02, 0.03),
+                Vec3::new(0.05, 0.06, 0.1),
+            ));
+            lookfrom = Vec3::new(0.0, 3.0, 9.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+        }
+        20 => {
+            world = Ok(glass_caustics(&mut lights));
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
+            lookfrom = Vec3::new(0.0, 3.0, 9.0);
+            lookat = Vec3::new(0.0, 0.8, 0.0);
+            vfov = 40.0;
+        }
         _ => {
-            world = final_scene();
-            background = Vec3::new(0.0, 0.0, 0.0);
+            world = final_scene(&mut lights);
+            background = Arc::new(ConstantEnvironment::new(Vec3::new(0.0, 0.0, 0.0)));
             lookfrom = Vec3::new(478.0, 278.0, -600.0);
             lookat = Vec3::new(278.0, 278.0, 0.0);
             vfov = 40.0;
@@ -640,7 +1446,16 @@ fn main() {
           // }
     }
 
-    let cam: Camera = Camera::new(
+    // Fail before spawning any threads if the scene could not be built.
+    let world = match world {
+        Ok(world) => world,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut cam: Camera = Camera::new(
         aspect_ratio,
         &lookfrom,
         &lookat,
@@ -649,59 +1464,109 @@ fn main() {
         aperture,
         (dist_to_focus, time_start, time_end),
     );
+    cam.set_image_height(height);
+    // e.g. Arc::new(DebugIntegrator::new(DebugView::Normals)) to check geometry,
+    // Arc::new(Bdpt::new(&cam, 10)) for caustics and hidden lights (mono cameras only: its
+    // splats are projected onto `cam`, so leave `stereo` unset and chromatic aberration off), or
+    // Arc::new(PhotonMapping::new(PhotonSettings::default(), &lights, &world)) for caustics
+    let integrator: Arc<dyn Integrator> = Arc::new(PathTracer::new(PathSettings {
+        clamp: Some(20.0),
+        ..PathSettings::default()
+    }));
+    let cam: Arc<dyn Projection> = match stereo {
+        Some(rig) => Arc::new(StereoCamera::new(&cam, rig)),
+        None => Arc::new(cam),
+    };
 
-    let job_times = 50;
-    let mut handles = vec![];
-    //image
-
-    for c in 0..job_times {
-        let mut world_0 = world.clone();
-        let bar_0 = bar.clone();
-        let img_0 = img.clone();
-        let cam_0 = cam.clone();
-        let handle = thread::spawn(move || {
-            let height_start = height * c / job_times;
-            let height_end = height * (c + 1) / job_times;
-            for j in height_start..height_end {
-                for i in 0..width {
-                    let mut pixel_color: Vec3 = Vec3::new(0.0, 0.0, 0.0);
-                    for _s in 0..samples_per_pixel {
-                        let u = ((i as f64) + random_f64()) / (width as f64 - 1.0);
-                        let v = ((j as f64) + random_f64()) / (height as f64 - 1.0);
-                        let r = cam_0.get_ray(u, v);
-                        let tmp = ray_color(&r, &background, &mut world_0, max_depth); //[0-1]
-                        pixel_color.x += tmp.x;
-                        pixel_color.y += tmp.y;
-                        pixel_color.z += tmp.z;
-
-                        //ray_1.info();
+    let mut samples = samples_per_pixel;
+    if let Some(sppm) = &progressive {
+        bar.set_length(sppm.settings().passes as u64);
+        let image = sppm.render(
+            cam.as_ref(),
+            width,
+            height,
+            background.as_ref(),
+            &lights,
+            &world,
+            &mut |pass| bar.set_position(pass as u64),
+        );
+        *film.lock().unwrap() = image;
+        samples = 1;
+    } else {
+        let job_times = 50;
+        let mut handles = vec![];
+        //image
+
+        for c in 0..job_times {
+            let world_0 = world.clone();
+            let bar_0 = bar.clone();
+            let film_0 = film.clone();
+            let cam_0 = cam.clone();
+            let background_0 = background.clone();
+            let lights_0 = lights.clone();
+            let integrator_0 = integrator.clone();
+            let handle = thread::spawn(move || {
+                let height_start = height * c / job_times;
+                let height_end = height * (c + 1) / job_times;
+                let mut splats = Vec::new();
+                for j in height_start..height_end {
+                    for i in 0..width {
+                        let mut pixel_color: Vec3 = Vec3::new(0.0, 0.0, 0.0);
+                        for _s in 0..samples_per_pixel {
+                            let u = ((i as f64) + random_f64()) / width as f64;
+                            let v = ((j as f64) + random_f64()) / height as f64;
+                            let (r, weight) = cam_0.get_ray_weighted(u, v);
+                            let tmp = Vec3::elemul(
+                                &weight,
+                                &integrator_0.radiance_with_splats(
+                                    &r,
+                                    background_0.as_ref(),
+                                    &lights_0,
+                                    &world_0,
+                                    &mut splats,
+                                ),
+                            ); //[0-1]
+                            pixel_color.x += tmp.x;
+                            pixel_color.y += tmp.y;
+                            pixel_color.z += tmp.z;
+
+                            //ray_1.info();
+                        }
+                        //pixel_color.info();
+                        let mut film = film_0.lock().unwrap();
+                        film[j * width + i] += pixel_color;
+                        for (s, t, color) in splats.drain(..) {
+                            let x = ((s * width as f64) as usize).min(width - 1);
+                            let y = ((t * height as f64) as usize).min(height - 1);
+                            film[y * width + x] += color;
+                        }
+                        bar_0.inc(1);
                     }
-                    //pixel_color.info();
-                    write_color(
-                        &pixel_color,
-                        &mut img_0.lock().unwrap(),
-                        i,
-                        height - j - 1,
-                        samples_per_pixel,
-                    ); //[0-255*sample]
-                    bar_0.inc(1);
                 }
-            }
-        });
-        handles.push(handle);
-    }
+            });
+            handles.push(handle);
+        }
 
-    for handle in handles {
-        handle.join().unwrap();
+        for handle in handles {
+            handle.join().unwrap();
+        }
     }
 
     // Finish progress bar
     bar.finish();
 
+    let film = film.lock().unwrap();
+    let mut img = ImageBuffer::new(width.try_into().unwrap(), height.try_into().unwrap());
+    for j in 0..height {
+        for i in 0..width {
+            write_color(&film[j * width + i], &mut img, i, height - j - 1, samples);
+            //[0-255*sample]
+        }
+    }
+
     // Output image to file
     println!("Ouput image as \"{}\"\n Author: {}", path, AUTHOR);
-    let output_image =
-        image::DynamicImage::ImageRgb8(Mutex::into_inner(Arc::into_inner(img).unwrap()).unwrap());
+    let output_image = image::DynamicImage::ImageRgb8(img);
     let mut output_file = File::create(path).unwrap();
     match output_image.write_to(&mut output_file, image::ImageOutputFormat::Jpeg(quality)) {
         Ok(_) => {}
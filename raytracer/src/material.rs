@@ -1,16 +1,20 @@
 use crate::aabb::AAbb;
+pub use crate::color::blackbody;
+use crate::error::{Error, Result};
 pub use crate::hiitable::Hiitable;
 pub use crate::hiitable::HitRecord;
-pub use crate::moving_sphere::MovingSphere;
+pub use crate::onb::Onb;
+pub use crate::phase::{IsotropicPhase, PhaseFunction};
 use crate::random_f64;
 pub use crate::ray::Ray;
 pub use crate::rtweekend::degrees_to_radians;
 use crate::rtweekend::{fmax, fmin};
 pub use crate::texture::SolidColor;
-use crate::texture::Texture;
+use crate::texture::{TexCoord, Texture, MISSING_TEXTURE};
 pub use crate::vec3::Vec3;
 
 const INFINITY: f64 = f64::INFINITY;
+use std::f64::consts::PI;
 
 use std::sync::Arc;
 
@@ -23,7 +27,58 @@ pub trait Material: Send + Sync {
         scattered: &mut Ray,
     ) -> bool;
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3;
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3;
+
+    /// BSDF times `|cos|` for light arriving from `direction`, used when lights are sampled
+    /// directly. Materials that only scatter into delta directions leave this at zero.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// Solid-angle density with which `scatter` picks `direction`.
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    /// Whether `scatter` samples a delta distribution, so light sampling is pointless.
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+
+    /// Whether `direction`, as picked by `scatter`, came from a delta lobe that `eval` and
+    /// `scattering_pdf` leave out. Asked right after `scatter` with the same record; only
+    /// materials mixing delta and smooth lobes override this.
+    fn is_delta(&self, _r_in: &Ray, rec: &HitRecord, _direction: &Vec3) -> bool {
+        self.is_specular(rec)
+    }
+
+    /// Factor `scatter` and `eval` apply to radiance carried from `direction`, `1 / eta^2`
+    /// where light refracts into a denser side. Paths traced from lights carry importance,
+    /// which refraction does not scale, and divide this back out. `Dielectric` leaves radiance
+    /// unscaled in both directions and keeps the default.
+    fn radiance_scale(&self, _r_in: &Ray, _rec: &HitRecord, _direction: &Vec3) -> f64 {
+        1.0
+    }
+
+    /// Whether this is the phase function of a medium rather than a surface.
+    fn is_volume(&self) -> bool {
+        false
+    }
+
+    /// Absorption coefficient of whatever fills the inside of the surface. Rays reaching the
+    /// back face are dimmed by Beer-Lambert over the distance they travelled inside.
+    fn absorption(&self) -> Vec3 {
+        Vec3::zero()
+    }
+}
+
+/// `exp(-sigma * distance)` per channel.
+pub fn beer_lambert(sigma: &Vec3, distance: f64) -> Vec3 {
+    Vec3::new(
+        (-sigma.x() * distance).exp(),
+        (-sigma.y() * distance).exp(),
+        (-sigma.z() * distance).exp(),
+    )
 }
 
 pub struct Lambertian {
@@ -57,15 +112,30 @@ impl Material for Lambertian {
         *scattered = Ray::new(rec.point3, scatter_direction, _r_in.tm());
         *attenuation = self
             .albedo
-            .clone()
-            .unwrap()
-            .value(rec.u, rec.v, &rec.point3);
+            .as_ref()
+            .map_or(MISSING_TEXTURE, |a| a.eval(&rec.tex_coord()));
         true
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let albedo = self
+            .albedo
+            .as_ref()
+            .map_or(MISSING_TEXTURE, |a| a.eval(&rec.tex_coord()));
+        albedo * self.scattering_pdf(r_in, rec, direction)
+    }
+
+    fn scattering_pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        (rec.normal * direction.unit()).max(0.0) / PI
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
 }
 
 //metal
@@ -107,7 +177,7 @@ impl Material for Metal {
         (scattered.direc() * rec.normal) > 0.0
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
 }
@@ -115,15 +185,22 @@ impl Material for Metal {
 //dielectric
 pub struct Dielectric {
     ir: f64,
+    absorption: Vec3,
 }
 
 impl Dielectric {
     pub fn new(index_of_refraction: f64) -> Self {
         Self {
             ir: index_of_refraction,
+            absorption: Vec3::zero(),
         }
     }
 
+    /// Tints the interior, e.g. coloured glass or water. Shapes inside are not accounted for.
+    pub fn set_absorption(&mut self, absorption: Vec3) {
+        self.absorption = absorption;
+    }
+
     pub fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let mut r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
         r0 = r0 * r0;
@@ -169,23 +246,93 @@ impl Material for Dielectric {
         true
     }
 
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
     }
+
+    fn absorption(&self) -> Vec3 {
+        self.absorption
+    }
+}
+
+/// How `DiffLight::with_power` reads its power figure.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PowerUnit {
+    /// Radiant power, in the scene's radiance units times area.
+    Watts,
+    /// Luminous power; converted at the 683 lm/W peak efficacy.
+    Lumens,
 }
 
+/// A diffuse emitter. It lights only the front of its surface unless made two-sided; use
+/// `FlipFace` to turn a shape's front around.
 pub struct DiffLight {
     emit: Option<Arc<dyn Texture>>,
+    strength: Option<Arc<dyn Texture>>,
+    scale: f64,
+    two_sided: bool,
+    /// Total power and emitting area when the light was specified by power.
+    power: Option<(f64, f64)>,
 }
 
 impl DiffLight {
     pub fn new1(a: Option<Arc<dyn Texture>>) -> Self {
-        Self { emit: a }
+        Self {
+            emit: a,
+            strength: None,
+            scale: 1.0,
+            two_sided: false,
+            power: None,
+        }
     }
 
     pub fn new2(c: Vec3) -> Self {
-        Self {
-            emit: Some(Arc::new(SolidColor::new(c))),
+        DiffLight::new1(Some(Arc::new(SolidColor::new(c))))
+    }
+
+    /// Black-body coloured light at `kelvin`, with luminance `radiance`.
+    pub fn new_temperature(kelvin: f64, radiance: f64) -> Self {
+        DiffLight::new2(blackbody(kelvin) * radiance)
+    }
+
+    /// A light emitting `power` in total from a surface of the given `area`, whatever its
+    /// size, so a lamp keeps its output when it is modelled bigger or smaller. `area` is
+    /// usually the `Hiitable::area` of the shape the light goes on.
+    pub fn with_power(color: Vec3, power: f64, unit: PowerUnit, area: f64) -> Result<Self> {
+        if !(area > 0.0 && area.is_finite()) {
+            return Err(Error::InvalidArea(area));
+        }
+        let luminance = color.luminance();
+        let color = if luminance > 0.0 {
+            color / luminance
+        } else {
+            color
+        };
+        let watts = match unit {
+            PowerUnit::Watts => power,
+            PowerUnit::Lumens => power / 683.0,
+        };
+        let mut light = DiffLight::new2(color);
+        light.power = Some((watts, area));
+        light.update_scale();
+        Ok(light)
+    }
+
+    pub fn set_two_sided(&mut self, two_sided: bool) {
+        self.two_sided = two_sided;
+        self.update_scale();
+    }
+
+    /// Scales emission by the red channel of `strength`, e.g. a mask for a patterned lamp.
+    pub fn set_strength(&mut self, strength: Option<Arc<dyn Texture>>) {
+        self.strength = strength;
+    }
+
+    fn update_scale(&mut self) {
+        if let Some((watts, area)) = self.power {
+            // A Lambertian emitter sends pi * L * A into each hemisphere it lights.
+            let sides = if self.two_sided { 2.0 } else { 1.0 };
+            self.scale = watts / (PI * area * sides);
         }
     }
 }
@@ -201,8 +348,16 @@ impl Material for DiffLight {
         false
     }
 
-    fn emitted(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
-        self.emit.clone().unwrap().value(u, v, p)
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        if !self.two_sided && !rec.front_size {
+            return Vec3::zero();
+        }
+        let coord = rec.tex_coord();
+        let strength = self.strength.as_ref().map_or(1.0, |s| s.eval(&coord).x());
+        self.emit
+            .as_ref()
+            .map_or(MISSING_TEXTURE, |e| e.eval(&coord))
+            * (self.scale * strength)
     }
 }
 
@@ -223,7 +378,11 @@ impl Translate {
 impl Hiitable for Translate {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let moved_r = Ray::new(r.ori() - self.offset, r.direc, r.tm());
-        if !self.ptr.clone().unwrap().hit(&moved_r, t_min, t_max, rec) {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.hit(&moved_r, t_min, t_max, rec))
+        {
             return false;
         }
 
@@ -236,9 +395,8 @@ impl Hiitable for Translate {
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AAbb) -> bool {
         if !self
             .ptr
-            .clone()
-            .unwrap()
-            .bounding_box(time0, time1, output_box)
+            .as_ref()
+            .is_some_and(|p| p.bounding_box(time0, time1, output_box))
         {
             return false;
         }
@@ -248,6 +406,98 @@ impl Hiitable for Translate {
         );
         true
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr
+            .as_ref()
+            .map_or(0.0, |p| p.pdf_value(&(*o - self.offset), v))
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.ptr
+            .as_ref()
+            .map_or(Vec3::new(1.0, 0.0, 0.0), |p| p.random(&(*o - self.offset)))
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.sample_surface(point, normal))
+        {
+            return false;
+        }
+        *point += self.offset;
+        true
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.as_ref().map_or(0.0, |p| p.area())
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let moved_r = Ray::new(r.ori() - self.offset, r.direc, r.tm());
+        self.ptr
+            .as_ref()
+            .map_or(1.0, |p| p.transmittance(&moved_r, t_min, t_max))
+    }
+}
+
+/// Swaps which side of a shape is its front, e.g. to aim a one-sided ceiling light down.
+pub struct FlipFace {
+    ptr: Option<Arc<dyn Hiitable>>,
+}
+
+impl FlipFace {
+    pub fn new(p: Option<Arc<dyn Hiitable>>) -> Self {
+        Self { ptr: p }
+    }
+}
+
+impl Hiitable for FlipFace {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.hit(r, t_min, t_max, rec))
+        {
+            return false;
+        }
+        rec.front_size = !rec.front_size;
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AAbb) -> bool {
+        self.ptr
+            .as_ref()
+            .is_some_and(|p| p.bounding_box(time0, time1, output_box))
+    }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr.as_ref().map_or(0.0, |p| p.pdf_value(o, v))
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.ptr
+            .as_ref()
+            .map_or(Vec3::new(1.0, 0.0, 0.0), |p| p.random(o))
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.sample_surface(point, normal))
+        {
+            return false;
+        }
+        *normal = -*normal;
+        true
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.as_ref().map_or(0.0, |p| p.area())
+    }
 }
 
 pub struct Rotatey {
@@ -264,7 +514,9 @@ impl Rotatey {
         let radians = degrees_to_radians(angle);
         let sin_theta_0 = radians.sin();
         let cos_theta_0 = radians.cos();
-        let hashbox0 = p.clone().unwrap().bounding_box(0.0, 1.0, &mut bbox_0);
+        let hashbox0 = p
+            .as_ref()
+            .is_some_and(|p| p.bounding_box(0.0, 1.0, &mut bbox_0));
 
         let mut min = Vec3::new(INFINITY, INFINITY, INFINITY);
         let mut max = Vec3::new(-INFINITY, -INFINITY, -INFINITY);
@@ -305,6 +557,25 @@ impl Rotatey {
     }
 }
 
+impl Rotatey {
+    /// World space to the space of the wrapped object.
+    fn rotate(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+
+    fn rotate_back(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+}
+
 impl Hiitable for Rotatey {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut origin = r.ori();
@@ -317,7 +588,11 @@ impl Hiitable for Rotatey {
 
         let rotated_r = Ray::new(origin, direction, r.tm());
 
-        if !self.ptr.clone().unwrap().hit(&rotated_r, t_min, t_max, rec) {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.hit(&rotated_r, t_min, t_max, rec))
+        {
             return false;
         }
 
@@ -330,6 +605,8 @@ impl Hiitable for Rotatey {
         normal.x = self.cos_theta * rec.normal.x() + self.sin_theta * rec.normal.z();
         normal.z = -self.sin_theta * rec.normal.x() + self.cos_theta * rec.normal.z();
 
+        rec.dpdu = self.rotate_back(&rec.dpdu);
+        rec.dpdv = self.rotate_back(&rec.dpdv);
         rec.point3 = p;
         rec.set_front_size(&rotated_r, &normal);
 
@@ -340,27 +617,96 @@ impl Hiitable for Rotatey {
         *output_box = self.bbox.clone();
         self.hasbox
     }
+
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        self.ptr
+            .as_ref()
+            .map_or(0.0, |p| p.pdf_value(&self.rotate(o), &self.rotate(v)))
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.ptr.as_ref().map_or(Vec3::new(1.0, 0.0, 0.0), |p| {
+            self.rotate_back(&p.random(&self.rotate(o)))
+        })
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        if !self
+            .ptr
+            .as_ref()
+            .is_some_and(|p| p.sample_surface(point, normal))
+        {
+            return false;
+        }
+        *point = self.rotate_back(point);
+        *normal = self.rotate_back(normal);
+        true
+    }
+
+    fn area(&self) -> f64 {
+        self.ptr.as_ref().map_or(0.0, |p| p.area())
+    }
 }
 
+/// The scattering "material" of a medium: an albedo and a phase function, isotropic unless
+/// `set_phase` says otherwise.
 pub struct Isotropic {
     albedo: Option<Arc<dyn Texture>>,
+    emission: Option<Arc<dyn Texture>>,
+    phase: Option<Arc<dyn PhaseFunction>>,
 }
 
 impl Isotropic {
     pub fn new1(c: Vec3) -> Self {
         Self {
             albedo: Some(Arc::new(SolidColor::new(c))),
+            emission: None,
+            phase: Some(Arc::new(IsotropicPhase)),
         }
     }
 
     pub fn new2(a: Option<Arc<dyn Texture>>) -> Self {
-        Self { albedo: a }
+        Self {
+            albedo: a,
+            emission: None,
+            phase: Some(Arc::new(IsotropicPhase)),
+        }
+    }
+
+    /// Light added at every collision, looked up by position, e.g. for fire.
+    pub fn set_emission(&mut self, emission: Option<Arc<dyn Texture>>) {
+        self.emission = emission;
+    }
+
+    pub fn set_phase(&mut self, phase: Option<Arc<dyn PhaseFunction>>) {
+        self.phase = phase;
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Vec3 {
+        let coord = TexCoord {
+            width: 0.0,
+            ..rec.tex_coord()
+        };
+        self.albedo
+            .as_ref()
+            .map_or(MISSING_TEXTURE, |a| a.eval(&coord))
+    }
+
+    fn phase_pdf(&self, r_in: &Ray, direction: &Vec3) -> f64 {
+        let cos_theta = r_in.direc().unit() * direction.unit();
+        self.phase
+            .as_ref()
+            .map_or(1.0 / (4.0 * PI), |p| p.p(cos_theta))
     }
 }
 
 impl Material for Isotropic {
-    fn emitted(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
-        Vec3::new(0.0, 0.0, 0.0)
+    fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        let c = TexCoord {
+            tm: rec.tm,
+            ..TexCoord::new(u, v, p)
+        };
+        self.emission.as_ref().map_or(Vec3::zero(), |e| e.eval(&c))
     }
 
     fn scatter(
@@ -370,12 +716,36 @@ impl Material for Isotropic {
         attenuation: &mut Vec3,
         scattered: &mut Ray,
     ) -> bool {
-        *scattered = Ray::new(rec.point3, Vec3::random_in_unit_sphere(), r_in.tm());
-        *attenuation = self
-            .albedo
-            .clone()
-            .unwrap()
-            .value(rec.u, rec.v, &rec.point3);
+        let cos_theta = self
+            .phase
+            .as_ref()
+            .map_or(1.0 - 2.0 * random_f64(), |p| p.sample_cos_theta());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let direction = Onb::build_from_w(&r_in.direc()).local(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        *scattered = Ray::new(rec.point3, direction, r_in.tm());
+        // The phase function is sampled exactly, so only the albedo is left.
+        *attenuation = self.albedo(rec);
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        self.albedo(rec) * self.phase_pdf(r_in, direction)
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, _rec: &HitRecord, direction: &Vec3) -> f64 {
+        self.phase_pdf(r_in, direction)
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    fn is_volume(&self) -> bool {
         true
     }
 }
@@ -0,0 +1,518 @@
+pub use crate::hiitable::HitRecord;
+pub use crate::material::Material;
+pub use crate::onb::Onb;
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+
+const INFINITY: f64 = f64::INFINITY;
+
+/// GGX / Trowbridge-Reitz distribution with anisotropic roughness.
+/// All directions are in the local shading frame, with the normal along +z.
+#[derive(Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha_x: f64,
+    alpha_y: f64,
+}
+
+impl TrowbridgeReitz {
+    /// Takes perceptual roughness in `[0, 1]`; alpha is its square.
+    pub fn new(roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            alpha_x: (roughness_u * roughness_u).max(1e-4),
+            alpha_y: (roughness_v * roughness_v).max(1e-4),
+        }
+    }
+
+    pub fn from_alpha(alpha_x: f64, alpha_y: f64) -> Self {
+        Self {
+            alpha_x: alpha_x.max(1e-4),
+            alpha_y: alpha_y.max(1e-4),
+        }
+    }
+
+    pub fn d(&self, wh: &Vec3) -> f64 {
+        if wh.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = wh.x() / self.alpha_x;
+        let y = wh.y() / self.alpha_y;
+        let denom = x * x + y * y + wh.z() * wh.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * denom * denom)
+    }
+
+    pub fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z() * w.z();
+        if cos2 == 0.0 {
+            return INFINITY;
+        }
+        let x = self.alpha_x * w.x();
+        let y = self.alpha_y * w.y();
+        ((1.0 + (x * x + y * y) / cos2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Height-correlated Smith masking-shadowing.
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Samples a microfacet normal from the distribution of normals visible from `wo`
+    /// (Heitz 2018). `wo` must be in the upper hemisphere.
+    pub fn sample_wh(&self, wo: &Vec3) -> Vec3 {
+        let vh = Vec3::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit();
+        let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = Vec3::cross(&vh, &t1);
+
+        let r = random_f64().sqrt();
+        let phi = 2.0 * PI * random_f64();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        Vec3::new(
+            self.alpha_x * nh.x(),
+            self.alpha_y * nh.y(),
+            nh.z().max(1e-6),
+        )
+        .unit()
+    }
+
+    /// Density of `sample_wh` with respect to solid angle around `wh`.
+    pub fn pdf(&self, wo: &Vec3, wh: &Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * (*wo * *wh).max(0.0) * self.d(wh) / wo.z()
+    }
+}
+
+/// Unpolarised Fresnel reflectance at a dielectric boundary, `eta` = n_t / n_i.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+/// Fresnel reflectance of a conductor with complex IOR `eta + i k`, per channel.
+pub fn fresnel_conductor(cos_theta_i: f64, eta: &Vec3, k: &Vec3) -> Vec3 {
+    let fr = |eta: f64, k: f64| -> f64 {
+        let cos2 = cos_theta_i.clamp(0.0, 1.0).powi(2);
+        let sin2 = 1.0 - cos2;
+        let t0 = eta * eta - k * k - sin2;
+        let a2plusb2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2plusb2 + cos2;
+        let a = (0.5 * (a2plusb2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_theta_i.clamp(0.0, 1.0) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2plusb2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        (rp + rs) / 2.0
+    };
+    Vec3::new(fr(eta.x(), k.x()), fr(eta.y(), k.y()), fr(eta.z(), k.z()))
+}
+
+/// Rough metal with a complex index of refraction.
+pub struct Conductor {
+    distribution: TrowbridgeReitz,
+    eta: Vec3,
+    k: Vec3,
+}
+
+impl Conductor {
+    pub fn new(eta: Vec3, k: Vec3, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            distribution: TrowbridgeReitz::new(roughness_u, roughness_v),
+            eta,
+            k,
+        }
+    }
+
+    pub fn gold(roughness: f64) -> Self {
+        Conductor::new(
+            Vec3::new(0.143, 0.374, 1.442),
+            Vec3::new(3.983, 2.385, 1.603),
+            roughness,
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> Self {
+        Conductor::new(
+            Vec3::new(0.200, 0.924, 1.102),
+            Vec3::new(3.912, 2.452, 2.142),
+            roughness,
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> Self {
+        Conductor::new(
+            Vec3::new(0.155, 0.117, 0.138),
+            Vec3::new(4.828, 3.122, 2.147),
+            roughness,
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> Self {
+        Conductor::new(
+            Vec3::new(1.657, 0.880, 0.521),
+            Vec3::new(9.224, 6.270, 4.837),
+            roughness,
+            roughness,
+        )
+    }
+}
+
+impl Material for Conductor {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let wh = self.distribution.sample_wh(&wo);
+        let wi = Vec3::reflect(&(-wo), &wh);
+        if wi.z() <= 0.0 {
+            return false;
+        }
+
+        // f * cos / pdf for visible-normal sampling reduces to F * G2 / G1.
+        let f = fresnel_conductor(wo * wh, &self.eta, &self.k);
+        *attenuation = f * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo));
+        *scattered = Ray::new(rec.point3, uvw.local(&wi), r_in.tm());
+        true
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        let wi = uvw.to_local(&direction.unit());
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3::zero();
+        }
+        let wh = (wo + wi).unit();
+        let f = fresnel_conductor(wo * wh, &self.eta, &self.k);
+        f * (self.distribution.d(&wh) * self.distribution.g(&wo, &wi) / (4.0 * wo.z()))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        let wi = uvw.to_local(&direction.unit());
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let wh = (wo + wi).unit();
+        self.distribution.pdf(&wo, &wh) / (4.0 * (wo * wh))
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+}
+
+/// Rough glass: GGX reflection and refraction weighted by exact dielectric Fresnel.
+pub struct RoughDielectric {
+    distribution: TrowbridgeReitz,
+    ir: f64,
+    absorption: Vec3,
+}
+
+impl RoughDielectric {
+    pub fn new(index_of_refraction: f64, roughness_u: f64, roughness_v: f64) -> Self {
+        Self {
+            distribution: TrowbridgeReitz::new(roughness_u, roughness_v),
+            ir: index_of_refraction,
+            absorption: Vec3::zero(),
+        }
+    }
+
+    pub fn set_absorption(&mut self, absorption: Vec3) {
+        self.absorption = absorption;
+    }
+
+    /// `wo` and `direction` in the shading frame, and the relative index across the surface
+    /// from the side `wo` is on.
+    fn local(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Option<(Vec3, Vec3, f64)> {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        let wi = uvw.to_local(&direction.unit());
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return None;
+        }
+        let eta = if rec.front_size {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        Some((wo, wi, eta))
+    }
+}
+
+/// The microfacet normal that refracts `wo` into `wi`, if both lie on opposite sides of it.
+fn transmission_half(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+    let mut wh = -(*wo + *wi * eta).unit();
+    if wh.z() < 0.0 {
+        wh = -wh;
+    }
+    if *wo * wh <= 0.0 || *wi * wh >= 0.0 {
+        return None;
+    }
+    Some(wh)
+}
+
+impl Material for RoughDielectric {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        // rec.normal always faces the incoming ray, so wo lives in the upper hemisphere.
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let eta = if rec.front_size {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+
+        let wh = self.distribution.sample_wh(&wo);
+        let cos_o = wo * wh;
+        let f = fresnel_dielectric(cos_o, eta);
+
+        let (wi, scale) = if random_f64() < f {
+            let wi = Vec3::reflect(&(-wo), &wh);
+            if wi.z() <= 0.0 {
+                return false;
+            }
+            (wi, 1.0)
+        } else {
+            let wi = Vec3::refract(&(-wo), &wh, 1.0 / eta).unit();
+            if wi.z() >= 0.0 {
+                return false;
+            }
+            (wi, 1.0 / (eta * eta))
+        };
+
+        // The Fresnel term cancels against the reflect/refract selection probability, and
+        // what is left of f * cos / pdf is G2 / G1 for either lobe, times the squeeze of
+        // radiance into the denser side.
+        *attenuation =
+            Vec3::ones() * (self.distribution.g(&wo, &wi) / self.distribution.g1(&wo) * scale);
+        *scattered = Ray::new(rec.point3, uvw.local(&wi), r_in.tm());
+        true
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let (wo, wi, eta) = match self.local(r_in, rec, direction) {
+            Some(local) => local,
+            None => return Vec3::zero(),
+        };
+        if wi.z() > 0.0 {
+            let wh = (wo + wi).unit();
+            let f = fresnel_dielectric(wo * wh, eta);
+            let d = self.distribution.d(&wh);
+            return Vec3::ones() * (f * d * self.distribution.g(&wo, &wi) / (4.0 * wo.z()));
+        }
+        let wh = match transmission_half(&wo, &wi, eta) {
+            Some(wh) => wh,
+            None => return Vec3::zero(),
+        };
+        // The eta^2 of the change of variables cancels the 1 / eta^2 scaling of radiance.
+        let denom = wo * wh + eta * (wi * wh);
+        let f = fresnel_dielectric(wo * wh, eta);
+        let d = self.distribution.d(&wh);
+        Vec3::ones()
+            * ((1.0 - f) * d * self.distribution.g(&wo, &wi) * (wi * wh).abs() * (wo * wh)
+                / (wo.z() * denom * denom))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let (wo, wi, eta) = match self.local(r_in, rec, direction) {
+            Some(local) => local,
+            None => return 0.0,
+        };
+        if wi.z() > 0.0 {
+            let wh = (wo + wi).unit();
+            let f = fresnel_dielectric(wo * wh, eta);
+            return f * self.distribution.pdf(&wo, &wh) / (4.0 * (wo * wh));
+        }
+        let wh = match transmission_half(&wo, &wi, eta) {
+            Some(wh) => wh,
+            None => return 0.0,
+        };
+        // Density of the half vector, times the Jacobian of refracting through it.
+        let denom = wo * wh + eta * (wi * wh);
+        let f = fresnel_dielectric(wo * wh, eta);
+        (1.0 - f) * self.distribution.pdf(&wo, &wh) * eta * eta * (wi * wh).abs() / (denom * denom)
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    fn radiance_scale(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        match self.local(r_in, rec, direction) {
+            Some((_, wi, eta)) if wi.z() < 0.0 => 1.0 / (eta * eta),
+            _ => 1.0,
+        }
+    }
+
+    fn absorption(&self) -> Vec3 {
+        self.absorption
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_ggx_projected_area_is_one() {
+        // Integral of D(h) cos(theta_h) over the hemisphere, by uniform sampling.
+        let dist = TrowbridgeReitz::new(0.5, 0.3);
+        let n = 200_000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            let z = random_f64();
+            let r = (1.0 - z * z).sqrt();
+            let phi = 2.0 * PI * random_f64();
+            let wh = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+            sum += dist.d(&wh) * z * 2.0 * PI;
+        }
+        assert!((sum / n as f64 - 1.0).abs() < 0.05);
+    }
+    #[test]
+    fn test_vndf_samples_face_viewer() {
+        let dist = TrowbridgeReitz::new(0.8, 0.8);
+        let wo = Vec3::new(0.6, 0.0, 0.8);
+        for _ in 0..1000 {
+            let wh = dist.sample_wh(&wo);
+            assert!(wh.z() > 0.0);
+            assert!(wo * wh >= -1e-9);
+        }
+    }
+    #[test]
+    fn test_rough_dielectric_white_furnace() {
+        let glass = RoughDielectric::new(1.5, 0.3, 0.3);
+        let r_in = Ray::new(Vec3::new(-0.6, 0.0, 0.8), Vec3::new(0.6, 0.0, -0.8), 0.0);
+        let n = 200_000;
+        for front in [true, false] {
+            let mut rec = HitRecord::new();
+            rec.normal = Vec3::new(0.0, 0.0, 1.0);
+            rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+            rec.front_size = front;
+
+            // Nothing is absorbed, so all the importance that goes missing is the light
+            // single-scattering GGX cannot account for. Radiance is also rescaled by refraction.
+            let mut albedo = 0.0;
+            let mut importance = 0.0;
+            for _ in 0..n {
+                let mut attenuation = Vec3::zero();
+                let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+                if !glass.scatter(&r_in, &mut rec, &mut attenuation, &mut scattered) {
+                    continue;
+                }
+                let direction = scattered.direc();
+                let expected = glass.eval(&r_in, &rec, &direction).x()
+                    / glass.scattering_pdf(&r_in, &rec, &direction);
+                assert!((attenuation.x() - expected).abs() < 1e-6 * expected.max(1.0));
+                albedo += attenuation.x();
+                importance += attenuation.x() / glass.radiance_scale(&r_in, &rec, &direction);
+            }
+            let albedo = albedo / n as f64;
+            let importance = importance / n as f64;
+            assert!(importance > 0.97 && importance < 1.01, "{}", importance);
+
+            // The same energy by integrating eval over the whole sphere on a fine grid.
+            let (steps_theta, steps_phi) = (1000, 400);
+            let (d_theta, d_phi) = (PI / steps_theta as f64, 2.0 * PI / steps_phi as f64);
+            let mut integral = 0.0;
+            for i in 0..steps_theta {
+                let theta = (i as f64 + 0.5) * d_theta;
+                for j in 0..steps_phi {
+                    let phi = (j as f64 + 0.5) * d_phi;
+                    let direction = Vec3::new(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    integral +=
+                        glass.eval(&r_in, &rec, &direction).x() * theta.sin() * d_theta * d_phi;
+                }
+            }
+            assert!((integral - albedo).abs() < 0.01, "{} {}", integral, albedo);
+        }
+    }
+    #[test]
+    fn test_rough_dielectric_reciprocity() {
+        // f(wo, wi) / n_o^2 = f(wi, wo) / n_i^2 across the interface.
+        let glass = RoughDielectric::new(1.5, 0.4, 0.4);
+        let outside = Vec3::new(0.6, 0.0, 0.8);
+        let inside = Vec3::new(-0.3, 0.2, -(1.0_f64 - 0.13).sqrt());
+
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_size = true;
+        let from_outside = Ray::new(outside, -outside, 0.0);
+        let f_out = glass.eval(&from_outside, &rec, &inside).x() / inside.z().abs();
+
+        rec.normal = Vec3::new(0.0, 0.0, -1.0);
+        rec.front_size = false;
+        let from_inside = Ray::new(inside, -inside, 0.0);
+        let f_in = glass.eval(&from_inside, &rec, &outside).x() / outside.z();
+
+        assert!(f_out > 0.0);
+        assert!((f_out * 1.5 * 1.5 - f_in).abs() < 1e-9 * f_in);
+        assert_eq!(
+            glass.radiance_scale(&from_inside, &rec, &outside),
+            1.5 * 1.5
+        );
+    }
+    #[test]
+    fn test_fresnel_limits() {
+        assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-9);
+        assert_eq!(fresnel_dielectric(0.1, 1.0 / 1.5), 1.0);
+        let f = fresnel_conductor(1.0, &Vec3::new(0.2, 0.9, 1.1), &Vec3::new(3.9, 2.5, 2.1));
+        assert!(f.x() > f.z());
+    }
+}
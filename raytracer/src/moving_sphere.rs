@@ -1,6 +1,7 @@
 pub use crate::aabb::AAbb;
 pub use crate::hiitable::{Hiitable, HitRecord};
 pub use crate::material::Material;
+use crate::object::Sphere;
 pub use crate::ray::Ray;
 pub use crate::vec3::Vec3;
 use std::sync::Arc;
@@ -75,6 +76,8 @@ impl Hiitable for MovingSphere {
         rec.point3 = r.at(rec.t);
         let outward_normal = (rec.point3 - self.center(r.tm())) / self.radius;
         rec.set_front_size(r, &outward_normal);
+        Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        Sphere::get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
         rec.mat = self.mat.clone();
 
         true
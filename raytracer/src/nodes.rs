@@ -0,0 +1,423 @@
+pub use crate::texture::{TexCoord, Texture, MISSING_TEXTURE};
+pub use crate::vec3::Vec3;
+
+use crate::rtweekend::degrees_to_radians;
+use std::sync::Arc;
+
+fn input(t: &Option<Arc<dyn Texture>>, c: &TexCoord) -> Vec3 {
+    t.as_ref().map_or(MISSING_TEXTURE, |t| t.eval(c))
+}
+
+/// `a + b`, per channel.
+pub struct AddTexture {
+    a: Option<Arc<dyn Texture>>,
+    b: Option<Arc<dyn Texture>>,
+}
+
+impl AddTexture {
+    pub fn new(a: Option<Arc<dyn Texture>>, b: Option<Arc<dyn Texture>>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for AddTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        input(&self.a, c) + input(&self.b, c)
+    }
+}
+
+/// `a * b`, per channel.
+pub struct MultiplyTexture {
+    a: Option<Arc<dyn Texture>>,
+    b: Option<Arc<dyn Texture>>,
+}
+
+impl MultiplyTexture {
+    pub fn new(a: Option<Arc<dyn Texture>>, b: Option<Arc<dyn Texture>>) -> Self {
+        Self { a, b }
+    }
+}
+
+impl Texture for MultiplyTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        Vec3::elemul(&input(&self.a, c), &input(&self.b, c))
+    }
+}
+
+/// Blends `a` into `b` by the `x` channel of `t`, unclamped.
+pub struct LerpTexture {
+    a: Option<Arc<dyn Texture>>,
+    b: Option<Arc<dyn Texture>>,
+    t: Option<Arc<dyn Texture>>,
+}
+
+impl LerpTexture {
+    pub fn new(
+        a: Option<Arc<dyn Texture>>,
+        b: Option<Arc<dyn Texture>>,
+        t: Option<Arc<dyn Texture>>,
+    ) -> Self {
+        Self { a, b, t }
+    }
+}
+
+impl Texture for LerpTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let t = input(&self.t, c).x();
+        input(&self.a, c) * (1.0 - t) + input(&self.b, c) * t
+    }
+}
+
+/// `1 - input`.
+pub struct InvertTexture {
+    input: Option<Arc<dyn Texture>>,
+}
+
+impl InvertTexture {
+    pub fn new(input: Option<Arc<dyn Texture>>) -> Self {
+        Self { input }
+    }
+}
+
+impl Texture for InvertTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        Vec3::ones() - input(&self.input, c)
+    }
+}
+
+/// Clamps every channel into `[min, max]`.
+pub struct ClampTexture {
+    input: Option<Arc<dyn Texture>>,
+    min: f64,
+    max: f64,
+}
+
+impl ClampTexture {
+    pub fn new(input: Option<Arc<dyn Texture>>, min: f64, max: f64) -> Self {
+        Self { input, min, max }
+    }
+}
+
+impl Texture for ClampTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let x = input(&self.input, c);
+        Vec3::new(
+            x.x().clamp(self.min, self.max),
+            x.y().clamp(self.min, self.max),
+            x.z().clamp(self.min, self.max),
+        )
+    }
+}
+
+/// Maps `[from_min, from_max]` linearly onto `[to_min, to_max]` and clamps, a contrast
+/// curve for masks. For colour output use `ColorRamp`.
+pub struct RemapTexture {
+    input: Option<Arc<dyn Texture>>,
+    from: (f64, f64),
+    to: (f64, f64),
+}
+
+impl RemapTexture {
+    pub fn new(input: Option<Arc<dyn Texture>>, from: (f64, f64), to: (f64, f64)) -> Self {
+        Self { input, from, to }
+    }
+
+    fn remap(&self, x: f64) -> f64 {
+        let span = self.from.1 - self.from.0;
+        let t = if span == 0.0 {
+            if x < self.from.0 {
+                0.0
+            } else {
+                1.0
+            }
+        } else {
+            ((x - self.from.0) / span).clamp(0.0, 1.0)
+        };
+        self.to.0 + (self.to.1 - self.to.0) * t
+    }
+}
+
+impl Texture for RemapTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let x = input(&self.input, c);
+        Vec3::new(self.remap(x.x()), self.remap(x.y()), self.remap(x.z()))
+    }
+}
+
+/// Rotates hue by `hue` turns and scales saturation and value, in HSV.
+pub struct HueSaturationTexture {
+    input: Option<Arc<dyn Texture>>,
+    hue: f64,
+    saturation: f64,
+    value: f64,
+}
+
+impl HueSaturationTexture {
+    pub fn new(input: Option<Arc<dyn Texture>>, hue: f64, saturation: f64, value: f64) -> Self {
+        Self {
+            input,
+            hue,
+            saturation,
+            value,
+        }
+    }
+}
+
+impl Texture for HueSaturationTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let (h, s, v) = rgb_to_hsv(&input(&self.input, c));
+        hsv_to_rgb(
+            (h + self.hue).rem_euclid(1.0),
+            (s * self.saturation).clamp(0.0, 1.0),
+            v * self.value,
+        )
+    }
+}
+
+/// Hue in turns, saturation and value.
+pub fn rgb_to_hsv(c: &Vec3) -> (f64, f64, f64) {
+    let max = c.x().max(c.y()).max(c.z());
+    let min = c.x().min(c.y()).min(c.z());
+    let delta = max - min;
+    if max <= 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+    if delta == 0.0 {
+        return (0.0, 0.0, max);
+    }
+    let h = if max == c.x() {
+        (c.y() - c.z()) / delta
+    } else if max == c.y() {
+        2.0 + (c.z() - c.x()) / delta
+    } else {
+        4.0 + (c.x() - c.y()) / delta
+    };
+    ((h / 6.0).rem_euclid(1.0), delta / max, max)
+}
+
+pub fn hsv_to_rgb(h: f64, s: f64, v: f64) -> Vec3 {
+    let h6 = h.rem_euclid(1.0) * 6.0;
+    let f = h6 - h6.floor();
+    let p = v * (1.0 - s);
+    let q = v * (1.0 - s * f);
+    let t = v * (1.0 - s * (1.0 - f));
+    match h6.floor() as i32 {
+        0 => Vec3::new(v, t, p),
+        1 => Vec3::new(q, v, p),
+        2 => Vec3::new(p, v, t),
+        3 => Vec3::new(p, q, v),
+        4 => Vec3::new(t, p, v),
+        _ => Vec3::new(v, p, q),
+    }
+}
+
+/// Scales, rotates (degrees) and offsets the uv coordinates its input sees.
+pub struct UvTransformTexture {
+    input: Option<Arc<dyn Texture>>,
+    scale: (f64, f64),
+    offset: (f64, f64),
+    rotation: f64,
+}
+
+impl UvTransformTexture {
+    pub fn new(
+        input: Option<Arc<dyn Texture>>,
+        scale: (f64, f64),
+        offset: (f64, f64),
+        rotation: f64,
+    ) -> Self {
+        Self {
+            input,
+            scale,
+            offset,
+            rotation,
+        }
+    }
+}
+
+impl Texture for UvTransformTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let (sin, cos) = degrees_to_radians(self.rotation).sin_cos();
+        let u = c.u * self.scale.0;
+        let v = c.v * self.scale.1;
+        let moved = TexCoord {
+            u: cos * u - sin * v + self.offset.0,
+            v: sin * u + cos * v + self.offset.1,
+            width: c.width * self.scale.0.abs().max(self.scale.1.abs()),
+            ..*c
+        };
+        input(&self.input, &moved)
+    }
+}
+
+/// Projects textures along the three world axes and blends them by the surface normal, for
+/// objects without usable uvs. The projected coordinate is fed in as uv and as `p`.
+pub struct TriplanarTexture {
+    x: Option<Arc<dyn Texture>>,
+    y: Option<Arc<dyn Texture>>,
+    z: Option<Arc<dyn Texture>>,
+    scale: f64,
+    sharpness: f64,
+}
+
+impl TriplanarTexture {
+    /// The same texture on all three planes.
+    pub fn new1(texture: Option<Arc<dyn Texture>>, scale: f64, sharpness: f64) -> Self {
+        Self {
+            x: texture.clone(),
+            y: texture.clone(),
+            z: texture,
+            scale,
+            sharpness,
+        }
+    }
+
+    pub fn new3(
+        x: Option<Arc<dyn Texture>>,
+        y: Option<Arc<dyn Texture>>,
+        z: Option<Arc<dyn Texture>>,
+        scale: f64,
+        sharpness: f64,
+    ) -> Self {
+        Self {
+            x,
+            y,
+            z,
+            scale,
+            sharpness,
+        }
+    }
+}
+
+impl Texture for TriplanarTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let n = c.normal;
+        let mut w = [
+            n.x().abs().powf(self.sharpness),
+            n.y().abs().powf(self.sharpness),
+            n.z().abs().powf(self.sharpness),
+        ];
+        let total = w[0] + w[1] + w[2];
+        if total <= 0.0 {
+            w = [0.0, 1.0, 0.0];
+        } else {
+            w.iter_mut().for_each(|w| *w /= total);
+        }
+
+        let q = c.p * self.scale;
+        let planes = [
+            (&self.x, q.z(), q.y()),
+            (&self.y, q.x(), q.z()),
+            (&self.z, q.x(), q.y()),
+        ];
+        let mut sum = Vec3::zero();
+        for ((texture, u, v), weight) in planes.into_iter().zip(w) {
+            if weight > 0.0 {
+                let projected = TexCoord {
+                    u,
+                    v,
+                    p: q,
+                    width: c.width * self.scale,
+                    ..*c
+                };
+                sum += input(texture, &projected) * weight;
+            }
+        }
+        sum
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Channel {
+    R,
+    G,
+    B,
+    /// Rec. 709 luminance.
+    Luminance,
+}
+
+/// One channel of its input, as gray.
+pub struct ChannelTexture {
+    input: Option<Arc<dyn Texture>>,
+    channel: Channel,
+}
+
+impl ChannelTexture {
+    pub fn new(input: Option<Arc<dyn Texture>>, channel: Channel) -> Self {
+        Self { input, channel }
+    }
+}
+
+impl Texture for ChannelTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let x = input(&self.input, c);
+        let gray = match self.channel {
+            Channel::R => x.x(),
+            Channel::G => x.y(),
+            Channel::B => x.z(),
+            Channel::Luminance => x.luminance(),
+        };
+        Vec3::ones() * gray
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texture::SolidColor;
+
+    #[test]
+    fn test_hsv_round_trip() {
+        let c = Vec3::new(0.8, 0.3, 0.1);
+        let (h, s, v) = rgb_to_hsv(&c);
+        let back = hsv_to_rgb(h, s, v);
+        assert!((back - c).length() < 1e-12);
+    }
+
+    #[test]
+    fn test_remap_contrast() {
+        let gray: Option<Arc<dyn Texture>> = Some(Arc::new(SolidColor::new(Vec3::ones() * 0.5)));
+        let remap = RemapTexture::new(gray, (0.25, 0.75), (0.0, 2.0));
+        assert!((remap.value(0.0, 0.0, &Vec3::zero()).x() - 1.0).abs() < 1e-12);
+    }
+}
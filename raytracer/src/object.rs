@@ -1,7 +1,9 @@
 pub use crate::aabb::AAbb;
 pub use crate::hiitable::{Hiitable, HitRecord};
 pub use crate::material::Material;
+pub use crate::onb::Onb;
 pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
 pub use crate::vec3::Vec3;
 
 use std::f64::consts::PI;
@@ -30,6 +32,23 @@ impl Sphere {
         *u = phi / (2.0 * PI);
         *v = theta / PI;
     }
+
+    /// Tangents of the `get_sphere_uv` parametrisation at unit normal `p`.
+    pub fn get_sphere_tangents(p: &Vec3, radius: f64, dpdu: &mut Vec3, dpdv: &mut Vec3) {
+        *dpdu = Vec3::new(p.z(), 0.0, -p.x()) * (2.0 * PI * radius);
+        let sin_theta = (1.0 - p.y() * p.y()).max(0.0).sqrt();
+        if sin_theta < 1e-8 {
+            // At the poles u is degenerate; any tangent perpendicular to the axis will do.
+            *dpdu = Vec3::new(2.0 * PI * radius, 0.0, 0.0);
+            *dpdv = Vec3::new(0.0, 0.0, PI * radius);
+            return;
+        }
+        *dpdv = Vec3::new(
+            -p.y() * p.x() / sin_theta,
+            sin_theta,
+            -p.y() * p.z() / sin_theta,
+        ) * (PI * radius);
+    }
 }
 
 impl Hiitable for Sphere {
@@ -58,11 +77,54 @@ impl Hiitable for Sphere {
         let outward_normal = (rec.point3 - self.center) / self.radius;
         rec.set_front_size(r, &outward_normal);
         Sphere::get_sphere_uv(&outward_normal, &mut rec.u, &mut rec.v);
+        Sphere::get_sphere_tangents(&outward_normal, self.radius, &mut rec.dpdu, &mut rec.dpdv);
         rec.mat = self.mat.clone();
 
         true
     }
 
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
+        let mut rec = HitRecord::new();
+        if !self.hit(&Ray::new(*o, *v, 0.0), 0.001, f64::INFINITY, &mut rec) {
+            return 0.0;
+        }
+        let distance_squared = (self.center - *o).squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return 0.0;
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    /// Samples the cone of directions the sphere subtends from `o`.
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let direction = self.center - *o;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            return direction;
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let z = 1.0 + random_f64() * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * random_f64();
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        Onb::build_from_w(&direction).local(&Vec3::new(
+            phi.cos() * sin_theta,
+            phi.sin() * sin_theta,
+            z,
+        ))
+    }
+
+    fn sample_surface(&self, point: &mut Vec3, normal: &mut Vec3) -> bool {
+        *normal = Vec3::random_unit_vector();
+        *point = self.center + *normal * self.radius;
+        true
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
+    }
+
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AAbb) -> bool {
         *output_box = AAbb::new(
             self.center - Vec3::new(self.radius, self.radius, self.radius),
@@ -0,0 +1,60 @@
+pub use crate::vec3::Vec3;
+
+/// Orthonormal basis; `w` is the surface normal when used as a shading frame.
+#[derive(Clone, Copy)]
+pub struct Onb {
+    axis: [Vec3; 3],
+}
+
+impl Onb {
+    pub fn build_from_w(n: &Vec3) -> Self {
+        let w_0 = n.unit();
+        let a = if w_0.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let v_0 = Vec3::cross(&w_0, &a).unit();
+        let u_0 = Vec3::cross(&w_0, &v_0);
+        Self {
+            axis: [u_0, v_0, w_0],
+        }
+    }
+
+    /// Frame around `n` whose `u` axis follows the tangent `t` (e.g. `HitRecord::dpdu`),
+    /// so anisotropic materials line up with the surface parametrisation.
+    pub fn build_from_w_u(n: &Vec3, t: &Vec3) -> Self {
+        let w_0 = n.unit();
+        let t_0 = *t - w_0 * (*t * w_0);
+        if t_0.squared_length() < 1e-12 {
+            return Onb::build_from_w(n);
+        }
+        let u_0 = t_0.unit();
+        let v_0 = Vec3::cross(&w_0, &u_0);
+        Self {
+            axis: [u_0, v_0, w_0],
+        }
+    }
+
+    pub fn u(&self) -> Vec3 {
+        self.axis[0]
+    }
+
+    pub fn v(&self) -> Vec3 {
+        self.axis[1]
+    }
+
+    pub fn w(&self) -> Vec3 {
+        self.axis[2]
+    }
+
+    /// Local coordinates to world space.
+    pub fn local(&self, a: &Vec3) -> Vec3 {
+        self.u() * a.x() + self.v() * a.y() + self.w() * a.z()
+    }
+
+    /// World space to local coordinates.
+    pub fn to_local(&self, a: &Vec3) -> Vec3 {
+        Vec3::new(*a * self.u(), *a * self.v(), *a * self.w())
+    }
+}
@@ -0,0 +1,386 @@
+pub use crate::environment::Environment;
+pub use crate::hiitable::HitRecord;
+pub use crate::hittable_list::HittableList;
+pub use crate::integrator::Integrator;
+pub use crate::light::{LightList, LightSample};
+pub use crate::material::{beer_lambert, Material};
+pub use crate::ray::Ray;
+use crate::rtweekend::{random_f64, random_f64_1};
+pub use crate::vec3::Vec3;
+
+const INFINITY: f64 = f64::INFINITY;
+
+/// Bounce limits and firefly control for `PathTracer`. A path ends at `max_depth` bounces
+/// in total or when it exceeds the limit for the kind of bounce it just took.
+#[derive(Clone, Copy, Debug)]
+pub struct PathSettings {
+    pub max_depth: u32,
+    /// Bounces always traced; Russian roulette may end paths after this many.
+    pub min_depth: u32,
+    pub diffuse_depth: u32,
+    /// Mirror-like reflection off a surface.
+    pub specular_depth: u32,
+    /// Refraction through a surface, such as glass.
+    pub transmission_depth: u32,
+    /// Scattering inside participating media.
+    pub volume_depth: u32,
+    /// Largest channel value allowed for light reached after the first bounce. Clamping
+    /// removes fireflies at the cost of some energy.
+    pub clamp: Option<f64>,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        Self {
+            max_depth: 50,
+            min_depth: 3,
+            diffuse_depth: 50,
+            specular_depth: 50,
+            transmission_depth: 50,
+            volume_depth: 50,
+            clamp: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Bounce {
+    Diffuse,
+    Specular,
+    Transmission,
+    Volume,
+}
+
+/// Unidirectional path tracing with next-event estimation towards the environment and the
+/// light list, combined with BSDF sampling by multiple importance sampling.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PathTracer {
+    settings: PathSettings,
+}
+
+impl PathTracer {
+    pub fn new(settings: PathSettings) -> Self {
+        Self { settings }
+    }
+
+    pub fn settings(&self) -> &PathSettings {
+        &self.settings
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        let settings = &self.settings;
+        let mut radiance = Vec3::zero();
+        let mut throughput = Vec3::ones();
+        let mut ray = r.clone();
+        // Solid-angle pdf of the BSDF sample that produced `ray`, or `None` when it came from
+        // the camera or a specular bounce and so could not have been found by light sampling.
+        let mut bsdf_pdf: Option<f64> = None;
+        let mut counts = [0; 4];
+
+        for depth in 0..settings.max_depth {
+            let mut rec: HitRecord = HitRecord::new();
+            if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+                // The environment was also sampled directly at the previous bounce; weight the
+                // two strategies against each other.
+                let mut env = background.radiance(&ray.direc());
+                if let Some(pdf) = bsdf_pdf {
+                    env *= power_heuristic(pdf, background.pdf(&ray.direc()));
+                }
+                radiance +=
+                    clamp_contribution(Vec3::elemul(&throughput, &env), depth, settings.clamp);
+                break;
+            }
+            let mat = match rec.mat.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            rec.footprint = ray.footprint(rec.t);
+            rec.tm = ray.tm();
+            // Leaving an absorbing interior: everything seen through this hit crossed it.
+            if !rec.front_size {
+                let interior = beer_lambert(&mat.absorption(), rec.t * ray.direc().length());
+                throughput = Vec3::elemul(&throughput, &interior);
+            }
+
+            let mut emitted = mat.emitted(&ray, &rec, rec.u, rec.v, &rec.point3);
+            if let Some(pdf) = bsdf_pdf {
+                // Registered emitters were also reached by a shadow ray from the previous hit.
+                emitted *= power_heuristic(pdf, lights.pdf(&ray.ori(), &ray.direc()));
+            }
+            let specular = mat.is_specular(&rec);
+            if !specular {
+                emitted += sample_environment(&ray, &rec, mat.as_ref(), background, world);
+                emitted += sample_lights(&ray, &rec, mat.as_ref(), lights, world);
+            }
+            radiance +=
+                clamp_contribution(Vec3::elemul(&throughput, &emitted), depth, settings.clamp);
+
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), random_f64_1(0.0, 1.0));
+            if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+            // The coat of a coated material mirrors into a delta lobe that eval leaves out.
+            let delta = specular || mat.is_delta(&ray, &rec, &scattered.direc());
+
+            let bounce = if mat.is_volume() {
+                Bounce::Volume
+            } else if scattered.direc() * rec.normal < 0.0 {
+                // The normal faces the incoming ray, so this went through the surface.
+                Bounce::Transmission
+            } else if delta {
+                Bounce::Specular
+            } else {
+                Bounce::Diffuse
+            };
+            let limit = match bounce {
+                Bounce::Diffuse => settings.diffuse_depth,
+                Bounce::Specular => settings.specular_depth,
+                Bounce::Transmission => settings.transmission_depth,
+                Bounce::Volume => settings.volume_depth,
+            };
+            counts[bounce as usize] += 1;
+            if counts[bounce as usize] > limit || depth + 1 == settings.max_depth {
+                // Cut off by a fixed rule rather than at random: the light-sampled half of the
+                // direct light here was taken, so take the BSDF-sampled half too.
+                if !specular {
+                    let pdf = (!delta).then(|| mat.scattering_pdf(&ray, &rec, &scattered.direc()));
+                    let found = bsdf_sampled_light(&scattered, pdf, background, lights, world);
+                    let found = Vec3::elemul(&throughput, &Vec3::elemul(&attenuation, &found));
+                    radiance += clamp_contribution(found, depth + 1, settings.clamp);
+                }
+                break;
+            }
+
+            bsdf_pdf = if delta {
+                None
+            } else {
+                Some(mat.scattering_pdf(&ray, &rec, &scattered.direc()))
+            };
+            throughput = Vec3::elemul(&throughput, &attenuation);
+            if depth + 1 >= settings.min_depth {
+                let survive = throughput
+                    .x()
+                    .max(throughput.y())
+                    .max(throughput.z())
+                    .min(0.95);
+                if random_f64() >= survive {
+                    break;
+                }
+                throughput = throughput / survive;
+            }
+
+            // Carry the ray cone on so textures seen in reflections are filtered as well.
+            scattered.width = rec.footprint;
+            scattered.spread = ray.spread;
+            ray = scattered;
+        }
+        radiance
+    }
+}
+
+fn clamp_contribution(contribution: Vec3, depth: u32, clamp: Option<f64>) -> Vec3 {
+    match clamp {
+        Some(limit) if depth > 0 => {
+            let peak = contribution.x().max(contribution.y()).max(contribution.z());
+            if peak > limit {
+                contribution * (limit / peak)
+            } else {
+                contribution
+            }
+        }
+        _ => contribution,
+    }
+}
+
+/// One shadow ray towards the environment, weighted against BSDF sampling.
+pub fn sample_environment(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &dyn Material,
+    background: &dyn Environment,
+    world: &HittableList,
+) -> Vec3 {
+    let (direction, light_pdf) = background.sample();
+    if light_pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let f = mat.eval(r, rec, &direction);
+    if f.near_zero() {
+        return Vec3::zero();
+    }
+    let shadow = Ray::new(rec.point3, direction, r.tm());
+    let transmittance = world.transmittance(&shadow, 0.001, INFINITY);
+    if transmittance <= 0.0 {
+        return Vec3::zero();
+    }
+    let weight = power_heuristic(light_pdf, mat.scattering_pdf(r, rec, &direction));
+    Vec3::elemul(&f, &background.radiance(&direction)) * (transmittance * weight / light_pdf)
+}
+
+/// One shadow ray towards a light from the scene's light list.
+pub fn sample_lights(
+    r: &Ray,
+    rec: &HitRecord,
+    mat: &dyn Material,
+    lights: &LightList,
+    world: &HittableList,
+) -> Vec3 {
+    let mut sample = LightSample::new();
+    if !lights.sample(&rec.point3, r.tm(), &mut sample) || sample.pdf <= 0.0 {
+        return Vec3::zero();
+    }
+    let f = mat.eval(r, rec, &sample.direction);
+    if f.near_zero() {
+        return Vec3::zero();
+    }
+    let shadow = Ray::new(rec.point3, sample.direction, r.tm());
+    let transmittance = world.transmittance(&shadow, 0.001, sample.distance - 0.001);
+    if transmittance <= 0.0 {
+        return Vec3::zero();
+    }
+    let weight = if sample.delta {
+        1.0
+    } else {
+        power_heuristic(sample.pdf, mat.scattering_pdf(r, rec, &sample.direction))
+    };
+    Vec3::elemul(&f, &sample.radiance) * (transmittance * weight / sample.pdf)
+}
+
+/// All the direct light at a hit: shadow rays towards the environment and the light list,
+/// and one BSDF sample for the other half of their MIS estimates. The BSDF-sampled ray ends
+/// at whatever it hits, so callers must not count emission found beyond this hit again.
+pub fn estimate_direct(
+    r: &Ray,
+    rec: &mut HitRecord,
+    mat: &dyn Material,
+    background: &dyn Environment,
+    lights: &LightList,
+    world: &HittableList,
+) -> Vec3 {
+    let mut direct = sample_environment(r, rec, mat, background, world);
+    direct += sample_lights(r, rec, mat, lights, world);
+    let mut attenuation = Vec3::zero();
+    let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), r.tm());
+    if mat.scatter(r, rec, &mut attenuation, &mut scattered) {
+        let pdf = (!mat.is_delta(r, rec, &scattered.direc()))
+            .then(|| mat.scattering_pdf(r, rec, &scattered.direc()));
+        let found = bsdf_sampled_light(&scattered, pdf, background, lights, world);
+        direct += Vec3::elemul(&attenuation, &found);
+    }
+    direct
+}
+
+/// Emission or environment light reached by a BSDF-sampled ray with density `pdf`, weighted
+/// against the shadow rays that could also have found it. A `None` pdf marks a delta sample,
+/// which no shadow ray can find.
+fn bsdf_sampled_light(
+    scattered: &Ray,
+    pdf: Option<f64>,
+    background: &dyn Environment,
+    lights: &LightList,
+    world: &HittableList,
+) -> Vec3 {
+    let mut next = HitRecord::new();
+    if !world.hit(scattered, 0.001, INFINITY, &mut next) {
+        return background.radiance(&scattered.direc())
+            * pdf.map_or(1.0, |pdf| {
+                power_heuristic(pdf, background.pdf(&scattered.direc()))
+            });
+    }
+    next.mat.clone().map_or(Vec3::zero(), |m| {
+        m.emitted(scattered, &next, next.u, next.v, &next.point3)
+    }) * pdf.map_or(1.0, |pdf| {
+        power_heuristic(pdf, lights.pdf(&scattered.ori(), &scattered.direc()))
+    })
+}
+
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment::ConstantEnvironment;
+    use crate::material::Lambertian;
+    use crate::object::Sphere;
+
+    use std::sync::Arc;
+
+    fn furnace(settings: PathSettings) -> f64 {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Sphere::new(
+            &Vec3::zero(),
+            1.0,
+            Some(Arc::new(Lambertian::new1(&Vec3::ones()))),
+        ))));
+        let background = ConstantEnvironment::new(Vec3::ones() * 0.5);
+        let tracer = PathTracer::new(settings);
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let n = 20000;
+        (0..n)
+            .map(|_| {
+                tracer
+                    .radiance(&r, &background, &LightList::new(), &world)
+                    .x()
+            })
+            .sum::<f64>()
+            / n as f64
+    }
+
+    #[test]
+    fn test_white_sphere_vanishes_in_a_furnace() {
+        // A white convex object under uniform light reflects exactly that light, whether or
+        // not Russian roulette cuts paths short.
+        let mean = furnace(PathSettings::default());
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+        let mean = furnace(PathSettings {
+            min_depth: 0,
+            ..PathSettings::default()
+        });
+        assert!((mean - 0.5).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn test_depth_limits_and_clamping() {
+        let direct_only = furnace(PathSettings {
+            diffuse_depth: 0,
+            ..PathSettings::default()
+        });
+        // Every direction off a convex sphere reaches the furnace, so direct light alone is
+        // all of it; both halves of the MIS estimate must still be taken at the cut-off.
+        assert!((direct_only - 0.5).abs() < 0.01, "{}", direct_only);
+        let one_bounce = furnace(PathSettings {
+            max_depth: 1,
+            ..PathSettings::default()
+        });
+        assert!((one_bounce - 0.5).abs() < 0.01, "{}", one_bounce);
+        let clamped = furnace(PathSettings {
+            clamp: Some(0.1),
+            ..PathSettings::default()
+        });
+        assert!(clamped < 0.5);
+        assert_eq!(
+            clamp_contribution(Vec3::new(4.0, 2.0, 0.0), 1, Some(1.0)).x(),
+            1.0
+        );
+        assert_eq!(
+            clamp_contribution(Vec3::new(4.0, 2.0, 0.0), 0, Some(1.0)).x(),
+            4.0
+        );
+    }
+}
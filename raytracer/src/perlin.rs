@@ -1,13 +1,28 @@
-use crate::rtweekend::random_i32_1;
+use crate::simplex::grad4_dot;
 pub use crate::vec3::Vec3;
 
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::vec::Vec;
+
+/// Interpolant used between lattice points.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Fade {
+    /// `3t^2 - 2t^3`, the original Perlin curve.
+    Cubic,
+    /// `6t^5 - 15t^4 + 10t^3`, Perlin's improved noise; continuous second derivative, so
+    /// no grid artefacts in bump maps.
+    Quintic,
+}
+
 pub struct Perlin {
     // point_count: i32,
     ranvec: Vec<Vec3>,
     perm_x: Vec<i32>,
     perm_y: Vec<i32>,
     perm_z: Vec<i32>,
+    perm_w: Vec<i32>,
+    fade: Fade,
 }
 
 impl Default for Perlin {
@@ -20,28 +35,54 @@ impl Perlin {
     pub fn new_0() -> Self {
         Self {
             //point_count: 256,
-            ranvec: vec![Vec3::new(0.0, 0.0, 0.0); 0],
+            ranvec: Vec::new(),
             perm_x: vec![0; 0],
             perm_y: vec![0; 0],
             perm_z: vec![0; 0],
+            perm_w: vec![0; 0],
+            fade: Fade::Cubic,
         }
     }
+
+    /// A different lattice on every run.
     pub fn new() -> Self {
+        Perlin::from_rng(&mut rand::thread_rng())
+    }
+
+    /// The same lattice for the same seed, so renders are reproducible.
+    pub fn with_seed(seed: u64) -> Self {
+        Perlin::from_rng(&mut StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(rng: &mut impl Rng) -> Self {
         let point_count_0 = 256;
-        let mut ranvec_0: Vec<Vec3> = vec![Vec3::new(0.0, 0.0, 0.0); point_count_0 as usize];
-        for i in 0..point_count_0 {
-            ranvec_0[i as usize] = Vec3::unit(&Vec3::random_vec3_2(-1.0, 1.0))
+        let mut ranvec_0: Vec<Vec3> = Vec::with_capacity(point_count_0);
+        for _ in 0..point_count_0 {
+            ranvec_0.push(
+                Vec3::new(
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                    rng.gen_range(-1.0..1.0),
+                )
+                .unit(),
+            );
         }
         Self {
             //point_count: point_count_0,
             ranvec: ranvec_0,
-            perm_x: Perlin::perlin_generate_perm(),
-            perm_y: Perlin::perlin_generate_perm(),
-            perm_z: Perlin::perlin_generate_perm(),
+            perm_x: Perlin::perlin_generate_perm(rng),
+            perm_y: Perlin::perlin_generate_perm(rng),
+            perm_z: Perlin::perlin_generate_perm(rng),
+            perm_w: Perlin::perlin_generate_perm(rng),
+            fade: Fade::Cubic,
         }
     }
 
-    pub fn perlin_generate_perm() -> Vec<i32> {
+    pub fn set_fade(&mut self, fade: Fade) {
+        self.fade = fade;
+    }
+
+    pub fn perlin_generate_perm(rng: &mut impl Rng) -> Vec<i32> {
         let point_count_0 = 256;
         let mut p = vec![0; point_count_0 as usize];
 
@@ -49,14 +90,14 @@ impl Perlin {
             p[i as usize] = i;
         }
 
-        Perlin::permute(&mut p, point_count_0);
+        Perlin::permute(&mut p, point_count_0, rng);
 
         p
     }
 
-    pub fn permute(p: &mut [i32], n: i32) {
-        for i in 1..n {
-            let target = random_i32_1(0, n - i) as usize;
+    pub fn permute(p: &mut [i32], n: i32, rng: &mut impl Rng) {
+        for i in (1..n).rev() {
+            let target = rng.gen_range(0..=i) as usize;
             p.swap(i as usize, target);
         }
     }
@@ -83,14 +124,38 @@ impl Perlin {
             }
         }
 
-        Perlin::trilinear_interp(c, u, v, w)
+        Perlin::trilinear_interp(c, u, v, w, self.fade)
+    }
+
+    /// Gradient noise over space and a fourth axis `w`, usually time, so patterns can evolve
+    /// instead of sliding through space.
+    pub fn noise4(&self, p: &Vec3, w: f64) -> f64 {
+        let x = [p.x(), p.y(), p.z(), w];
+        let cell = x.map(f64::floor);
+        let f: [f64; 4] = std::array::from_fn(|a| x[a] - cell[a]);
+        let perms = [&self.perm_x, &self.perm_y, &self.perm_z, &self.perm_w];
+        let mut accum = 0.0;
+        for corner in 0..16 {
+            let mut h = 0;
+            let mut weight = 1.0;
+            let mut d = [0.0; 4];
+            for a in 0..4 {
+                let b = (corner >> a) & 1;
+                h ^= perms[a][(cell[a] as i32 + b) as usize & 255] as usize;
+                d[a] = f[a] - b as f64;
+                let t = fade_curve(f[a], self.fade);
+                weight *= if b == 1 { t } else { 1.0 - t };
+            }
+            accum += weight * grad4_dot(h, &d);
+        }
+        accum
     }
 
-    pub fn trilinear_interp(c: [[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    pub fn trilinear_interp(c: [[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64, fade: Fade) -> f64 {
         let mut accum = 0.0;
-        let uu = u * u * (3.0 - 2.0 * u);
-        let vv = v * v * (3.0 - 2.0 * v);
-        let ww = w * w * (3.0 - 2.0 * w);
+        let uu = fade_curve(u, fade);
+        let vv = fade_curve(v, fade);
+        let ww = fade_curve(w, fade);
         let vec_loop = [0, 1];
         for i in vec_loop {
             for j in vec_loop {
@@ -121,4 +186,109 @@ impl Perlin {
 
         accum.abs()
     }
+
+    /// Fractional Brownian motion: `octaves` layers of noise, each `lacunarity` times finer
+    /// and `gain` times weaker than the last, normalised to roughly `[-1, 1]`.
+    pub fn fbm(&self, p: &Vec3, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        self.octaves(p, octaves, lacunarity, gain, |n| n)
+    }
+
+    /// Like `fbm` but summing absolute values, in `[0, 1]`.
+    pub fn turbulence(&self, p: &Vec3, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        self.octaves(p, octaves, lacunarity, gain, f64::abs)
+    }
+
+    /// `fbm` of `noise4`; `w` is scaled with each octave like the other axes.
+    pub fn fbm4(&self, p: &Vec3, w: f64, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        self.octaves4(p, w, octaves, lacunarity, gain, |n| n)
+    }
+
+    /// `turbulence` of `noise4`.
+    pub fn turbulence4(&self, p: &Vec3, w: f64, octaves: i32, lacunarity: f64, gain: f64) -> f64 {
+        self.octaves4(p, w, octaves, lacunarity, gain, f64::abs)
+    }
+
+    fn octaves(
+        &self,
+        p: &Vec3,
+        octaves: i32,
+        lacunarity: f64,
+        gain: f64,
+        shape: impl Fn(f64) -> f64,
+    ) -> f64 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut temp_p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves.max(1) {
+            accum += weight * shape(self.noise(&temp_p));
+            total += weight;
+            weight *= gain;
+            temp_p *= lacunarity;
+        }
+        accum / total
+    }
+
+    fn octaves4(
+        &self,
+        p: &Vec3,
+        w: f64,
+        octaves: i32,
+        lacunarity: f64,
+        gain: f64,
+        shape: impl Fn(f64) -> f64,
+    ) -> f64 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut temp_p = *p;
+        let mut temp_w = w;
+        let mut weight = 1.0;
+        for _ in 0..octaves.max(1) {
+            accum += weight * shape(self.noise4(&temp_p, temp_w));
+            total += weight;
+            weight *= gain;
+            temp_p *= lacunarity;
+            temp_w *= lacunarity;
+        }
+        accum / total
+    }
+}
+
+fn fade_curve(t: f64, fade: Fade) -> f64 {
+    match fade {
+        Fade::Cubic => t * t * (3.0 - 2.0 * t),
+        Fade::Quintic => t * t * t * (t * (t * 6.0 - 15.0) + 10.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_noise_repeats() {
+        let a = Perlin::with_seed(42);
+        let b = Perlin::with_seed(42);
+        let c = Perlin::with_seed(43);
+        let p = Vec3::new(1.3, -0.7, 2.1);
+        assert_eq!(a.noise(&p), b.noise(&p));
+        assert_ne!(a.noise(&p), c.noise(&p));
+    }
+
+    #[test]
+    fn test_noise4_evolves_smoothly() {
+        let perlin = Perlin::with_seed(7);
+        let p = Vec3::new(1.3, -0.7, 2.1);
+        let mut changed = false;
+        for i in 0..200 {
+            let w = i as f64 * 0.05;
+            let n = perlin.noise4(&p, w);
+            assert!(n.abs() <= 2.0, "{}", n);
+            assert!((perlin.noise4(&p, w + 1e-4) - n).abs() < 1e-2);
+            changed |= (n - perlin.noise4(&p, 0.0)).abs() > 0.1;
+        }
+        assert!(changed);
+        // Gradient noise vanishes on the lattice.
+        assert_eq!(perlin.noise4(&Vec3::new(1.0, 2.0, -3.0), 4.0), 0.0);
+    }
 }
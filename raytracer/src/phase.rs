@@ -0,0 +1,152 @@
+use crate::rtweekend::random_f64;
+
+use std::f64::consts::PI;
+
+/// How a medium redirects light at a scattering event. Everything here depends only on the
+/// cosine of the angle between the incoming travel direction and the scattered direction,
+/// so positive cosines mean forward scattering.
+pub trait PhaseFunction: Send + Sync {
+    /// Density per steradian; integrates to one over the sphere.
+    fn p(&self, cos_theta: f64) -> f64;
+
+    /// Draws a cosine distributed exactly as `p`, so the sample weight is one.
+    fn sample_cos_theta(&self) -> f64;
+}
+
+pub struct IsotropicPhase;
+
+impl PhaseFunction for IsotropicPhase {
+    fn p(&self, _cos_theta: f64) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        1.0 - 2.0 * random_f64()
+    }
+}
+
+/// Henyey-Greenstein: `g` in (-1, 1) is the mean cosine, from back (-) through isotropic (0)
+/// to forward (+) scattering.
+pub struct HenyeyGreenstein {
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(g: f64) -> Self {
+        Self {
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+}
+
+impl PhaseFunction for HenyeyGreenstein {
+    fn p(&self, cos_theta: f64) -> f64 {
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        let g = self.g;
+        let xi = random_f64();
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * xi;
+        }
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+/// A blend of two Henyey-Greenstein lobes, typically a strong forward lobe and a weak
+/// backward one as in clouds. `weight` goes to the first lobe.
+pub struct DoubleHenyeyGreenstein {
+    first: HenyeyGreenstein,
+    second: HenyeyGreenstein,
+    weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(g1: f64, g2: f64, weight: f64) -> Self {
+        Self {
+            first: HenyeyGreenstein::new(g1),
+            second: HenyeyGreenstein::new(g2),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl PhaseFunction for DoubleHenyeyGreenstein {
+    fn p(&self, cos_theta: f64) -> f64 {
+        self.weight * self.first.p(cos_theta) + (1.0 - self.weight) * self.second.p(cos_theta)
+    }
+
+    fn sample_cos_theta(&self) -> f64 {
+        if random_f64() < self.weight {
+            self.first.sample_cos_theta()
+        } else {
+            self.second.sample_cos_theta()
+        }
+    }
+}
+
+/// Scattering by particles much smaller than the wavelength, such as air molecules.
+pub struct Rayleigh;
+
+impl PhaseFunction for Rayleigh {
+    fn p(&self, cos_theta: f64) -> f64 {
+        3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+    }
+
+    // Inverts the CDF (mu^3 + 3 mu + 4) / 8 with Cardano's formula.
+    fn sample_cos_theta(&self) -> f64 {
+        let q = 4.0 - 8.0 * random_f64();
+        let root = (q * q / 4.0 + 1.0).sqrt();
+        ((-q / 2.0 + root).cbrt() + (-q / 2.0 - root).cbrt()).clamp(-1.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phases() -> Vec<(Box<dyn PhaseFunction>, f64)> {
+        vec![
+            (Box::new(IsotropicPhase), 0.0),
+            (Box::new(HenyeyGreenstein::new(0.7)), 0.7),
+            (Box::new(HenyeyGreenstein::new(-0.4)), -0.4),
+            (
+                Box::new(DoubleHenyeyGreenstein::new(0.8, -0.3, 0.75)),
+                0.525,
+            ),
+            (Box::new(Rayleigh), 0.0),
+        ]
+    }
+
+    #[test]
+    fn test_phase_functions_are_normalised() {
+        let n = 100_000;
+        for (phase, _) in phases() {
+            let integral: f64 = (0..n)
+                .map(|i| phase.p(-1.0 + 2.0 * (i as f64 + 0.5) / n as f64))
+                .sum::<f64>()
+                * 2.0
+                * PI
+                * (2.0 / n as f64);
+            assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn test_samples_have_the_expected_mean_cosine() {
+        let n = 50_000;
+        for (phase, g) in phases() {
+            let mean = (0..n).map(|_| phase.sample_cos_theta()).sum::<f64>() / n as f64;
+            assert!((mean - g).abs() < 0.02, "{} vs {}", mean, g);
+        }
+        // Rayleigh puts equal weight forward and back, but more there than sideways.
+        let n_side = (0..n)
+            .filter(|_| Rayleigh.sample_cos_theta().abs() < 0.5)
+            .count();
+        assert!((n_side as f64 / n as f64 - 0.40625).abs() < 0.02);
+    }
+}
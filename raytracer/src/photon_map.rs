@@ -0,0 +1,610 @@
+pub use crate::environment::Environment;
+pub use crate::hiitable::HitRecord;
+pub use crate::hittable_list::HittableList;
+pub use crate::integrator::Integrator;
+pub use crate::light::{EmissionSample, LightList};
+pub use crate::material::{beer_lambert, Material};
+pub use crate::path_tracer::{estimate_direct, power_heuristic, sample_environment, sample_lights};
+pub use crate::ray::Ray;
+use crate::rtweekend::{random_f64, random_f64_1};
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+use std::thread;
+
+const INFINITY: f64 = f64::INFINITY;
+
+/// Light left on a surface by a photon: where it landed, the unit direction it was
+/// travelling in, and the power it carried.
+#[derive(Clone, Copy, Debug)]
+pub struct Photon {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub power: Vec3,
+}
+
+/// Photons in a balanced kd-tree, for finding those near a point. The tree is implicit: the
+/// median of each range splits it, along the axis stored for that photon.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> Self {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+        Self { photons, axes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Calls `f` with every photon closer than `radius` to `point`.
+    pub fn within(&self, point: &Vec3, radius: f64, f: &mut dyn FnMut(&Photon)) {
+        search(&self.photons, &self.axes, point, radius, f);
+    }
+
+    /// Power of the photons within `radius` of the hit, reflected along `r` back towards where
+    /// it came from, and how many photons were found. Divided by the disc area this is the
+    /// radiance estimate.
+    pub fn reflected(
+        &self,
+        r: &Ray,
+        rec: &HitRecord,
+        mat: &dyn Material,
+        radius: f64,
+    ) -> (Vec3, usize) {
+        let mut power = Vec3::zero();
+        let mut found = 0;
+        self.within(&rec.point3, radius, &mut |photon| {
+            found += 1;
+            let cos_theta = (rec.normal.unit() * photon.direction).abs();
+            if cos_theta < 1e-6 {
+                return;
+            }
+            // `eval` includes the cosine term, which the photon density already accounts for.
+            let f = mat.eval(r, rec, &-photon.direction) / cos_theta;
+            power += Vec3::elemul(&f, &photon.power);
+        });
+        (power, found)
+    }
+}
+
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let mut lo = photons[0].position;
+    let mut hi = lo;
+    for photon in photons.iter() {
+        let p = photon.position;
+        lo = Vec3::new(lo.x().min(p.x()), lo.y().min(p.y()), lo.z().min(p.z()));
+        hi = Vec3::new(hi.x().max(p.x()), hi.y().max(p.y()), hi.z().max(p.z()));
+    }
+    let extent = hi - lo;
+    let axis = if extent.x() >= extent.y() && extent.x() >= extent.z() {
+        0
+    } else if extent.y() >= extent.z() {
+        1
+    } else {
+        2
+    };
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+    axes[mid] = axis as u8;
+    let (left, right) = photons.split_at_mut(mid);
+    let (left_axes, right_axes) = axes.split_at_mut(mid);
+    build(left, left_axes);
+    build(&mut right[1..], &mut right_axes[1..]);
+}
+
+fn search(photons: &[Photon], axes: &[u8], point: &Vec3, radius: f64, f: &mut dyn FnMut(&Photon)) {
+    if photons.is_empty() {
+        return;
+    }
+    let mid = photons.len() / 2;
+    let photon = &photons[mid];
+    if (photon.position - *point).squared_length() <= radius * radius {
+        f(photon);
+    }
+    if photons.len() == 1 {
+        return;
+    }
+    let axis = axes[mid] as usize;
+    let d = point[axis] - photon.position[axis];
+    if d <= radius {
+        search(&photons[..mid], &axes[..mid], point, radius, f);
+    }
+    if d >= -radius {
+        search(&photons[mid + 1..], &axes[mid + 1..], point, radius, f);
+    }
+}
+
+/// Which landings of a photon are kept.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum PhotonKind {
+    /// Every non-specular surface it reaches.
+    All,
+    /// All but the first, which is direct light.
+    Indirect,
+    /// Only the first, and only after mirrors and glass.
+    Caustic,
+}
+
+/// Traces `count` photons from the lights in parallel. Their power is divided by `count`, so
+/// the photons together carry the power of one emission.
+pub(crate) fn shoot_photons(
+    count: usize,
+    kind: PhotonKind,
+    max_depth: u32,
+    lights: &LightList,
+    world: &HittableList,
+) -> Vec<Photon> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let scale = 1.0 / count.max(1) as f64;
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads)
+            .map(|t| {
+                let share = count * (t + 1) / threads - count * t / threads;
+                scope.spawn(move || {
+                    let mut photons = Vec::new();
+                    for _ in 0..share {
+                        trace_photon(kind, max_depth, scale, lights, world, &mut photons);
+                    }
+                    photons
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+fn trace_photon(
+    kind: PhotonKind,
+    max_depth: u32,
+    scale: f64,
+    lights: &LightList,
+    world: &HittableList,
+    photons: &mut Vec<Photon>,
+) {
+    let mut sample = EmissionSample::new();
+    if lights.sample_emission(random_f64(), &mut sample).is_none()
+        || sample.pdf_pos <= 0.0
+        || sample.pdf_dir <= 0.0
+    {
+        return;
+    }
+    let cos_theta = if sample.normal.near_zero() {
+        1.0
+    } else {
+        (sample.normal * sample.ray.direc()).abs()
+    };
+    let mut power = sample.radiance * (cos_theta * scale / (sample.pdf_pos * sample.pdf_dir));
+    let mut ray = sample.ray;
+    let mut specular_path = true;
+    for depth in 0..max_depth {
+        let mut rec = HitRecord::new();
+        if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+            return;
+        }
+        let mat = match rec.mat.clone() {
+            Some(mat) => mat,
+            None => return,
+        };
+        rec.tm = ray.tm();
+        if !rec.front_size {
+            let interior = beer_lambert(&mat.absorption(), rec.t * ray.direc().length());
+            power = Vec3::elemul(&power, &interior);
+        }
+        let specular = mat.is_specular(&rec);
+        if !specular && !mat.is_volume() {
+            let keep = match kind {
+                PhotonKind::All => true,
+                PhotonKind::Indirect => depth > 0,
+                PhotonKind::Caustic => depth > 0 && specular_path,
+            };
+            if keep {
+                photons.push(Photon {
+                    position: rec.point3,
+                    direction: ray.direc().unit(),
+                    power,
+                });
+            }
+        }
+
+        let mut attenuation = Vec3::zero();
+        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), ray.tm());
+        if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+            return;
+        }
+        specular_path &=
+            !mat.is_volume() && (specular || mat.is_delta(&ray, &rec, &scattered.direc()));
+        if kind == PhotonKind::Caustic && !specular_path {
+            return;
+        }
+        // Photons carry importance, which refraction does not scale.
+        power =
+            Vec3::elemul(&power, &attenuation) / mat.radiance_scale(&ray, &rec, &scattered.direc());
+        if depth >= 3 {
+            let survive = attenuation
+                .x()
+                .max(attenuation.y())
+                .max(attenuation.z())
+                .min(0.95);
+            if random_f64() >= survive {
+                return;
+            }
+            power = power / survive;
+        }
+        ray = scattered;
+    }
+}
+
+/// Where a camera ray first reaches a surface that photons can be gathered on, with the
+/// throughput of the mirrors, glass and media it went through to get there.
+pub(crate) struct VisiblePoint {
+    pub ray: Ray,
+    pub rec: HitRecord,
+    pub mat: Arc<dyn Material>,
+    pub beta: Vec3,
+}
+
+/// Follows `r` to its visible point, adding the light seen on the way and the direct light
+/// at the point itself to `radiance`. Returns `None` if the ray escapes or is absorbed first.
+pub(crate) fn visible_point(
+    r: &Ray,
+    background: &dyn Environment,
+    lights: &LightList,
+    world: &HittableList,
+    max_depth: u32,
+    radiance: &mut Vec3,
+) -> Option<VisiblePoint> {
+    let mut beta = Vec3::ones();
+    let mut ray = r.clone();
+    let mut bsdf_pdf: Option<f64> = None;
+    for _ in 0..max_depth {
+        let mut rec = HitRecord::new();
+        if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+            let mut env = background.radiance(&ray.direc());
+            if let Some(pdf) = bsdf_pdf {
+                env *= power_heuristic(pdf, background.pdf(&ray.direc()));
+            }
+            *radiance += Vec3::elemul(&beta, &env);
+            return None;
+        }
+        let mat = rec.mat.clone()?;
+        rec.footprint = ray.footprint(rec.t);
+        rec.tm = ray.tm();
+        if !rec.front_size {
+            let interior = beer_lambert(&mat.absorption(), rec.t * ray.direc().length());
+            beta = Vec3::elemul(&beta, &interior);
+        }
+
+        let mut emitted = mat.emitted(&ray, &rec, rec.u, rec.v, &rec.point3);
+        if let Some(pdf) = bsdf_pdf {
+            emitted *= power_heuristic(pdf, lights.pdf(&ray.ori(), &ray.direc()));
+        }
+        if !mat.is_volume() && !mat.is_specular(&rec) {
+            emitted += estimate_direct(&ray, &mut rec, mat.as_ref(), background, lights, world);
+            *radiance += Vec3::elemul(&beta, &emitted);
+            return Some(VisiblePoint {
+                ray,
+                rec,
+                mat,
+                beta,
+            });
+        }
+        // Photons are not kept in media, so light scattered there is found by shadow rays.
+        if mat.is_volume() {
+            emitted += sample_environment(&ray, &rec, mat.as_ref(), background, world);
+            emitted += sample_lights(&ray, &rec, mat.as_ref(), lights, world);
+        }
+        *radiance += Vec3::elemul(&beta, &emitted);
+
+        let mut attenuation = Vec3::zero();
+        let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), random_f64_1(0.0, 1.0));
+        if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+            return None;
+        }
+        bsdf_pdf = if mat.is_volume() {
+            Some(mat.scattering_pdf(&ray, &rec, &scattered.direc()))
+        } else {
+            None
+        };
+        beta = Vec3::elemul(&beta, &attenuation);
+        scattered.width = rec.footprint;
+        scattered.spread = ray.spread;
+        ray = scattered;
+    }
+    None
+}
+
+/// Photon counts, gather radii in scene units, and the bounce limit shared by photons and
+/// camera rays.
+#[derive(Clone, Copy, Debug)]
+pub struct PhotonSettings {
+    pub global_photons: usize,
+    pub caustic_photons: usize,
+    pub global_radius: f64,
+    pub caustic_radius: f64,
+    pub max_depth: u32,
+}
+
+impl Default for PhotonSettings {
+    fn default() -> Self {
+        Self {
+            global_photons: 200_000,
+            caustic_photons: 500_000,
+            global_radius: 0.3,
+            caustic_radius: 0.05,
+            max_depth: 10,
+        }
+    }
+}
+
+/// Two-pass photon mapping. Direct light is sampled, caustics are read from a dense map of
+/// photons that came through mirrors and glass, and the rest of the indirect light from a
+/// global map one final-gather bounce away. Photons only leave the light list; light from the
+/// environment reaches diffuse surfaces directly or through a specular final gather.
+pub struct PhotonMapping {
+    settings: PhotonSettings,
+    global: PhotonMap,
+    caustic: PhotonMap,
+}
+
+impl PhotonMapping {
+    pub fn new(settings: PhotonSettings, lights: &LightList, world: &HittableList) -> Self {
+        let global = shoot_photons(
+            settings.global_photons,
+            PhotonKind::All,
+            settings.max_depth,
+            lights,
+            world,
+        );
+        let caustic = shoot_photons(
+            settings.caustic_photons,
+            PhotonKind::Caustic,
+            settings.max_depth,
+            lights,
+            world,
+        );
+        Self {
+            settings,
+            global: PhotonMap::new(global),
+            caustic: PhotonMap::new(caustic),
+        }
+    }
+
+    pub fn settings(&self) -> &PhotonSettings {
+        &self.settings
+    }
+
+    /// Indirect light at a visible point that did not come straight through mirrors and glass
+    /// from a light: one bounce, followed through specular surfaces to the global map.
+    fn final_gather(
+        &self,
+        r: &Ray,
+        rec: &mut HitRecord,
+        mat: &dyn Material,
+        background: &dyn Environment,
+        world: &HittableList,
+    ) -> Vec3 {
+        let mut throughput = Vec3::zero();
+        let mut ray = Ray::new(Vec3::zero(), Vec3::zero(), r.tm());
+        if !mat.scatter(r, rec, &mut throughput, &mut ray) {
+            return Vec3::zero();
+        }
+        for depth in 0..self.settings.max_depth {
+            let mut rec = HitRecord::new();
+            if !world.hit(&ray, 0.001, INFINITY, &mut rec) {
+                // The environment sends no photons, so the caustic map has none of its light.
+                if depth == 0 {
+                    return Vec3::zero();
+                }
+                return Vec3::elemul(&throughput, &background.radiance(&ray.direc()));
+            }
+            let mat = match rec.mat.clone() {
+                Some(mat) => mat,
+                None => break,
+            };
+            rec.tm = ray.tm();
+            if !rec.front_size {
+                let interior = beer_lambert(&mat.absorption(), rec.t * ray.direc().length());
+                throughput = Vec3::elemul(&throughput, &interior);
+            }
+            if !mat.is_volume() && !mat.is_specular(&rec) {
+                let radius = self.settings.global_radius;
+                let (power, _) = self.global.reflected(&ray, &rec, mat.as_ref(), radius);
+                return Vec3::elemul(&throughput, &power) / (PI * radius * radius);
+            }
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), ray.tm());
+            if !mat.scatter(&ray, &mut rec, &mut attenuation, &mut scattered) {
+                break;
+            }
+            throughput = Vec3::elemul(&throughput, &attenuation);
+            ray = scattered;
+        }
+        Vec3::zero()
+    }
+}
+
+impl Integrator for PhotonMapping {
+    fn radiance(
+        &self,
+        r: &Ray,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+    ) -> Vec3 {
+        let mut radiance = Vec3::zero();
+        let VisiblePoint {
+            ray,
+            mut rec,
+            mat,
+            beta,
+        } = match visible_point(
+            r,
+            background,
+            lights,
+            world,
+            self.settings.max_depth,
+            &mut radiance,
+        ) {
+            Some(point) => point,
+            None => return radiance,
+        };
+        let radius = self.settings.caustic_radius;
+        let (power, _) = self.caustic.reflected(&ray, &rec, mat.as_ref(), radius);
+        let mut indirect = power / (PI * radius * radius);
+        indirect += self.final_gather(&ray, &mut rec, mat.as_ref(), background, world);
+        radiance + Vec3::elemul(&beta, &indirect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xzrect;
+    use crate::camera::{Camera, Projection};
+    use crate::environment::ConstantEnvironment;
+    use crate::hiitable::Hiitable;
+    use crate::material::{Dielectric, DiffLight, FlipFace, Lambertian};
+    use crate::object::Sphere;
+    use crate::path_tracer::{PathSettings, PathTracer};
+
+    /// A grey floor under a ball, lit by a small square lamp facing down.
+    fn scene(glass: bool) -> (HittableList, LightList, Camera) {
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Xzrect::new(
+            -2.0,
+            2.0,
+            -2.0,
+            2.0,
+            0.0,
+            Some(Arc::new(Lambertian::new1(&(Vec3::ones() * 0.5)))),
+        ))));
+        let ball: Arc<dyn Material> = if glass {
+            Arc::new(Dielectric::new(1.5))
+        } else {
+            Arc::new(Lambertian::new1(&Vec3::new(0.8, 0.3, 0.3)))
+        };
+        world.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(0.3, 0.4, 0.0),
+            0.4,
+            Some(ball),
+        ))));
+        let lamp: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(FlipFace::new(Some(Arc::new(Xzrect::new(
+                -0.3,
+                0.3,
+                -0.3,
+                0.3,
+                1.5,
+                Some(Arc::new(DiffLight::new2(Vec3::ones() * 8.0))),
+            ))))));
+        world.add(lamp.clone());
+        let mut lights = LightList::new();
+        lights.add_area(lamp);
+        let cam = Camera::new(
+            1.0,
+            &Vec3::new(0.0, 1.0, -3.0),
+            &Vec3::zero(),
+            &Vec3::new(0.0, 1.0, 0.0),
+            50.0,
+            0.0,
+            (3.0, 0.0, 1.0),
+        );
+        (world, lights, cam)
+    }
+
+    /// Sum of the red channel over a 4x4 render.
+    fn total(
+        integrator: &dyn Integrator,
+        world: &HittableList,
+        lights: &LightList,
+        cam: &Camera,
+        spp: usize,
+    ) -> f64 {
+        let background = ConstantEnvironment::new(Vec3::zero());
+        let mut sum = 0.0;
+        for j in 0..4 {
+            for i in 0..4 {
+                for _ in 0..spp {
+                    let u = (i as f64 + random_f64()) / 4.0;
+                    let v = (j as f64 + random_f64()) / 4.0;
+                    let r = cam.get_ray(u, v);
+                    sum += integrator.radiance(&r, &background, lights, world).x();
+                }
+            }
+        }
+        sum / spp as f64
+    }
+
+    #[test]
+    fn test_within_matches_brute_force() {
+        let photons: Vec<Photon> = (0..2000)
+            .map(|_| Photon {
+                position: Vec3::new(
+                    random_f64_1(-1.0, 1.0),
+                    random_f64_1(-1.0, 1.0) * 0.1,
+                    random_f64_1(-1.0, 1.0),
+                ),
+                direction: Vec3::new(0.0, -1.0, 0.0),
+                power: Vec3::ones(),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.len(), photons.len());
+        for _ in 0..50 {
+            let point = Vec3::new(random_f64_1(-1.0, 1.0), 0.0, random_f64_1(-1.0, 1.0));
+            let radius = random_f64_1(0.0, 0.5);
+            let mut found = Vec::new();
+            map.within(&point, radius, &mut |p| found.push(p.position));
+            let mut expected: Vec<Vec3> = photons
+                .iter()
+                .map(|p| p.position)
+                .filter(|p| (*p - point).squared_length() <= radius * radius)
+                .collect();
+            let key = |p: &Vec3| (p.x(), p.y(), p.z());
+            found.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            expected.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+            assert_eq!(found.len(), expected.len());
+            assert!(found.iter().zip(&expected).all(|(a, b)| *a == *b));
+        }
+    }
+
+    #[test]
+    fn test_agrees_with_path_tracing() {
+        let reference = PathTracer::new(PathSettings::default());
+        for glass in [false, true] {
+            let (world, lights, cam) = scene(glass);
+            let expected = total(&reference, &world, &lights, &cam, 2000);
+            let settings = PhotonSettings {
+                global_photons: 100_000,
+                caustic_photons: 200_000,
+                global_radius: 0.1,
+                caustic_radius: 0.05,
+                ..PhotonSettings::default()
+            };
+            let photons = PhotonMapping::new(settings, &lights, &world);
+            let found = total(&photons, &world, &lights, &cam, 500);
+            assert!(
+                (found - expected).abs() < 0.08 * expected,
+                "{} vs {}",
+                found,
+                expected
+            );
+        }
+    }
+}
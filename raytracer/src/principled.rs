@@ -0,0 +1,449 @@
+pub use crate::hiitable::HitRecord;
+pub use crate::material::Material;
+use crate::microfacet::{fresnel_dielectric, TrowbridgeReitz};
+pub use crate::onb::Onb;
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::texture::{SolidColor, Texture, MISSING_TEXTURE};
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Inputs of the principled BSDF. Scalar parameters read the `x` channel of their texture.
+#[derive(Clone)]
+pub struct PrincipledParams {
+    pub base_color: Option<Arc<dyn Texture>>,
+    pub metallic: Option<Arc<dyn Texture>>,
+    pub roughness: Option<Arc<dyn Texture>>,
+    pub specular: Option<Arc<dyn Texture>>,
+    pub specular_tint: Option<Arc<dyn Texture>>,
+    pub sheen: Option<Arc<dyn Texture>>,
+    pub sheen_tint: Option<Arc<dyn Texture>>,
+    pub clearcoat: Option<Arc<dyn Texture>>,
+    pub clearcoat_gloss: Option<Arc<dyn Texture>>,
+    pub transmission: Option<Arc<dyn Texture>>,
+    pub ior: Option<Arc<dyn Texture>>,
+    pub anisotropic: Option<Arc<dyn Texture>>,
+}
+
+impl PrincipledParams {
+    pub fn constant(value: f64) -> Option<Arc<dyn Texture>> {
+        Some(Arc::new(SolidColor::new(Vec3::ones() * value)))
+    }
+}
+
+impl Default for PrincipledParams {
+    fn default() -> Self {
+        Self {
+            base_color: Some(Arc::new(SolidColor::new(Vec3::new(0.8, 0.8, 0.8)))),
+            metallic: PrincipledParams::constant(0.0),
+            roughness: PrincipledParams::constant(0.5),
+            specular: PrincipledParams::constant(0.5),
+            specular_tint: PrincipledParams::constant(0.0),
+            sheen: PrincipledParams::constant(0.0),
+            sheen_tint: PrincipledParams::constant(0.5),
+            clearcoat: PrincipledParams::constant(0.0),
+            clearcoat_gloss: PrincipledParams::constant(1.0),
+            transmission: PrincipledParams::constant(0.0),
+            ior: PrincipledParams::constant(1.5),
+            anisotropic: PrincipledParams::constant(0.0),
+        }
+    }
+}
+
+/// Disney-style uber material: diffuse with retro-reflection and sheen, anisotropic GGX
+/// specular, a GTR1 clearcoat and rough transmission, all blended by `metallic` and
+/// `transmission`.
+pub struct Principled {
+    params: PrincipledParams,
+}
+
+impl Principled {
+    pub fn new(params: PrincipledParams) -> Self {
+        Self { params }
+    }
+
+    fn lobes(&self, rec: &HitRecord, wo: &Vec3) -> PrincipledLobes {
+        let coord = rec.tex_coord();
+        let tex =
+            |t: &Option<Arc<dyn Texture>>| t.as_ref().map_or(MISSING_TEXTURE, |t| t.eval(&coord));
+        let scalar = |t: &Option<Arc<dyn Texture>>| tex(t).x();
+
+        let base = tex(&self.params.base_color);
+        let metallic = scalar(&self.params.metallic).clamp(0.0, 1.0);
+        let roughness = scalar(&self.params.roughness).clamp(0.0, 1.0);
+        let transmission = scalar(&self.params.transmission).clamp(0.0, 1.0);
+        let ior = scalar(&self.params.ior).max(1.0);
+        let aspect = (1.0 - 0.9 * scalar(&self.params.anisotropic).clamp(0.0, 1.0)).sqrt();
+        let clearcoat = scalar(&self.params.clearcoat).max(0.0);
+
+        let lum = base.luminance();
+        let tint = if lum > 0.0 { base / lum } else { Vec3::ones() };
+        let specular_tint = scalar(&self.params.specular_tint);
+        let sheen_tint = scalar(&self.params.sheen_tint);
+
+        let mut lobes = PrincipledLobes {
+            base,
+            metallic,
+            roughness,
+            transmission,
+            eta: if rec.front_size { ior } else { 1.0 / ior },
+            ggx: TrowbridgeReitz::from_alpha(
+                roughness * roughness / aspect,
+                roughness * roughness * aspect,
+            ),
+            cspec0: lerp(&Vec3::ones(), &tint, specular_tint)
+                * (scalar(&self.params.specular) * 0.08),
+            csheen: lerp(&Vec3::ones(), &tint, sheen_tint) * scalar(&self.params.sheen),
+            clearcoat,
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * scalar(&self.params.clearcoat_gloss),
+            probs: [0.0; 4],
+        };
+
+        // Pick lobes roughly by how much energy each one carries towards the viewer.
+        let weights = [
+            (1.0 - metallic) * (1.0 - transmission) * lum,
+            lobes.fresnel(wo.z()).luminance(),
+            0.25 * clearcoat * (0.04 + 0.96 * schlick_weight(wo.z())),
+            (1.0 - metallic) * transmission * (1.0 - fresnel_dielectric(wo.z(), lobes.eta)),
+        ];
+        let total: f64 = weights.iter().sum();
+        if total > 0.0 {
+            for (p, w) in lobes.probs.iter_mut().zip(weights) {
+                *p = w / total;
+            }
+        }
+        lobes
+    }
+}
+
+struct PrincipledLobes {
+    base: Vec3,
+    metallic: f64,
+    roughness: f64,
+    transmission: f64,
+    eta: f64,
+    ggx: TrowbridgeReitz,
+    cspec0: Vec3,
+    csheen: Vec3,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    probs: [f64; 4],
+}
+
+impl PrincipledLobes {
+    /// Specular Fresnel, blending the dielectric, metallic and transmissive responses.
+    fn fresnel(&self, cos_d: f64) -> Vec3 {
+        let metal = self.base + (Vec3::ones() - self.base) * schlick_weight(cos_d);
+        let plastic = self.cspec0 + (Vec3::ones() - self.cspec0) * schlick_weight(cos_d);
+        let glass = Vec3::ones() * fresnel_dielectric(cos_d, self.eta);
+        metal * self.metallic
+            + (plastic * (1.0 - self.transmission) + glass * self.transmission)
+                * (1.0 - self.metallic)
+    }
+
+    /// Returns f(wo, wi) * |cos(wi)|.
+    fn eval(&self, wo: &Vec3, wi: &Vec3) -> Vec3 {
+        if wi.z() > 0.0 {
+            let h = (*wo + *wi).unit();
+            let cos_d = *wi * h;
+            let mut f = Vec3::zero();
+
+            let diffuse_weight = (1.0 - self.metallic) * (1.0 - self.transmission);
+            if diffuse_weight > 0.0 {
+                let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+                let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()))
+                    * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()));
+                f += (self.base * (fd / PI) + self.csheen * schlick_weight(cos_d)) * diffuse_weight;
+            }
+
+            f += self.fresnel(cos_d)
+                * (self.ggx.d(&h) * self.ggx.g(wo, wi) / (4.0 * wo.z() * wi.z()));
+
+            if self.clearcoat > 0.0 {
+                let fr = 0.04 + 0.96 * schlick_weight(cos_d);
+                let gr = smith_g1(wo.z(), 0.25) * smith_g1(wi.z(), 0.25);
+                f += Vec3::ones()
+                    * (0.25 * self.clearcoat * gtr1(h.z(), self.clearcoat_alpha) * fr * gr
+                        / (4.0 * wo.z() * wi.z()));
+            }
+            f * wi.z()
+        } else {
+            let weight = (1.0 - self.metallic) * self.transmission;
+            if weight <= 0.0 {
+                return Vec3::zero();
+            }
+            let h = match self.refraction_half_vector(wo, wi) {
+                Some(h) => h,
+                None => return Vec3::zero(),
+            };
+            let cos_o = *wo * h;
+            let cos_i = *wi * h;
+            let denom = cos_o + self.eta * cos_i;
+            // The eta^2 of the change of variables cancels the 1 / eta^2 scaling of radiance,
+            // and the |cos(wi)| of the result the one in the BTDF's denominator.
+            let f = (1.0 - fresnel_dielectric(cos_o, self.eta))
+                * self.ggx.d(&h)
+                * self.ggx.g(wo, wi)
+                * cos_i.abs()
+                * cos_o.abs()
+                / (wo.z() * denom * denom);
+            self.base * (weight * f)
+        }
+    }
+
+    fn pdf(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wi.z() > 0.0 {
+            let h = (*wo + *wi).unit();
+            let cos_o = *wo * h;
+            if cos_o <= 0.0 {
+                return 0.0;
+            }
+            self.probs[0] * wi.z() / PI
+                + self.probs[1] * self.ggx.pdf(wo, &h) / (4.0 * cos_o)
+                + self.probs[2] * gtr1(h.z(), self.clearcoat_alpha) * h.z() / (4.0 * cos_o)
+        } else {
+            match self.refraction_half_vector(wo, wi) {
+                Some(h) => {
+                    let denom = *wo * h + self.eta * (*wi * h);
+                    self.probs[3] * self.ggx.pdf(wo, &h) * self.eta * self.eta * (*wi * h).abs()
+                        / (denom * denom)
+                }
+                None => 0.0,
+            }
+        }
+    }
+
+    fn refraction_half_vector(&self, wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let mut h = *wo + *wi * self.eta;
+        if h.near_zero() {
+            return None;
+        }
+        h = h.unit();
+        if h.z() < 0.0 {
+            h = -h;
+        }
+        // Both directions must straddle the microfacet.
+        if (*wo * h) <= 0.0 || (*wi * h) >= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+
+    fn sample(&self, wo: &Vec3) -> Option<Vec3> {
+        let mut xi = random_f64();
+        let mut lobe = 0;
+        while lobe < 3 && xi >= self.probs[lobe] {
+            xi -= self.probs[lobe];
+            lobe += 1;
+        }
+        match lobe {
+            0 => {
+                let r = random_f64().sqrt();
+                let phi = 2.0 * PI * random_f64();
+                Some(Vec3::new(
+                    r * phi.cos(),
+                    r * phi.sin(),
+                    (1.0 - r * r).max(0.0).sqrt(),
+                ))
+            }
+            1 => Some(Vec3::reflect(&(-*wo), &self.ggx.sample_wh(wo))),
+            2 => {
+                let a2 = self.clearcoat_alpha * self.clearcoat_alpha;
+                let cos_h = ((1.0 - a2.powf(1.0 - random_f64())) / (1.0 - a2)).sqrt();
+                let sin_h = (1.0 - cos_h * cos_h).max(0.0).sqrt();
+                let phi = 2.0 * PI * random_f64();
+                let h = Vec3::new(sin_h * phi.cos(), sin_h * phi.sin(), cos_h);
+                Some(Vec3::reflect(&(-*wo), &h))
+            }
+            _ => {
+                let h = self.ggx.sample_wh(wo);
+                let cos_i = *wo * h;
+                let sin2_t = (1.0 - cos_i * cos_i) / (self.eta * self.eta);
+                if sin2_t >= 1.0 {
+                    return None;
+                }
+                let cos_t = (1.0 - sin2_t).sqrt();
+                Some(-*wo / self.eta + h * (cos_i / self.eta - cos_t))
+            }
+        }
+    }
+}
+
+impl Material for Principled {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        let lobes = self.lobes(rec, &wo);
+        let wi = match lobes.sample(&wo) {
+            Some(wi) => wi,
+            None => return false,
+        };
+        let pdf = lobes.pdf(&wo, &wi);
+        if pdf <= 0.0 {
+            return false;
+        }
+
+        // Dividing the full BSDF by the mixture pdf keeps every lobe's contribution,
+        // whichever lobe generated the direction.
+        *attenuation = lobes.eval(&wo, &wi) / pdf;
+        *scattered = Ray::new(rec.point3, uvw.local(&wi), r_in.tm());
+        true
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> Vec3 {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 {
+            return Vec3::zero();
+        }
+        self.lobes(rec, &wo)
+            .eval(&wo, &uvw.to_local(&direction.unit()))
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.lobes(rec, &wo)
+            .pdf(&wo, &uvw.to_local(&direction.unit()))
+    }
+
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        false
+    }
+
+    fn radiance_scale(&self, r_in: &Ray, rec: &HitRecord, direction: &Vec3) -> f64 {
+        let uvw = Onb::build_from_w_u(&rec.normal, &rec.dpdu);
+        let wo = uvw.to_local(&(-r_in.direc().unit()));
+        if wo.z() <= 0.0 || uvw.to_local(direction).z() >= 0.0 {
+            return 1.0;
+        }
+        let eta = self.lobes(rec, &wo).eta;
+        1.0 / (eta * eta)
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        Vec3::new(0.0, 0.0, 0.0)
+    }
+}
+
+fn lerp(a: &Vec3, b: &Vec3, t: f64) -> Vec3 {
+    *a * (1.0 - t) + *b * t
+}
+
+fn schlick_weight(cos: f64) -> f64 {
+    (1.0 - cos.clamp(0.0, 1.0)).powi(5)
+}
+
+fn gtr1(cos_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    (a2 - 1.0) / (PI * a2.ln() * (1.0 + (a2 - 1.0) * cos_h * cos_h))
+}
+
+fn smith_g1(cos: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let c2 = cos * cos;
+    2.0 * cos / (cos + (a2 + c2 - a2 * c2).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white(metallic: f64, transmission: f64) -> Principled {
+        Principled::new(PrincipledParams {
+            base_color: PrincipledParams::constant(1.0),
+            roughness: PrincipledParams::constant(0.3),
+            metallic: PrincipledParams::constant(metallic),
+            transmission: PrincipledParams::constant(transmission),
+            ..PrincipledParams::default()
+        })
+    }
+
+    fn flat_hit(front: bool) -> HitRecord {
+        let mut rec = HitRecord::new();
+        rec.normal = Vec3::new(0.0, 0.0, 1.0);
+        rec.dpdu = Vec3::new(1.0, 0.0, 0.0);
+        rec.front_size = front;
+        rec
+    }
+
+    #[test]
+    fn test_principled_white_furnace() {
+        let r_in = Ray::new(Vec3::new(-0.6, 0.0, 0.8), Vec3::new(0.6, 0.0, -0.8), 0.0);
+        let n = 100_000;
+        for (material, front) in [
+            (white(1.0, 0.0), true),
+            (white(0.0, 1.0), true),
+            (white(0.0, 1.0), false),
+        ] {
+            let mut rec = flat_hit(front);
+            // Nothing is absorbed, so all the importance that goes missing is the light
+            // single-scattering GGX cannot account for. Radiance is also rescaled by refraction.
+            let mut albedo = 0.0;
+            let mut importance = 0.0;
+            for _ in 0..n {
+                let mut attenuation = Vec3::zero();
+                let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+                if !material.scatter(&r_in, &mut rec, &mut attenuation, &mut scattered) {
+                    continue;
+                }
+                let direction = scattered.direc();
+                let expected = material.eval(&r_in, &rec, &direction)
+                    / material.scattering_pdf(&r_in, &rec, &direction);
+                assert!((attenuation - expected).length() < 1e-6 * expected.length().max(1.0));
+                albedo += attenuation.x();
+                importance += attenuation.x() / material.radiance_scale(&r_in, &rec, &direction);
+            }
+            let albedo = albedo / n as f64;
+            let importance = importance / n as f64;
+            assert!(importance > 0.97 && importance < 1.01, "{}", importance);
+
+            // Integrating eval over the sphere gives the same albedo as sampling.
+            let (steps_theta, steps_phi) = (600, 200);
+            let (d_theta, d_phi) = (PI / steps_theta as f64, 2.0 * PI / steps_phi as f64);
+            let mut integral = 0.0;
+            for i in 0..steps_theta {
+                let theta = (i as f64 + 0.5) * d_theta;
+                for j in 0..steps_phi {
+                    let phi = (j as f64 + 0.5) * d_phi;
+                    let direction = Vec3::new(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    integral +=
+                        material.eval(&r_in, &rec, &direction).x() * theta.sin() * d_theta * d_phi;
+                }
+            }
+            assert!(
+                (integral - albedo).abs() < 0.02 * albedo.max(1.0),
+                "{} {}",
+                integral,
+                albedo
+            );
+        }
+    }
+
+    #[test]
+    fn test_grazing_transmission_is_finite() {
+        let rec = flat_hit(false);
+        let wo = Vec3::new(0.9, 0.0, (1.0_f64 - 0.81).sqrt());
+        let lobes = white(0.0, 1.0).lobes(&rec, &wo);
+        let f = lobes.eval(&wo, &Vec3::new(-1.0, 0.0, -0.0));
+        assert!(f.x().is_finite() && f.x() >= 0.0, "{:?}", f);
+    }
+}
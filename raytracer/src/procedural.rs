@@ -0,0 +1,492 @@
+pub use crate::perlin::Perlin;
+pub use crate::simplex::Simplex;
+pub use crate::texture::{SolidColor, TexCoord, Texture, MISSING_TEXTURE};
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+/// Gray fBm of Perlin noise in `[0, 1]`, or turbulence when built with `new_turbulence`.
+pub struct FbmTexture {
+    noise: Perlin,
+    scale: f64,
+    octaves: i32,
+    lacunarity: f64,
+    gain: f64,
+    turbulence: bool,
+    speed: f64,
+}
+
+impl FbmTexture {
+    pub fn new(scale: f64, octaves: i32, lacunarity: f64, gain: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            octaves,
+            lacunarity,
+            gain,
+            turbulence: false,
+            speed: 0.0,
+        }
+    }
+
+    pub fn new_turbulence(scale: f64, octaves: i32, lacunarity: f64, gain: f64) -> Self {
+        Self {
+            turbulence: true,
+            ..FbmTexture::new(scale, octaves, lacunarity, gain)
+        }
+    }
+
+    pub fn set_noise(&mut self, noise: Perlin) {
+        self.noise = noise;
+    }
+
+    /// Drifts the pattern through a fourth noise dimension at `speed` noise units per unit
+    /// of ray time, as `SimplexTexture` does.
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    fn sample(&self, p: &Vec3, tm: f64) -> Vec3 {
+        let q = *p * self.scale;
+        let w = tm * self.speed;
+        let n = if self.turbulence {
+            self.noise
+                .turbulence4(&q, w, self.octaves, self.lacunarity, self.gain)
+        } else {
+            0.5 * (1.0
+                + self
+                    .noise
+                    .fbm4(&q, w, self.octaves, self.lacunarity, self.gain))
+        };
+        Vec3::ones() * n.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for FbmTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        if self.speed != 0.0 {
+            return self.sample(p, 0.0);
+        }
+        let q = *p * self.scale;
+        let n = if self.turbulence {
+            self.noise
+                .turbulence(&q, self.octaves, self.lacunarity, self.gain)
+        } else {
+            0.5 * (1.0 + self.noise.fbm(&q, self.octaves, self.lacunarity, self.gain))
+        };
+        Vec3::ones() * n.clamp(0.0, 1.0)
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        if self.speed == 0.0 {
+            return self.value(c.u, c.v, &c.p);
+        }
+        self.sample(&c.p, c.tm)
+    }
+}
+
+/// Gray marble veins: sine bands along `axis` warped by turbulence. Feed it to a
+/// `ColorRamp` for colour.
+pub struct MarbleTexture {
+    noise: Perlin,
+    scale: f64,
+    distortion: f64,
+    octaves: i32,
+    axis: Vec3,
+}
+
+impl MarbleTexture {
+    pub fn new(scale: f64, distortion: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            scale,
+            distortion,
+            octaves: 7,
+            axis: Vec3::new(0.0, 0.0, 1.0),
+        }
+    }
+
+    pub fn set_noise(&mut self, noise: Perlin) {
+        self.noise = noise;
+    }
+
+    pub fn set_axis(&mut self, axis: Vec3) {
+        self.axis = axis.unit();
+    }
+
+    pub fn set_octaves(&mut self, octaves: i32) {
+        self.octaves = octaves;
+    }
+}
+
+impl Texture for MarbleTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let q = *p * self.scale;
+        let warp = self.noise.turbulence(&q, self.octaves, 2.0, 0.5);
+        Vec3::ones() * 0.5 * (1.0 + (q * self.axis + self.distortion * warp).sin())
+    }
+}
+
+/// Gray growth rings around the `y` axis through the origin; `rings` per unit radius.
+pub struct WoodTexture {
+    noise: Perlin,
+    rings: f64,
+    distortion: f64,
+    grain_scale: f64,
+}
+
+impl WoodTexture {
+    pub fn new(rings: f64, distortion: f64, grain_scale: f64) -> Self {
+        Self {
+            noise: Perlin::new(),
+            rings,
+            distortion,
+            grain_scale,
+        }
+    }
+
+    pub fn set_noise(&mut self, noise: Perlin) {
+        self.noise = noise;
+    }
+}
+
+impl Texture for WoodTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let radius = (p.x() * p.x() + p.z() * p.z()).sqrt();
+        let grain = self.noise.fbm(&(*p * self.grain_scale), 4, 2.0, 0.5);
+        let r = (radius + self.distortion * grain) * self.rings;
+        // Sharpen each ring so late wood is a thin dark band.
+        Vec3::ones() * (r - r.floor()).powf(3.0)
+    }
+}
+
+/// Gray simplex fBm in `[0, 1]` that drifts through a fourth dimension at `speed` noise
+/// units per unit of ray time, so motion-blurred frames see the pattern evolve.
+pub struct SimplexTexture {
+    noise: Simplex,
+    scale: f64,
+    octaves: i32,
+    lacunarity: f64,
+    gain: f64,
+    speed: f64,
+}
+
+impl SimplexTexture {
+    pub fn new(scale: f64, octaves: i32, lacunarity: f64, gain: f64, speed: f64) -> Self {
+        Self {
+            noise: Simplex::new(),
+            scale,
+            octaves,
+            lacunarity,
+            gain,
+            speed,
+        }
+    }
+
+    pub fn set_noise(&mut self, noise: Simplex) {
+        self.noise = noise;
+    }
+
+    pub fn fbm4(&self, p: &Vec3, w: f64) -> f64 {
+        let mut accum = 0.0;
+        let mut total = 0.0;
+        let mut weight = 1.0;
+        let mut q = *p * self.scale;
+        let mut w = w;
+        for _ in 0..self.octaves.max(1) {
+            accum += weight * self.noise.noise4(q.x(), q.y(), q.z(), w);
+            total += weight;
+            weight *= self.gain;
+            q *= self.lacunarity;
+            w *= self.lacunarity;
+        }
+        accum / total
+    }
+}
+
+impl Texture for SimplexTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        Vec3::ones() * (0.5 * (1.0 + self.fbm4(p, 0.0))).clamp(0.0, 1.0)
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        Vec3::ones() * (0.5 * (1.0 + self.fbm4(&c.p, c.tm * self.speed))).clamp(0.0, 1.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WorleyMode {
+    /// Distance to the nearest feature point.
+    F1,
+    /// Distance to the second nearest.
+    F2,
+    /// Cell borders.
+    F2MinusF1,
+}
+
+/// Cellular noise with one jittered feature point per unit cell.
+pub struct WorleyTexture {
+    scale: f64,
+    seed: u32,
+    mode: WorleyMode,
+}
+
+impl WorleyTexture {
+    pub fn new(scale: f64, seed: u32, mode: WorleyMode) -> Self {
+        Self { scale, seed, mode }
+    }
+
+    fn feature_point(&self, i: i32, j: i32, k: i32) -> Vec3 {
+        let h = hash3(self.seed, i, j, k);
+        Vec3::new(
+            i as f64 + unit_float(h),
+            j as f64 + unit_float(hash_u32(h)),
+            k as f64 + unit_float(hash_u32(hash_u32(h))),
+        )
+    }
+
+    pub fn distances(&self, p: &Vec3) -> (f64, f64) {
+        let q = *p * self.scale;
+        let (ci, cj, ck) = (
+            q.x().floor() as i32,
+            q.y().floor() as i32,
+            q.z().floor() as i32,
+        );
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+        for i in ci - 1..=ci + 1 {
+            for j in cj - 1..=cj + 1 {
+                for k in ck - 1..=ck + 1 {
+                    let d = (self.feature_point(i, j, k) - q).length();
+                    if d < f1 {
+                        f2 = f1;
+                        f1 = d;
+                    } else if d < f2 {
+                        f2 = d;
+                    }
+                }
+            }
+        }
+        (f1, f2)
+    }
+}
+
+impl Texture for WorleyTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let (f1, f2) = self.distances(p);
+        let d = match self.mode {
+            WorleyMode::F1 => f1,
+            WorleyMode::F2 => f2,
+            WorleyMode::F2MinusF1 => f2 - f1,
+        };
+        Vec3::ones() * d.clamp(0.0, 1.0)
+    }
+}
+
+fn hash_u32(mut x: u32) -> u32 {
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb_352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846c_a68b);
+    x ^= x >> 16;
+    x
+}
+
+fn hash3(seed: u32, i: i32, j: i32, k: i32) -> u32 {
+    let h = hash_u32(seed ^ (i as u32));
+    let h = hash_u32(h ^ (j as u32).wrapping_mul(0x9e37_79b9));
+    hash_u32(h ^ (k as u32).wrapping_mul(0x85eb_ca6b))
+}
+
+fn unit_float(h: u32) -> f64 {
+    h as f64 / (u32::MAX as f64 + 1.0)
+}
+
+/// Checker in texture space, `freq_u` by `freq_v` squares over the unit square, so it
+/// follows the surface instead of cutting through it like `CheckerTexture`.
+pub struct UvCheckerTexture {
+    even: Option<Arc<dyn Texture>>,
+    odd: Option<Arc<dyn Texture>>,
+    freq_u: f64,
+    freq_v: f64,
+}
+
+impl UvCheckerTexture {
+    pub fn new_1(
+        even: Option<Arc<dyn Texture>>,
+        odd: Option<Arc<dyn Texture>>,
+        freq_u: f64,
+        freq_v: f64,
+    ) -> Self {
+        Self {
+            even,
+            odd,
+            freq_u,
+            freq_v,
+        }
+    }
+
+    pub fn new_2(c1: Vec3, c2: Vec3, freq_u: f64, freq_v: f64) -> Self {
+        Self {
+            even: Some(Arc::new(SolidColor::new(c1))),
+            odd: Some(Arc::new(SolidColor::new(c2))),
+            freq_u,
+            freq_v,
+        }
+    }
+}
+
+impl Texture for UvCheckerTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let cell = (c.u * self.freq_u).floor() as i64 + (c.v * self.freq_v).floor() as i64;
+        let side = if cell.rem_euclid(2) == 0 {
+            &self.even
+        } else {
+            &self.odd
+        };
+        side.as_ref().map_or(MISSING_TEXTURE, |t| t.eval(c))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum GradientKind {
+    U,
+    V,
+    /// From `origin` (0) to `origin + direction` (1).
+    Linear {
+        origin: Vec3,
+        direction: Vec3,
+    },
+    /// From `center` (0) out to `radius` (1).
+    Radial {
+        center: Vec3,
+        radius: f64,
+    },
+}
+
+/// Blends from `start` to `end` along a gradient; both ends are textures.
+pub struct GradientTexture {
+    start: Option<Arc<dyn Texture>>,
+    end: Option<Arc<dyn Texture>>,
+    kind: GradientKind,
+}
+
+impl GradientTexture {
+    pub fn new(
+        start: Option<Arc<dyn Texture>>,
+        end: Option<Arc<dyn Texture>>,
+        kind: GradientKind,
+    ) -> Self {
+        Self { start, end, kind }
+    }
+
+    pub fn t(&self, u: f64, v: f64, p: &Vec3) -> f64 {
+        let t = match self.kind {
+            GradientKind::U => u,
+            GradientKind::V => v,
+            GradientKind::Linear { origin, direction } => {
+                (*p - origin) * direction / direction.squared_length()
+            }
+            GradientKind::Radial { center, radius } => (*p - center).length() / radius,
+        };
+        t.clamp(0.0, 1.0)
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let t = self.t(c.u, c.v, &c.p);
+        let end = |e: &Option<Arc<dyn Texture>>| e.as_ref().map_or(MISSING_TEXTURE, |e| e.eval(c));
+        end(&self.start) * (1.0 - t) + end(&self.end) * t
+    }
+}
+
+/// Maps the `x` channel of `input` through piecewise-linear colour stops.
+pub struct ColorRamp {
+    input: Option<Arc<dyn Texture>>,
+    stops: Vec<(f64, Vec3)>,
+}
+
+impl ColorRamp {
+    pub fn new(input: Option<Arc<dyn Texture>>, mut stops: Vec<(f64, Vec3)>) -> Self {
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { input, stops }
+    }
+
+    pub fn color_at(&self, x: f64) -> Vec3 {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vec3::ones() * x,
+        };
+        if x <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let (x0, c0) = pair[0];
+            let (x1, c1) = pair[1];
+            if x <= x1 {
+                let t = if x1 > x0 { (x - x0) / (x1 - x0) } else { 1.0 };
+                return c0 * (1.0 - t) + c1 * t;
+            }
+        }
+        last.1
+    }
+}
+
+impl Texture for ColorRamp {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let x = self.input.as_ref().map_or(0.0, |t| t.eval(c).x());
+        self.color_at(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_ramp_interpolates() {
+        let ramp = ColorRamp::new(None, vec![(1.0, Vec3::ones()), (0.0, Vec3::zero())]);
+        assert_eq!(ramp.color_at(-1.0).x(), 0.0);
+        assert!((ramp.color_at(0.25).y() - 0.25).abs() < 1e-12);
+        assert_eq!(ramp.color_at(2.0).z(), 1.0);
+    }
+
+    #[test]
+    fn test_worley_is_deterministic() {
+        let a = WorleyTexture::new(4.0, 7, WorleyMode::F1);
+        let b = WorleyTexture::new(4.0, 7, WorleyMode::F1);
+        let p = Vec3::new(0.3, -1.2, 2.5);
+        assert_eq!(a.distances(&p), b.distances(&p));
+        let (f1, f2) = a.distances(&p);
+        assert!(f1 <= f2);
+    }
+
+    #[test]
+    fn test_fbm_animates_with_ray_time() {
+        let mut fbm = FbmTexture::new(2.0, 4, 2.0, 0.5);
+        fbm.set_noise(Perlin::with_seed(5));
+        fbm.set_speed(1.5);
+        let at = |tm: f64| {
+            fbm.eval(&TexCoord {
+                tm,
+                ..TexCoord::new(0.0, 0.0, &Vec3::new(0.3, 0.7, -0.2))
+            })
+            .x()
+        };
+        assert_eq!(at(0.0), fbm.value(0.0, 0.0, &Vec3::new(0.3, 0.7, -0.2)).x());
+        assert!((0..10).any(|i| (at(i as f64 * 0.1) - at(0.0)).abs() > 0.01));
+    }
+}
@@ -1,9 +1,14 @@
 pub use crate::vec3::Vec3;
 
+#[derive(Clone)]
 pub struct Ray {
     pub direc: Vec3,
     pub ori: Vec3,
     pub tm: f64,
+    /// Ray cone used for texture filtering: width at the origin and growth per unit
+    /// distance. Both are zero for rays that need no filtering.
+    pub width: f64,
+    pub spread: f64,
 }
 
 impl Ray {
@@ -16,6 +21,8 @@ impl Ray {
             ori: Vec3::new(origin.x, origin.y, origin.z),
             direc: Vec3::new(direction.x, direction.y, direction.z),
             tm: time,
+            width: 0.0,
+            spread: 0.0,
         }
     }
 
@@ -34,4 +41,9 @@ impl Ray {
     pub fn tm(&self) -> f64 {
         self.tm
     }
+
+    /// Width of the ray cone at parameter `t`.
+    pub fn footprint(&self, t: f64) -> f64 {
+        self.width + self.spread * t * self.direc.length()
+    }
 }
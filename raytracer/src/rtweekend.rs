@@ -1,6 +1,3 @@
-pub use crate::aabb::AAbb;
-pub use crate::vec3::Vec3;
-
 use rand::Rng;
 use std::f64::consts::PI;
 
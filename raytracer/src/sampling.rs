@@ -0,0 +1,140 @@
+/// Piecewise-constant distribution over `[0, 1)`, sampled by inverting its CDF.
+#[derive(Clone)]
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    func_int: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i].max(0.0) / n as f64;
+        }
+        let func_int = cdf[n];
+        if func_int > 0.0 {
+            for c in cdf.iter_mut() {
+                *c /= func_int;
+            }
+        } else {
+            // All zero: fall back to uniform so sampling still works.
+            for (i, c) in cdf.iter_mut().enumerate() {
+                *c = i as f64 / n as f64;
+            }
+        }
+        Self {
+            func,
+            cdf,
+            func_int,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.func.len()
+    }
+
+    /// Integral of the unnormalised function over `[0, 1)`.
+    pub fn integral(&self) -> f64 {
+        self.func_int
+    }
+
+    /// Maps a uniform `xi` to `(x, pdf, bucket)`.
+    pub fn sample_continuous(&self, xi: f64) -> (f64, f64, usize) {
+        let offset = self
+            .cdf
+            .partition_point(|c| *c <= xi)
+            .saturating_sub(1)
+            .min(self.count() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 {
+            (xi - self.cdf[offset]) / width
+        } else {
+            0.0
+        };
+        let x = ((offset as f64 + du) / self.count() as f64).min(1.0 - f64::EPSILON);
+        (x, self.pdf_bucket(offset), offset)
+    }
+
+    /// Maps a uniform `xi` to a bucket index and its probability.
+    pub fn sample_discrete(&self, xi: f64) -> (usize, f64) {
+        let (_, _, offset) = self.sample_continuous(xi);
+        (offset, self.discrete_pdf(offset))
+    }
+
+    pub fn discrete_pdf(&self, i: usize) -> f64 {
+        self.cdf[i + 1] - self.cdf[i]
+    }
+
+    /// Density of `x` in `[0, 1)`.
+    pub fn pdf(&self, x: f64) -> f64 {
+        let i = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.pdf_bucket(i)
+    }
+
+    fn pdf_bucket(&self, i: usize) -> f64 {
+        self.discrete_pdf(i) * self.count() as f64
+    }
+}
+
+/// Piecewise-constant distribution over the unit square: a marginal over rows and a
+/// conditional distribution within each row.
+pub struct Distribution2D {
+    conditional: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    /// `func` holds `height` rows of `width` values.
+    pub fn new(func: &[f64], width: usize, height: usize) -> Self {
+        let conditional: Vec<Distribution1D> = (0..height)
+            .map(|v| Distribution1D::new(func[v * width..(v + 1) * width].to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(conditional.iter().map(|d| d.integral()).collect());
+        Self {
+            conditional,
+            marginal,
+        }
+    }
+
+    /// Maps two uniforms to `(u, v)` and the density there.
+    pub fn sample(&self, xi_u: f64, xi_v: f64) -> (f64, f64, f64) {
+        let (v, pdf_v, row) = self.marginal.sample_continuous(xi_v);
+        let (u, pdf_u, _) = self.conditional[row].sample_continuous(xi_u);
+        (u, v, pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        self.marginal.pdf(v) * self.conditional[row].pdf(u)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distribution_inverts_cdf() {
+        let d = Distribution1D::new(vec![1.0, 3.0]);
+        let (x, pdf, i) = d.sample_continuous(0.5);
+        assert_eq!(i, 1);
+        assert!((pdf - 1.5).abs() < 1e-12);
+        assert!((x - 2.0 / 3.0).abs() < 1e-12);
+        assert!((d.pdf(0.25) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_distribution_2d_pdf_integrates_to_one() {
+        let func = [1.0, 2.0, 0.0, 4.0, 0.5, 0.5];
+        let d = Distribution2D::new(&func, 3, 2);
+        let mut total = 0.0;
+        for v in 0..2 {
+            for u in 0..3 {
+                total += d.pdf((u as f64 + 0.5) / 3.0, (v as f64 + 0.5) / 2.0) / 6.0;
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,208 @@
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0],
+    [-1.0, 1.0, 0.0],
+    [1.0, -1.0, 0.0],
+    [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0],
+    [-1.0, 0.0, 1.0],
+    [1.0, 0.0, -1.0],
+    [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0],
+    [0.0, -1.0, 1.0],
+    [0.0, 1.0, -1.0],
+    [0.0, -1.0, -1.0],
+];
+
+/// Simplex noise in two, three and four dimensions (Gustavson's formulation). Output is
+/// roughly in `[-1, 1]`. The fourth dimension is usually time, see `SimplexTexture`.
+pub struct Simplex {
+    perm: Vec<usize>,
+}
+
+impl Default for Simplex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simplex {
+    pub fn new() -> Self {
+        Simplex::from_rng(&mut rand::thread_rng())
+    }
+
+    pub fn with_seed(seed: u64) -> Self {
+        Simplex::from_rng(&mut StdRng::seed_from_u64(seed))
+    }
+
+    fn from_rng(rng: &mut impl Rng) -> Self {
+        let mut p: Vec<usize> = (0..256).collect();
+        p.shuffle(rng);
+        let perm = p.iter().chain(p.iter()).copied().collect();
+        Self { perm }
+    }
+
+    fn hash(&self, i: i64) -> usize {
+        self.perm[(i & 255) as usize]
+    }
+
+    pub fn noise2(&self, x: f64, y: f64) -> f64 {
+        let f2 = 0.5 * (3.0_f64.sqrt() - 1.0);
+        let g2 = (3.0 - 3.0_f64.sqrt()) / 6.0;
+
+        let s = (x + y) * f2;
+        let i = (x + s).floor();
+        let j = (y + s).floor();
+        let t = (i + j) * g2;
+        let x0 = x - (i - t);
+        let y0 = y - (j - t);
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+
+        let corners = [
+            (x0, y0, 0, 0),
+            (x0 - i1 as f64 + g2, y0 - j1 as f64 + g2, i1, j1),
+            (x0 - 1.0 + 2.0 * g2, y0 - 1.0 + 2.0 * g2, 1, 1),
+        ];
+        let (i, j) = (i as i64, j as i64);
+        let mut n = 0.0;
+        for (cx, cy, di, dj) in corners {
+            let t = 0.5 - cx * cx - cy * cy;
+            if t > 0.0 {
+                let g = GRAD3[self.hash(i + di + self.hash(j + dj) as i64) % 12];
+                n += t.powi(4) * (g[0] * cx + g[1] * cy);
+            }
+        }
+        70.0 * n
+    }
+
+    pub fn noise3(&self, x: f64, y: f64, z: f64) -> f64 {
+        let f3 = 1.0 / 3.0;
+        let g3 = 1.0 / 6.0;
+
+        let s = (x + y + z) * f3;
+        let i = (x + s).floor();
+        let j = (y + s).floor();
+        let k = (z + s).floor();
+        let t = (i + j + k) * g3;
+        let x0 = [x - (i - t), y - (j - t), z - (k - t)];
+
+        // Walk the simplex corners from the origin, stepping along the largest offset first.
+        let order = rank(&x0);
+        let base = [i as i64, j as i64, k as i64];
+        let mut offset = [0_i64; 3];
+        let mut n = 0.0;
+        for corner in 0..4 {
+            if corner > 0 {
+                offset[order[corner - 1]] = 1;
+            }
+            let d: [f64; 3] =
+                std::array::from_fn(|a| x0[a] - offset[a] as f64 + corner as f64 * g3);
+            let t = 0.6 - d.iter().map(|c| c * c).sum::<f64>();
+            if t > 0.0 {
+                let h = self.hash(
+                    base[0]
+                        + offset[0]
+                        + self.hash(base[1] + offset[1] + self.hash(base[2] + offset[2]) as i64)
+                            as i64,
+                );
+                let g = GRAD3[h % 12];
+                n += t.powi(4) * (g[0] * d[0] + g[1] * d[1] + g[2] * d[2]);
+            }
+        }
+        32.0 * n
+    }
+
+    pub fn noise4(&self, x: f64, y: f64, z: f64, w: f64) -> f64 {
+        let f4 = (5.0_f64.sqrt() - 1.0) / 4.0;
+        let g4 = (5.0 - 5.0_f64.sqrt()) / 20.0;
+
+        let s = (x + y + z + w) * f4;
+        let cell = [
+            (x + s).floor(),
+            (y + s).floor(),
+            (z + s).floor(),
+            (w + s).floor(),
+        ];
+        let t = cell.iter().sum::<f64>() * g4;
+        let x0 = [
+            x - (cell[0] - t),
+            y - (cell[1] - t),
+            z - (cell[2] - t),
+            w - (cell[3] - t),
+        ];
+
+        let order = rank(&x0);
+        let base = cell.map(|c| c as i64);
+        let mut offset = [0_i64; 4];
+        let mut n = 0.0;
+        for corner in 0..5 {
+            if corner > 0 {
+                offset[order[corner - 1]] = 1;
+            }
+            let d: [f64; 4] =
+                std::array::from_fn(|a| x0[a] - offset[a] as f64 + corner as f64 * g4);
+            let t = 0.6 - d.iter().map(|c| c * c).sum::<f64>();
+            if t > 0.0 {
+                let mut h = 0;
+                for a in (0..4).rev() {
+                    h = self.hash(base[a] + offset[a] + h as i64);
+                }
+                n += t.powi(4) * grad4_dot(h, &d);
+            }
+        }
+        27.0 * n
+    }
+}
+
+/// Axis indices sorted by decreasing coordinate.
+fn rank<const N: usize>(x: &[f64; N]) -> [usize; N] {
+    let mut order = [0; N];
+    for (a, o) in order.iter_mut().enumerate() {
+        *o = a;
+    }
+    order.sort_by(|&a, &b| x[b].total_cmp(&x[a]));
+    order
+}
+
+/// Dot product with one of the 32 gradients pointing at the edges of a tesseract: one
+/// component zero, the other three `±1`.
+pub(crate) fn grad4_dot(h: usize, d: &[f64; 4]) -> f64 {
+    let h = h & 31;
+    let zero = h >> 3;
+    let mut sum = 0.0;
+    let mut bit = 0;
+    for (a, c) in d.iter().enumerate() {
+        if a == zero {
+            continue;
+        }
+        sum += if h & (1 << bit) == 0 { *c } else { -*c };
+        bit += 1;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simplex_range_and_seed() {
+        let a = Simplex::with_seed(3);
+        let b = Simplex::with_seed(3);
+        let mut max: f64 = 0.0;
+        for i in 0..2000 {
+            let x = i as f64 * 0.137;
+            let (n2, n3, n4) = (
+                a.noise2(x, -x * 0.5),
+                a.noise3(x, x * 0.3, -x),
+                a.noise4(x, -x, x * 0.7, x * 0.2),
+            );
+            assert_eq!(n4, b.noise4(x, -x, x * 0.7, x * 0.2));
+            max = max.max(n2.abs()).max(n3.abs()).max(n4.abs());
+        }
+        assert!(max > 0.3 && max <= 1.1, "{}", max);
+    }
+}
@@ -0,0 +1,290 @@
+pub use crate::environment::Environment;
+pub use crate::onb::Onb;
+use crate::rtweekend::{degrees_to_radians, random_f64};
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+
+/// Angular radius of the sun seen from the ground.
+const SUN_RADIUS: f64 = 0.004_65;
+/// Luminance of the sun outside the atmosphere, in kcd/m².
+const SUN_LUMINANCE: f64 = 1.6e6;
+
+/// Perez sky-luminance distribution for one of Y, x or y.
+#[derive(Clone, Copy)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        (1.0 + self.a * (self.b / cos_theta.max(0.01)).exp())
+            * (1.0 + self.c * (self.d * gamma).exp() + self.e * gamma.cos().powi(2))
+    }
+}
+
+/// Preetham, Shirley and Smits' analytic daylight sky with a sun disk. Radiance comes out
+/// in kcd/m² times `intensity`; the default scale keeps a white surface in full sun just
+/// under one.
+pub struct SkyEnvironment {
+    sun: Vec3,
+    turbidity: f64,
+    ground_albedo: Vec3,
+    intensity: f64,
+    perez: [Perez; 3],
+    zenith: [f64; 3],
+    sun_radiance: Vec3,
+}
+
+impl SkyEnvironment {
+    /// `sun_direction` points towards the sun; `turbidity` runs from about 2 (very clear)
+    /// to 10 (hazy).
+    pub fn new(sun_direction: Vec3, turbidity: f64, ground_albedo: Vec3) -> Self {
+        let mut sky = Self {
+            sun: sun_direction.unit(),
+            turbidity: turbidity.clamp(1.7, 10.0),
+            ground_albedo,
+            intensity: 0.025,
+            perez: [Perez {
+                a: 0.0,
+                b: 0.0,
+                c: 0.0,
+                d: 0.0,
+                e: 0.0,
+            }; 3],
+            zenith: [0.0; 3],
+            sun_radiance: Vec3::zero(),
+        };
+        sky.update();
+        sky
+    }
+
+    /// Places the sun from the day of the year (1-365), local solar time in hours and the
+    /// latitude in degrees. +x is east, -z is north.
+    pub fn from_time(
+        day_of_year: u32,
+        hour: f64,
+        latitude: f64,
+        turbidity: f64,
+        ground_albedo: Vec3,
+    ) -> Self {
+        SkyEnvironment::new(
+            sun_direction(day_of_year, hour, latitude),
+            turbidity,
+            ground_albedo,
+        )
+    }
+
+    pub fn set_intensity(&mut self, intensity: f64) {
+        self.intensity = intensity;
+    }
+
+    pub fn set_sun_direction(&mut self, sun_direction: Vec3) {
+        self.sun = sun_direction.unit();
+        self.update();
+    }
+
+    pub fn set_turbidity(&mut self, turbidity: f64) {
+        self.turbidity = turbidity.clamp(1.7, 10.0);
+        self.update();
+    }
+
+    fn update(&mut self) {
+        let t = self.turbidity;
+        self.perez = [
+            Perez {
+                a: 0.1787 * t - 1.4630,
+                b: -0.3554 * t + 0.4275,
+                c: -0.0227 * t + 5.3251,
+                d: 0.1206 * t - 2.5771,
+                e: -0.0670 * t + 0.3703,
+            },
+            Perez {
+                a: -0.0193 * t - 0.2592,
+                b: -0.0665 * t + 0.0008,
+                c: -0.0004 * t + 0.2125,
+                d: -0.0641 * t - 0.8989,
+                e: -0.0033 * t + 0.0452,
+            },
+            Perez {
+                a: -0.0167 * t - 0.2608,
+                b: -0.0950 * t + 0.0092,
+                c: -0.0079 * t + 0.2102,
+                d: -0.0441 * t - 1.6537,
+                e: -0.0109 * t + 0.0529,
+            },
+        ];
+
+        // The fits only hold with the sun above the horizon.
+        let theta_s = self.sun.y().clamp(0.0, 1.0).acos();
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+        let big_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+        let cubic =
+            |c: [f64; 4]| c[0] * theta_s.powi(3) + c[1] * theta_s.powi(2) + c[2] * theta_s + c[3];
+        let x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+        self.zenith = [big_y, x, y];
+
+        self.sun_radiance = if self.sun.y() > -SUN_RADIUS {
+            sun_transmittance(self.sun.y(), t) * SUN_LUMINANCE
+        } else {
+            Vec3::zero()
+        };
+    }
+
+    /// Sky radiance in kcd/m², without the sun disk.
+    fn sky(&self, d: &Vec3) -> Vec3 {
+        let cos_theta = d.y().max(0.0);
+        let gamma = (*d * self.sun).clamp(-1.0, 1.0).acos();
+        let theta_s = self.sun.y().clamp(0.0, 1.0).acos();
+        let mut xyy = [0.0; 3];
+        for (i, c) in xyy.iter_mut().enumerate() {
+            *c = self.zenith[i] * self.perez[i].f(cos_theta, gamma) / self.perez[i].f(1.0, theta_s);
+        }
+        xyy_to_rgb(xyy[1], xyy[2], xyy[0])
+    }
+
+    fn sun_probability(&self) -> f64 {
+        if self.sun_radiance.near_zero() {
+            0.0
+        } else {
+            0.5
+        }
+    }
+}
+
+impl Environment for SkyEnvironment {
+    fn radiance(&self, direction: &Vec3) -> Vec3 {
+        let d = direction.unit();
+        if d.y() < 0.0 {
+            // Diffuse ground lit by the sun and, roughly, the zenith sky.
+            let sun = self.sun_radiance * (solid_angle() * self.sun.y().max(0.0));
+            let sky = self.sky(&Vec3::new(0.0, 1.0, 0.0)) * PI;
+            return Vec3::elemul(&self.ground_albedo, &(sun + sky)) * (self.intensity / PI);
+        }
+        let mut radiance = self.sky(&d);
+        if d * self.sun >= SUN_RADIUS.cos() {
+            radiance += self.sun_radiance;
+        }
+        radiance * self.intensity
+    }
+
+    /// Half the samples go to the sun disk, the rest uniformly over the sphere.
+    fn sample(&self) -> (Vec3, f64) {
+        let direction = if random_f64() < self.sun_probability() {
+            let cos_theta = 1.0 - random_f64() * (1.0 - SUN_RADIUS.cos());
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * PI * random_f64();
+            Onb::build_from_w(&self.sun).local(&Vec3::new(
+                sin_theta * phi.cos(),
+                sin_theta * phi.sin(),
+                cos_theta,
+            ))
+        } else {
+            Vec3::random_unit_vector()
+        };
+        (direction, self.pdf(&direction))
+    }
+
+    fn pdf(&self, direction: &Vec3) -> f64 {
+        let p_sun = self.sun_probability();
+        let mut pdf = (1.0 - p_sun) / (4.0 * PI);
+        if direction.unit() * self.sun >= SUN_RADIUS.cos() {
+            pdf += p_sun / solid_angle();
+        }
+        pdf
+    }
+}
+
+fn solid_angle() -> f64 {
+    2.0 * PI * (1.0 - SUN_RADIUS.cos())
+}
+
+/// Rayleigh and aerosol (Ångström) extinction along the path to the sun, at 650, 550 and
+/// 450 nm for red, green and blue.
+fn sun_transmittance(cos_theta: f64, turbidity: f64) -> Vec3 {
+    let theta = cos_theta.clamp(0.0, 1.0).acos().to_degrees();
+    let air_mass = 1.0 / (cos_theta.max(0.0) + 0.15 * (93.885 - theta).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let tau = |lambda: f64| {
+        let rayleigh = 0.008735 * lambda.powf(-4.08);
+        let aerosol = beta * lambda.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    };
+    Vec3::new(tau(0.65), tau(0.55), tau(0.45))
+}
+
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Vec3 {
+    if y <= 0.0 {
+        return Vec3::zero();
+    }
+    let big_x = x * big_y / y;
+    let big_z = (1.0 - x - y) * big_y / y;
+    Vec3::new(
+        (3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z).max(0.0),
+        (-0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z).max(0.0),
+        (0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z).max(0.0),
+    )
+}
+
+/// Unit vector towards the sun, using the usual declination and hour-angle approximation.
+pub fn sun_direction(day_of_year: u32, hour: f64, latitude: f64) -> Vec3 {
+    let declination =
+        degrees_to_radians(23.44) * (2.0 * PI * (284.0 + day_of_year as f64) / 365.0).sin();
+    let hour_angle = degrees_to_radians(15.0 * (hour - 12.0));
+    let latitude = degrees_to_radians(latitude);
+
+    let up =
+        latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos();
+    let east = -declination.cos() * hour_angle.sin();
+    let north =
+        declination.sin() * latitude.cos() - declination.cos() * latitude.sin() * hour_angle.cos();
+    Vec3::new(east, up, -north).unit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sun_position() {
+        // Equinox noon at the equator: overhead. Northern summer noon: sun to the south.
+        let noon = sun_direction(80, 12.0, 0.0);
+        assert!(noon.y() > 0.99, "{:?}", noon.y());
+        let summer = sun_direction(172, 12.0, 60.0);
+        assert!(summer.z() > 0.0 && summer.y() > 0.7);
+        let morning = sun_direction(172, 8.0, 40.0);
+        assert!(morning.x() > 0.0);
+    }
+
+    #[test]
+    fn test_sky_is_bluer_than_sunset() {
+        let noon = SkyEnvironment::new(Vec3::new(0.0, 1.0, 0.3), 2.5, Vec3::ones() * 0.3);
+        let zenith = noon.radiance(&Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z() > zenith.x());
+        let sun = noon.radiance(&Vec3::new(0.0, 1.0, 0.3));
+        assert!(sun.y() > 1000.0 * zenith.y());
+        let (d, pdf) = noon.sample();
+        assert!((noon.pdf(&d) - pdf).abs() < 1e-9 * pdf);
+
+        // A low sun shines through more air, which scatters its blue away.
+        let low = Vec3::new(0.0, 0.05, 1.0);
+        let sunset = SkyEnvironment::new(low, 2.5, Vec3::ones() * 0.3);
+        let red_sun = sunset.radiance(&low);
+        assert!(red_sun.x() > red_sun.z());
+        assert!(
+            red_sun.x() / red_sun.z() > sun.x() / sun.z(),
+            "{:?} {:?}",
+            red_sun,
+            sun
+        );
+    }
+}
@@ -0,0 +1,250 @@
+pub use crate::camera::Projection;
+pub use crate::environment::Environment;
+pub use crate::hittable_list::HittableList;
+pub use crate::light::LightList;
+use crate::photon_map::{shoot_photons, visible_point, PhotonKind, PhotonMap, VisiblePoint};
+use crate::rtweekend::random_f64;
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::thread;
+
+/// Fraction of the photons found in a pass that count towards shrinking the radius.
+const ALPHA: f64 = 2.0 / 3.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SppmSettings {
+    pub passes: usize,
+    pub photons_per_pass: usize,
+    /// Gather radius of every pixel in the first pass, in scene units.
+    pub initial_radius: f64,
+    pub max_depth: u32,
+}
+
+impl Default for SppmSettings {
+    fn default() -> Self {
+        Self {
+            passes: 64,
+            photons_per_pass: 200_000,
+            initial_radius: 0.1,
+            max_depth: 10,
+        }
+    }
+}
+
+/// Stochastic progressive photon mapping. Every pass traces a new visible point per pixel
+/// and a new set of photons; each pixel keeps its own gather radius, which shrinks as photons
+/// arrive so the estimate converges to the right answer instead of a blurred one.
+pub struct Sppm {
+    settings: SppmSettings,
+}
+
+struct Pixel {
+    radius: f64,
+    /// Light found by the camera rays themselves, summed over passes.
+    direct: Vec3,
+    /// Photons counted so far, scaled down by `ALPHA` each pass.
+    n: f64,
+    /// Reflected photon power within the current radius.
+    tau: Vec3,
+    point: Option<VisiblePoint>,
+}
+
+impl Sppm {
+    pub fn new(settings: SppmSettings) -> Self {
+        Self { settings }
+    }
+
+    pub fn settings(&self) -> &SppmSettings {
+        &self.settings
+    }
+
+    /// Renders the whole image, row by row from the bottom, as radiance per pixel. `on_pass`
+    /// is called with the number of passes done after each one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render(
+        &self,
+        cam: &dyn Projection,
+        width: usize,
+        height: usize,
+        background: &dyn Environment,
+        lights: &LightList,
+        world: &HittableList,
+        on_pass: &mut dyn FnMut(usize),
+    ) -> Vec<Vec3> {
+        let settings = &self.settings;
+        let mut pixels: Vec<Pixel> = (0..width * height)
+            .map(|_| Pixel {
+                radius: settings.initial_radius,
+                direct: Vec3::zero(),
+                n: 0.0,
+                tau: Vec3::zero(),
+                point: None,
+            })
+            .collect();
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = (height.div_ceil(threads) * width).max(1);
+
+        for pass in 0..settings.passes {
+            thread::scope(|scope| {
+                for (c, pixels) in pixels.chunks_mut(chunk).enumerate() {
+                    scope.spawn(move || {
+                        for (k, pixel) in pixels.iter_mut().enumerate() {
+                            let (i, j) = ((c * chunk + k) % width, (c * chunk + k) / width);
+                            let u = (i as f64 + random_f64()) / width as f64;
+                            let v = (j as f64 + random_f64()) / height as f64;
+                            let (r, weight) = cam.get_ray_weighted(u, v);
+                            let mut found = Vec3::zero();
+                            pixel.point = visible_point(
+                                &r,
+                                background,
+                                lights,
+                                world,
+                                settings.max_depth,
+                                &mut found,
+                            );
+                            pixel.direct += Vec3::elemul(&weight, &found);
+                            if let Some(point) = pixel.point.as_mut() {
+                                point.beta = Vec3::elemul(&weight, &point.beta);
+                            }
+                        }
+                    });
+                }
+            });
+
+            // Direct light was sampled at the visible points; photons only bring the rest.
+            let map = PhotonMap::new(shoot_photons(
+                settings.photons_per_pass,
+                PhotonKind::Indirect,
+                settings.max_depth,
+                lights,
+                world,
+            ));
+            let map = &map;
+            thread::scope(|scope| {
+                for pixels in pixels.chunks_mut(chunk) {
+                    scope.spawn(move || {
+                        for pixel in pixels.iter_mut() {
+                            let point = match pixel.point.take() {
+                                Some(point) => point,
+                                None => continue,
+                            };
+                            let (power, m) = map.reflected(
+                                &point.ray,
+                                &point.rec,
+                                point.mat.as_ref(),
+                                pixel.radius,
+                            );
+                            if m == 0 {
+                                continue;
+                            }
+                            let n = pixel.n + ALPHA * m as f64;
+                            let radius = pixel.radius * (n / (pixel.n + m as f64)).sqrt();
+                            let shrink = (radius / pixel.radius).powi(2);
+                            pixel.tau = (pixel.tau + Vec3::elemul(&point.beta, &power)) * shrink;
+                            pixel.n = n;
+                            pixel.radius = radius;
+                        }
+                    });
+                }
+            });
+            on_pass(pass + 1);
+        }
+
+        let passes = settings.passes.max(1) as f64;
+        pixels
+            .iter()
+            .map(|p| p.direct / passes + p.tau / (passes * PI * p.radius * p.radius))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aarect::Xzrect;
+    use crate::camera::Camera;
+    use crate::environment::ConstantEnvironment;
+    use crate::hiitable::Hiitable;
+    use crate::integrator::Integrator;
+    use crate::material::{Dielectric, DiffLight, FlipFace, Lambertian};
+    use crate::object::Sphere;
+    use crate::path_tracer::{PathSettings, PathTracer};
+
+    use std::sync::Arc;
+
+    #[test]
+    fn test_converges_to_path_tracing() {
+        // A glass ball casting a caustic on a floor under a small lamp.
+        let mut world = HittableList::new();
+        world.add(Some(Arc::new(Xzrect::new(
+            -2.0,
+            2.0,
+            -2.0,
+            2.0,
+            0.0,
+            Some(Arc::new(Lambertian::new1(&(Vec3::ones() * 0.5)))),
+        ))));
+        world.add(Some(Arc::new(Sphere::new(
+            &Vec3::new(0.3, 0.4, 0.0),
+            0.4,
+            Some(Arc::new(Dielectric::new(1.5))),
+        ))));
+        let lamp: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(FlipFace::new(Some(Arc::new(Xzrect::new(
+                -0.3,
+                0.3,
+                -0.3,
+                0.3,
+                1.5,
+                Some(Arc::new(DiffLight::new2(Vec3::ones() * 8.0))),
+            ))))));
+        world.add(lamp.clone());
+        let mut lights = LightList::new();
+        lights.add_area(lamp);
+        let cam = Camera::new(
+            1.0,
+            &Vec3::new(0.0, 1.0, -3.0),
+            &Vec3::zero(),
+            &Vec3::new(0.0, 1.0, 0.0),
+            50.0,
+            0.0,
+            (3.0, 0.0, 1.0),
+        );
+        let background = ConstantEnvironment::new(Vec3::zero());
+
+        let reference = PathTracer::new(PathSettings::default());
+        let spp = 4000;
+        let mut expected = 0.0;
+        for j in 0..4 {
+            for i in 0..4 {
+                for _ in 0..spp {
+                    let u = (i as f64 + random_f64()) / 4.0;
+                    let v = (j as f64 + random_f64()) / 4.0;
+                    let r = cam.get_ray(u, v);
+                    expected += reference.radiance(&r, &background, &lights, &world).x();
+                }
+            }
+        }
+        expected /= spp as f64;
+
+        let sppm = Sppm::new(SppmSettings {
+            passes: 200,
+            photons_per_pass: 5_000,
+            initial_radius: 0.3,
+            ..SppmSettings::default()
+        });
+        let mut done = 0;
+        let image = sppm.render(&cam, 4, 4, &background, &lights, &world, &mut |pass| {
+            done = pass
+        });
+        assert_eq!(done, 200);
+        let found: f64 = image.iter().map(|p| p.x()).sum();
+        assert!(
+            (found - expected).abs() < 0.08 * expected,
+            "{} vs {}",
+            found,
+            expected
+        );
+    }
+}
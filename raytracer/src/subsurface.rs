@@ -0,0 +1,287 @@
+use crate::aabb::AAbb;
+use crate::constant_medium::boundary_exit;
+use crate::error::{Error, Result};
+pub use crate::hiitable::{Hiitable, HitRecord};
+pub use crate::material::{beer_lambert, Dielectric, Material};
+pub use crate::onb::Onb;
+pub use crate::phase::{IsotropicPhase, PhaseFunction};
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::vec3::Vec3;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+/// Walks still inside after this many scattering events are treated as absorbed.
+const MAX_STEPS: usize = 256;
+
+/// A translucent object such as wax, skin, marble or milk: light refracts into `boundary`,
+/// random-walks through a scattering medium inside it and refracts out somewhere else.
+/// `boundary` must be a closed shape given in world space, so wrap any `Translate` or
+/// `Rotatey` inside it rather than around it.
+///
+/// The walk has no closed-form density, so integrators treat the surface as specular: no
+/// light is sampled at the exit point and lights are only found by the exit ray hitting
+/// them. Objects lit by small or point lights converge slowly or stay dark.
+pub struct Subsurface {
+    boundary: Option<Arc<dyn Hiitable>>,
+    material: Option<Arc<dyn Material>>,
+}
+
+impl Subsurface {
+    /// `albedo` is the colour the object ends up with once all the scattering is done, and
+    /// `mean_free_path` how far light travels inside between events, per channel.
+    pub fn new(
+        boundary: Option<Arc<dyn Hiitable>>,
+        albedo: Vec3,
+        mean_free_path: Vec3,
+        ir: f64,
+    ) -> Result<Self> {
+        Subsurface::with_phase(
+            boundary,
+            albedo,
+            mean_free_path,
+            ir,
+            Some(Arc::new(IsotropicPhase)),
+        )
+    }
+
+    pub fn with_phase(
+        boundary: Option<Arc<dyn Hiitable>>,
+        albedo: Vec3,
+        mean_free_path: Vec3,
+        ir: f64,
+        phase: Option<Arc<dyn PhaseFunction>>,
+    ) -> Result<Self> {
+        let mut sigma_t = [0.0; 3];
+        let mut sigma_s = [0.0; 3];
+        for c in 0..3 {
+            let mfp = mean_free_path[c];
+            if !(mfp > 0.0 && mfp.is_finite()) {
+                return Err(Error::InvalidDensity(1.0 / mfp));
+            }
+            sigma_t[c] = 1.0 / mfp;
+            sigma_s[c] = sigma_t[c] * single_scattering_albedo(albedo[c]);
+        }
+        let walk = RandomWalk {
+            boundary: boundary.clone(),
+            sigma_t: Vec3::new(sigma_t[0], sigma_t[1], sigma_t[2]),
+            sigma_s: Vec3::new(sigma_s[0], sigma_s[1], sigma_s[2]),
+            ir,
+            phase,
+        };
+        Ok(Self {
+            boundary,
+            material: Some(Arc::new(walk)),
+        })
+    }
+}
+
+impl Hiitable for Subsurface {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self
+            .boundary
+            .as_ref()
+            .is_some_and(|b| b.hit(r, t_min, t_max, rec))
+        {
+            return false;
+        }
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AAbb) -> bool {
+        self.boundary
+            .as_ref()
+            .is_some_and(|b| b.bounding_box(time0, time1, output_box))
+    }
+}
+
+/// Maps the multiple-scattering albedo an artist picks to the single-scattering albedo of
+/// the medium, with the fit used by Cycles' random walk.
+fn single_scattering_albedo(albedo: f64) -> f64 {
+    let a = albedo.clamp(0.0, 0.999);
+    let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+    (1.0 - s * s).clamp(0.0, 1.0)
+}
+
+/// The surface of a `Subsurface` object. Its `scatter` follows the whole walk, so one
+/// bounce of the integrator goes in at one point and out at another.
+struct RandomWalk {
+    boundary: Option<Arc<dyn Hiitable>>,
+    sigma_t: Vec3,
+    sigma_s: Vec3,
+    ir: f64,
+    phase: Option<Arc<dyn PhaseFunction>>,
+}
+
+impl RandomWalk {
+    fn sample_phase(&self, forward: &Vec3) -> Vec3 {
+        let cos_theta = self
+            .phase
+            .as_ref()
+            .map_or(1.0 - 2.0 * random_f64(), |p| p.sample_cos_theta());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * random_f64();
+        Onb::build_from_w(forward).local(&Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+
+    /// Follows light from `origin` heading along `direction` inside the boundary until it
+    /// leaves. Returns the exit ray and the throughput, or `None` if it was absorbed.
+    /// Distances are sampled per channel, since the medium is coloured, and the exit is
+    /// found with the crossing count `ConstantMedium` uses.
+    fn walk(
+        &self,
+        boundary: &dyn Hiitable,
+        origin: Vec3,
+        direction: Vec3,
+        tm: f64,
+    ) -> Option<(Ray, Vec3)> {
+        let mut throughput = Vec3::ones();
+        let mut ray = Ray::new(origin, direction, tm);
+        for _ in 0..MAX_STEPS {
+            // Pick the channel to sample distances with in proportion to its throughput and
+            // divide by the pdf averaged over all three, so coloured media stay unbiased
+            // without one channel's weight running away.
+            let total = throughput.x() + throughput.y() + throughput.z();
+            if total <= 0.0 {
+                return None;
+            }
+            let weights = throughput / total;
+            let xi = random_f64();
+            let channel = if xi < weights.x() {
+                0
+            } else if xi < weights.x() + weights.y() {
+                1
+            } else {
+                2
+            };
+            let t = -(1.0 - random_f64()).ln() / self.sigma_t[channel];
+            let rec = boundary_exit(boundary, &ray, 0.0001)?;
+            if rec.t <= t {
+                let transmittance = beer_lambert(&self.sigma_t, rec.t);
+                let pdf = weights * transmittance;
+                throughput = Vec3::elemul(&throughput, &transmittance) / pdf;
+
+                // Hit records face their normal against the ray, here back into the object.
+                let unit = ray.direc().unit();
+                let cos_theta = (-unit * rec.normal).min(1.0);
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                if self.ir * sin_theta > 1.0
+                    || Dielectric::reflectance(cos_theta, self.ir) > random_f64()
+                {
+                    ray = Ray::new(rec.point3, Vec3::reflect(&unit, &rec.normal), tm);
+                    continue;
+                }
+                let out = Vec3::refract(&unit, &rec.normal, self.ir);
+                return Some((Ray::new(rec.point3, out, tm), throughput));
+            }
+
+            let transmittance = beer_lambert(&self.sigma_t, t);
+            let density = Vec3::elemul(&self.sigma_t, &transmittance);
+            let pdf = weights * density;
+            throughput =
+                Vec3::elemul(&throughput, &Vec3::elemul(&self.sigma_s, &transmittance)) / pdf;
+            if throughput.near_zero() {
+                return None;
+            }
+            let position = ray.at(t);
+            ray = Ray::new(position, self.sample_phase(&ray.direc().unit()), tm);
+        }
+        None
+    }
+}
+
+impl Material for RandomWalk {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &mut HitRecord,
+        attenuation: &mut Vec3,
+        scattered: &mut Ray,
+    ) -> bool {
+        let boundary = match &self.boundary {
+            Some(b) => b,
+            None => return false,
+        };
+        let unit = r_in.direc().unit();
+        let cos_theta = (-unit * rec.normal).clamp(0.0, 1.0);
+        if Dielectric::reflectance(cos_theta, 1.0 / self.ir) > random_f64() {
+            *attenuation = Vec3::ones();
+            *scattered = Ray::new(rec.point3, Vec3::reflect(&unit, &rec.normal), r_in.tm());
+            return true;
+        }
+        let inside = Vec3::refract(&unit, &rec.normal, 1.0 / self.ir);
+        match self.walk(boundary.as_ref(), rec.point3, inside, r_in.tm()) {
+            Some((exit, throughput)) => {
+                *attenuation = throughput;
+                *scattered = exit;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+        Vec3::zero()
+    }
+
+    /// See `Subsurface`: the exit direction has no density to weigh light samples with.
+    fn is_specular(&self, _rec: &HitRecord) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Sphere;
+
+    #[test]
+    fn test_albedo_inversion_is_monotonic() {
+        assert!(single_scattering_albedo(0.0) < 1e-3);
+        assert!(single_scattering_albedo(0.8) > single_scattering_albedo(0.5));
+        assert!(single_scattering_albedo(0.999) <= 1.0);
+    }
+
+    fn mean_exit(albedo: f64, ir: f64, n: usize) -> (usize, f64) {
+        let sphere: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(Sphere::new(&Vec3::zero(), 1.0, None)));
+        let object =
+            Subsurface::new(sphere, Vec3::ones() * albedo, Vec3::ones() * 0.3, ir).unwrap();
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let mut exits = 0;
+        let mut energy = 0.0;
+        for _ in 0..n {
+            let mut rec = HitRecord::new();
+            assert!(object.hit(&r, 0.001, f64::INFINITY, &mut rec));
+            let mat = rec.mat.clone().unwrap();
+            let mut attenuation = Vec3::zero();
+            let mut scattered = Ray::new(Vec3::zero(), Vec3::zero(), 0.0);
+            if mat.scatter(&r, &mut rec, &mut attenuation, &mut scattered) {
+                exits += 1;
+                energy += attenuation.x();
+                assert!((scattered.ori().length() - 1.0).abs() < 1e-3);
+            }
+        }
+        (exits, energy / n as f64)
+    }
+
+    #[test]
+    fn test_white_walk_conserves_energy() {
+        // Nothing is absorbed and no interface reflects, so every walk leaves with its
+        // weight intact.
+        let n = 2000;
+        let (exits, mean) = mean_exit(1.0, 1.0, n);
+        assert!(exits > n * 99 / 100, "{}", exits);
+        assert!((mean - 1.0).abs() < 0.01, "{}", mean);
+
+        // Absorption and a refracting surface only ever take energy away.
+        let (_, lossy) = mean_exit(0.5, 1.3, n);
+        assert!(lossy < mean_exit(0.8, 1.3, n).1 && lossy < 0.9, "{}", lossy);
+    }
+}
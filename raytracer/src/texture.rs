@@ -1,10 +1,60 @@
-pub use crate::color::clamp;
 pub use crate::perlin::Perlin;
+use crate::rtweekend::degrees_to_radians;
 pub use crate::vec3::Vec3;
 
-use std::sync::Arc;
+use crate::error::{open_image, Error, Result};
+use image::{ColorType, DynamicImage};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Shown wherever a texture is missing, so the gap is obvious in the render.
+pub const MISSING_TEXTURE: Vec3 = Vec3 {
+    x: 1.0,
+    y: 0.0,
+    z: 1.0,
+};
+
+/// Everything a lookup may depend on, see `HitRecord::tex_coord`.
+#[derive(Clone, Copy)]
+pub struct TexCoord {
+    pub u: f64,
+    pub v: f64,
+    pub p: Vec3,
+    /// Surface normal, for projections such as `TriplanarTexture`.
+    pub normal: Vec3,
+    /// Footprint in uv units, as for `Texture::value_filtered`.
+    pub width: f64,
+    /// Ray time, for textures that animate.
+    pub tm: f64,
+}
+
+impl TexCoord {
+    pub fn new(u: f64, v: f64, p: &Vec3) -> Self {
+        Self {
+            u,
+            v,
+            p: *p,
+            normal: Vec3::zero(),
+            width: 0.0,
+            tm: 0.0,
+        }
+    }
+}
+
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3;
+
+    /// Lookup averaged over a footprint `width` wide in uv units (see
+    /// `HitRecord::uv_footprint`). Textures without prefiltering point sample.
+    fn value_filtered(&self, u: f64, v: f64, p: &Vec3, _width: f64) -> Vec3 {
+        self.value(u, v, p)
+    }
+
+    /// Full lookup used by materials. Textures that combine other textures forward the
+    /// whole coordinate so filtering, time and normals reach the leaves.
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        self.value_filtered(c.u, c.v, &c.p, c.width)
+    }
 }
 
 #[derive(Clone)]
@@ -57,12 +107,14 @@ impl CheckerTexture {
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.eval(&TexCoord::new(u, v, p))
+    }
+
+    fn eval(&self, c: &TexCoord) -> Vec3 {
+        let p = c.p;
         let sines = (p.x() * 10.0).sin() * (p.y() * 10.0).sin() * (p.z() * 10.0).sin();
-        if sines < 0.0 {
-            self.odd.clone().unwrap().value(u, v, p)
-        } else {
-            self.even.clone().unwrap().value(u, v, p)
-        }
+        let side = if sines < 0.0 { &self.odd } else { &self.even };
+        side.as_ref().map_or(MISSING_TEXTURE, |t| t.eval(c))
     }
 }
 
@@ -78,74 +130,488 @@ impl NoiseTexture {
             scale: sc,
         }
     }
+
+    /// Replaces the lattice, e.g. with a seeded or quintic `Perlin`.
+    pub fn set_noise(&mut self, noise: Perlin) {
+        self.noise = noise;
+    }
 }
 
 impl Texture for NoiseTexture {
     fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
-        //Vec3::new(1.0, 1.0, 1.0) * self.noise.turb(&(p.clone() * self.scale), 7)
         Vec3::new(1.0, 1.0, 1.0)
             * 0.5
-            * (1.0 + (self.scale * p.z() + self.noise.turb(p, 7) * 10.0).sin())
+            * (1.0 + (self.scale * p.z() + self.noise.turb(&(*p * self.scale), 7) * 10.0).sin())
     }
 }
 
-pub struct ImageTexture {
-    data: Vec<u8>,
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    /// Catmull-Rom over a 4x4 neighbourhood.
+    Bicubic,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum ColorSpace {
+    /// 8- and 16-bit colour images; decoded to linear on load.
+    Srgb,
+    /// Float images and data maps (normals, roughness, masks); used as stored.
+    Linear,
+}
+
+pub fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+struct MipLevel {
     width: i32,
     height: i32,
-    bytes_per_scanline: i32,
+    texels: Vec<Vec3>,
+    alpha: Vec<f64>,
+}
+
+impl MipLevel {
+    /// 2x2 box filter; odd edges reuse their last row or column.
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        let mut alpha = Vec::with_capacity((width * height) as usize);
+        for j in 0..height {
+            for i in 0..width {
+                let mut sum = Vec3::zero();
+                let mut sum_alpha = 0.0;
+                for (di, dj) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let x = (2 * i + di).min(self.width - 1);
+                    let y = (2 * j + dj).min(self.height - 1);
+                    sum += self.texels[(y * self.width + x) as usize];
+                    sum_alpha += self.alpha[(y * self.width + x) as usize];
+                }
+                texels.push(sum / 4.0);
+                alpha.push(sum_alpha / 4.0);
+            }
+        }
+        MipLevel {
+            width,
+            height,
+            texels,
+            alpha,
+        }
+    }
+}
+
+/// An image decoded to linear floating point, with its mip pyramid. Decoded images are
+/// shared between textures through `TextureCache`.
+pub struct ImageTexture {
+    levels: Arc<Vec<MipLevel>>,
+    read_alpha: bool,
+    filter: TextureFilter,
+    wrap: WrapMode,
+    mipmap: bool,
+    uv_scale: (f64, f64),
+    uv_offset: (f64, f64),
+    uv_rotation: f64,
 }
 
 impl ImageTexture {
     pub fn new_0() -> Self {
         Self {
-            data: vec![0_u8; 0],
-            width: 0,
-            height: 0,
-            bytes_per_scanline: 0,
+            levels: Arc::new(Vec::new()),
+            read_alpha: false,
+            filter: TextureFilter::Bilinear,
+            wrap: WrapMode::Clamp,
+            mipmap: true,
+            uv_scale: (1.0, 1.0),
+            uv_offset: (0.0, 0.0),
+            uv_rotation: 0.0,
         }
     }
 
-    pub fn new(filename: &str) -> Self {
-        let photo = image::open(filename).unwrap();
+    /// Loads a colour image. Integer formats are treated as sRGB, float formats
+    /// (HDR, EXR) as linear.
+    pub fn new(filename: &str) -> Result<Self> {
+        Ok(ImageTexture::from_levels(ImageTexture::load_levels(
+            filename, None,
+        )?))
+    }
+
+    /// Loads an image whose values are data rather than colour, e.g. a normal map.
+    pub fn new_linear(filename: &str) -> Result<Self> {
+        Ok(ImageTexture::from_levels(ImageTexture::load_levels(
+            filename,
+            Some(ColorSpace::Linear),
+        )?))
+    }
+
+    fn load_levels(filename: &str, space: Option<ColorSpace>) -> Result<Arc<Vec<MipLevel>>> {
+        let photo = open_image(filename)?;
+        match photo.color() {
+            ColorType::L8
+            | ColorType::La8
+            | ColorType::Rgb8
+            | ColorType::Rgba8
+            | ColorType::L16
+            | ColorType::La16
+            | ColorType::Rgb16
+            | ColorType::Rgba16
+            | ColorType::Rgb32F
+            | ColorType::Rgba32F => Ok(ImageTexture::from_image(&photo, space).levels),
+            color => Err(Error::UnsupportedPixelFormat {
+                path: filename.to_string(),
+                color,
+            }),
+        }
+    }
+
+    /// Decodes any supported pixel format (grayscale, RGB, RGBA, 8/16-bit, float) to
+    /// linear RGB plus alpha. `space` of `None` picks by sample type.
+    pub fn from_image(photo: &DynamicImage, space: Option<ColorSpace>) -> Self {
+        let is_float = matches!(photo.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let space = space.unwrap_or(if is_float {
+            ColorSpace::Linear
+        } else {
+            ColorSpace::Srgb
+        });
+        let decode = |c: f32| -> f64 {
+            match space {
+                ColorSpace::Srgb => srgb_to_linear(c as f64),
+                ColorSpace::Linear => c as f64,
+            }
+        };
+
+        let rgba = photo.to_rgba32f();
+        let mut texels = Vec::with_capacity(rgba.pixels().len());
+        let mut alpha = Vec::with_capacity(rgba.pixels().len());
+        for pixel in rgba.pixels() {
+            texels.push(Vec3::new(
+                decode(pixel.0[0]),
+                decode(pixel.0[1]),
+                decode(pixel.0[2]),
+            ));
+            alpha.push(pixel.0[3] as f64);
+        }
+        ImageTexture::from_levels(ImageTexture::build_levels(
+            photo.width() as i32,
+            photo.height() as i32,
+            texels,
+            alpha,
+        ))
+    }
+
+    /// Texels are linear and stored row by row, top row first.
+    pub fn from_texels(width: i32, height: i32, texels: Vec<Vec3>) -> Self {
+        let alpha = vec![1.0; texels.len()];
+        ImageTexture::from_levels(ImageTexture::build_levels(width, height, texels, alpha))
+    }
+
+    /// Width, height and linear texels of the full-resolution image, top row first.
+    pub fn base_level(&self) -> Option<(i32, i32, &[Vec3])> {
+        self.levels
+            .first()
+            .map(|l| (l.width, l.height, l.texels.as_slice()))
+    }
+
+    fn from_levels(levels: Arc<Vec<MipLevel>>) -> Self {
+        let mut texture = ImageTexture::new_0();
+        texture.levels = levels;
+        texture
+    }
 
+    fn build_levels(
+        width: i32,
+        height: i32,
+        texels: Vec<Vec3>,
+        alpha: Vec<f64>,
+    ) -> Arc<Vec<MipLevel>> {
+        let mut levels = Vec::new();
+        let mut level = MipLevel {
+            width,
+            height,
+            texels,
+            alpha,
+        };
+        while level.width > 1 || level.height > 1 {
+            let next = level.downsample();
+            levels.push(level);
+            level = next;
+        }
+        levels.push(level);
+        Arc::new(levels)
+    }
+
+    /// A texture reading this image's alpha channel as gray, sharing the decoded data.
+    /// Images without alpha read as fully opaque. Useful with `AlphaMask`.
+    pub fn alpha(&self) -> Self {
         Self {
-            data: photo.clone().into_bytes(),
-            width: photo.width() as i32,
-            height: photo.height() as i32,
-            bytes_per_scanline: (photo.width() * 3) as i32,
+            levels: self.levels.clone(),
+            read_alpha: true,
+            filter: self.filter,
+            wrap: self.wrap,
+            mipmap: self.mipmap,
+            uv_scale: self.uv_scale,
+            uv_offset: self.uv_offset,
+            uv_rotation: self.uv_rotation,
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: TextureFilter) {
+        self.filter = filter;
+    }
+
+    pub fn set_wrap(&mut self, wrap: WrapMode) {
+        self.wrap = wrap;
+    }
+
+    /// Without mipmaps every lookup reads the full-resolution image.
+    pub fn set_mipmap(&mut self, mipmap: bool) {
+        self.mipmap = mipmap;
+    }
+
+    /// Rotates (in degrees) about the centre of the image, then scales, then offsets.
+    pub fn set_uv_transform(&mut self, scale: (f64, f64), offset: (f64, f64), rotation: f64) {
+        self.uv_scale = scale;
+        self.uv_offset = offset;
+        self.uv_rotation = rotation;
+    }
+
+    fn transform_uv(&self, u: f64, v: f64) -> (f64, f64) {
+        let (sin, cos) = degrees_to_radians(self.uv_rotation).sin_cos();
+        let du = u - 0.5;
+        let dv = v - 0.5;
+        (
+            (cos * du - sin * dv) * self.uv_scale.0 + 0.5 + self.uv_offset.0,
+            (sin * du + cos * dv) * self.uv_scale.1 + 0.5 + self.uv_offset.1,
+        )
+    }
+
+    fn wrap_index(&self, i: i32, n: i32) -> i32 {
+        match self.wrap {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m >= n {
+                    2 * n - 1 - m
+                } else {
+                    m
+                }
+            }
+        }
+    }
+
+    fn texel(&self, level: &MipLevel, i: i32, j: i32) -> Vec3 {
+        let i = self.wrap_index(i, level.width);
+        let j = self.wrap_index(j, level.height);
+        let k = (j * level.width + i) as usize;
+        if self.read_alpha {
+            Vec3::ones() * level.alpha[k]
+        } else {
+            level.texels[k]
+        }
+    }
+
+    fn sample_level(&self, level: &MipLevel, u: f64, v: f64) -> Vec3 {
+        // Image rows run top to bottom, v runs bottom to top.
+        let x = u * level.width as f64;
+        let y = (1.0 - v) * level.height as f64;
+        match self.filter {
+            TextureFilter::Nearest => self.texel(level, x.floor() as i32, y.floor() as i32),
+            TextureFilter::Bilinear => {
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let i = x.floor() as i32;
+                let j = y.floor() as i32;
+                let fx = x - x.floor();
+                let fy = y - y.floor();
+                (self.texel(level, i, j) * (1.0 - fx) + self.texel(level, i + 1, j) * fx)
+                    * (1.0 - fy)
+                    + (self.texel(level, i, j + 1) * (1.0 - fx)
+                        + self.texel(level, i + 1, j + 1) * fx)
+                        * fy
+            }
+            TextureFilter::Bicubic => {
+                let x = x - 0.5;
+                let y = y - 0.5;
+                let i = x.floor() as i32;
+                let j = y.floor() as i32;
+                let wx = catmull_rom_weights(x - x.floor());
+                let wy = catmull_rom_weights(y - y.floor());
+                let mut sum = Vec3::zero();
+                for (dj, wj) in wy.iter().enumerate() {
+                    for (di, wi) in wx.iter().enumerate() {
+                        sum += self.texel(level, i + di as i32 - 1, j + dj as i32 - 1) * (wi * wj);
+                    }
+                }
+                sum
+            }
         }
     }
 }
 
-impl Texture for ImageTexture {
-    fn value(&self, mut u: f64, mut v: f64, _p: &Vec3) -> Vec3 {
-        if self.data == [0_u8, 0] {
-            return Vec3::new(0.0, 1.0, 1.0);
+type CacheKey = (String, Option<ColorSpace>);
+
+/// Decoded images keyed by path and colour space, so materials loading the same file
+/// share one copy of its pixels and mip pyramid.
+#[derive(Default)]
+pub struct TextureCache {
+    images: Mutex<HashMap<CacheKey, Arc<Vec<MipLevel>>>>,
+    missing_fallback: bool,
+}
+
+impl TextureCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// With the fallback on, images that fail to load are reported on stderr and render
+    /// magenta instead of aborting the scene.
+    pub fn set_missing_fallback(&mut self, fallback: bool) {
+        self.missing_fallback = fallback;
+    }
+
+    /// Colour image, as `ImageTexture::new`.
+    pub fn load(&self, filename: &str) -> Result<ImageTexture> {
+        self.load_in(filename, None)
+    }
+
+    /// Data image, as `ImageTexture::new_linear`.
+    pub fn load_linear(&self, filename: &str) -> Result<ImageTexture> {
+        self.load_in(filename, Some(ColorSpace::Linear))
+    }
+
+    fn load_in(&self, filename: &str, space: Option<ColorSpace>) -> Result<ImageTexture> {
+        let key = (filename.to_string(), space);
+        if let Some(levels) = self.images.lock().unwrap().get(&key) {
+            return Ok(ImageTexture::from_levels(levels.clone()));
+        }
+        // Decode without the lock so other files load in parallel. If another thread got
+        // here first with the same file, keep its copy and drop ours.
+        match ImageTexture::load_levels(filename, space) {
+            Ok(levels) => {
+                let levels = self
+                    .images
+                    .lock()
+                    .unwrap()
+                    .entry(key)
+                    .or_insert(levels)
+                    .clone();
+                Ok(ImageTexture::from_levels(levels))
+            }
+            Err(e) if self.missing_fallback => {
+                eprintln!("warning: {}", e);
+                Ok(ImageTexture::new_0())
+            }
+            Err(e) => Err(e),
         }
+    }
+}
+
+fn catmull_rom_weights(t: f64) -> [f64; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
 
-        u = clamp(u, 0.0, 1.0);
-        v = 1.0 - clamp(v, 0.0, 1.0);
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, p: &Vec3) -> Vec3 {
+        self.value_filtered(u, v, p, 0.0)
+    }
 
-        let mut i = (u * self.width as f64) as i32;
-        let mut j = (v * self.height as f64) as i32;
-        if i >= self.width {
-            i = self.width - 1;
+    fn value_filtered(&self, u: f64, v: f64, _p: &Vec3, width: f64) -> Vec3 {
+        if self.levels.is_empty() {
+            return MISSING_TEXTURE;
         }
-        if j >= self.height {
-            j = self.height - 1;
+        let (u, v) = self.transform_uv(u, v);
+
+        let base = &self.levels[0];
+        let texels_across = width
+            * self.uv_scale.0.abs().max(self.uv_scale.1.abs())
+            * base.width.max(base.height) as f64;
+        if !self.mipmap || texels_across <= 1.0 {
+            return self.sample_level(base, u, v);
         }
 
-        let color_scale = 1.0 / 255.0;
-        let mut pixel: [f64; 3] = [0.0; 3];
-        pixel[0] = self.data[(j * self.bytes_per_scanline + i * 3) as usize] as f64;
-        pixel[1] = self.data[(j * self.bytes_per_scanline + i * 3 + 1) as usize] as f64;
-        pixel[2] = self.data[(j * self.bytes_per_scanline + i * 3 + 2) as usize] as f64;
+        // Trilinear: blend the two levels whose texel size brackets the footprint.
+        let lod = texels_across.log2().min((self.levels.len() - 1) as f64);
+        let l0 = lod.floor() as usize;
+        let l1 = (l0 + 1).min(self.levels.len() - 1);
+        let t = lod - l0 as f64;
+        self.sample_level(&self.levels[l0], u, v) * (1.0 - t)
+            + self.sample_level(&self.levels[l1], u, v) * t
+    }
+}
 
-        Vec3::new(
-            color_scale * pixel[0],
-            color_scale * pixel[1],
-            color_scale * pixel[2],
-        )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn two_by_one() -> ImageTexture {
+        ImageTexture::from_texels(2, 1, vec![Vec3::zero(), Vec3::ones()])
+    }
+    #[test]
+    fn test_mip_pyramid_averages() {
+        let tex = two_by_one();
+        assert_eq!(tex.levels.len(), 2);
+        assert_eq!(tex.levels[1].texels[0], Vec3::ones() * 0.5);
+    }
+    #[test]
+    fn test_wrap_modes() {
+        let mut tex = two_by_one();
+        tex.set_filter(TextureFilter::Nearest);
+        let p = Vec3::zero();
+        tex.set_wrap(WrapMode::Repeat);
+        assert_eq!(tex.value(1.25, 0.5, &p), Vec3::zero());
+        tex.set_wrap(WrapMode::Clamp);
+        assert_eq!(tex.value(1.25, 0.5, &p), Vec3::ones());
+        tex.set_wrap(WrapMode::Mirror);
+        assert_eq!(tex.value(1.25, 0.5, &p), Vec3::ones());
+        assert_eq!(tex.value(1.75, 0.5, &p), Vec3::zero());
+    }
+    #[test]
+    fn test_srgb_decoding() {
+        let photo = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([255, 188, 0, 128]),
+        ));
+        let tex = ImageTexture::from_image(&photo, None);
+        let c = tex.value(0.5, 0.5, &Vec3::zero());
+        assert!((c.x() - 1.0).abs() < 1e-6);
+        assert!((c.y() - 0.5).abs() < 0.01);
+        assert!((tex.alpha().value(0.5, 0.5, &Vec3::zero()).x() - 128.0 / 255.0).abs() < 1e-6);
+    }
+    #[test]
+    fn test_missing_file() {
+        assert!(matches!(
+            ImageTexture::new("no/such/texture.png"),
+            Err(Error::Image { .. })
+        ));
+        let mut cache = TextureCache::new();
+        cache.set_missing_fallback(true);
+        let tex = cache.load("no/such/texture.png").unwrap();
+        assert_eq!(tex.value(0.5, 0.5, &Vec3::zero()).y(), 0.0);
+    }
+    #[test]
+    fn test_bilinear_midpoint() {
+        let mut tex = two_by_one();
+        tex.set_filter(TextureFilter::Bilinear);
+        assert_eq!(tex.value(0.5, 0.5, &Vec3::zero()), Vec3::ones() * 0.5);
     }
 }
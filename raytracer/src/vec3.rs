@@ -117,6 +117,11 @@ impl Vec3 {
             && (self.z > -s)
     }
 
+    /// Rec. 709 luminance of a linear RGB colour.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
         *v - *n * (*v * *n) * 2.0
     }
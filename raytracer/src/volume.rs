@@ -0,0 +1,374 @@
+use crate::constant_medium::boundary_segments;
+use crate::error::{Error, Result};
+pub use crate::hiitable::{Hiitable, HitRecord};
+pub use crate::material::{Isotropic, Material};
+pub use crate::phase::PhaseFunction;
+pub use crate::ray::Ray;
+use crate::rtweekend::random_f64;
+pub use crate::texture::{TexCoord, Texture};
+pub use crate::vec3::Vec3;
+
+use std::sync::Arc;
+
+/// A voxel grid stretched over an axis-aligned box and read with trilinear filtering.
+/// Single-channel grids hold density; three channels hold colour, e.g. fire emission.
+pub struct GridTexture {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    voxels: Vec<Vec3>,
+    min: Vec3,
+    max: Vec3,
+}
+
+impl GridTexture {
+    /// Voxels run x fastest, then y, then z.
+    pub fn from_voxels(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        voxels: Vec<Vec3>,
+        min: Vec3,
+        max: Vec3,
+    ) -> Self {
+        Self {
+            nx,
+            ny,
+            nz,
+            voxels,
+            min,
+            max,
+        }
+    }
+
+    /// Reads a raw grid: an ASCII header line `VOL nx ny nz channels` followed by
+    /// `nx * ny * nz * channels` little-endian `f32`s, x fastest. `channels` is 1 or 3.
+    pub fn load(path: &str, min: Vec3, max: Vec3) -> Result<Self> {
+        let bytes = std::fs::read(path).map_err(|source| Error::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        GridTexture::parse(&bytes, min, max).map_err(|message| Error::Volume {
+            path: path.to_string(),
+            message,
+        })
+    }
+
+    fn parse(bytes: &[u8], min: Vec3, max: Vec3) -> std::result::Result<Self, String> {
+        let end = bytes
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| "missing header".to_string())?;
+        let header = std::str::from_utf8(&bytes[..end]).map_err(|_| "bad header".to_string())?;
+        let fields: Vec<&str> = header.split_whitespace().collect();
+        if fields.len() != 5 || fields[0] != "VOL" {
+            return Err("header must be `VOL nx ny nz channels`".to_string());
+        }
+        let dims = fields[1..]
+            .iter()
+            .map(|f| {
+                f.parse::<usize>()
+                    .map_err(|_| format!("bad size \"{}\"", f))
+            })
+            .collect::<std::result::Result<Vec<usize>, String>>()?;
+        let (nx, ny, nz, channels) = (dims[0], dims[1], dims[2], dims[3]);
+        if channels != 1 && channels != 3 {
+            return Err(format!("expected 1 or 3 channels, found {}", channels));
+        }
+        let count = nx * ny * nz;
+        let data = &bytes[end + 1..];
+        if count == 0 || data.len() != count * channels * 4 {
+            return Err(format!(
+                "expected {} voxels of {} channels, found {} bytes",
+                count,
+                channels,
+                data.len()
+            ));
+        }
+        let values: Vec<f64> = data
+            .chunks_exact(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]) as f64)
+            .collect();
+        let voxels = if channels == 1 {
+            values.iter().map(|&d| Vec3::ones() * d).collect()
+        } else {
+            values
+                .chunks_exact(3)
+                .map(|c| Vec3::new(c[0], c[1], c[2]))
+                .collect()
+        };
+        Ok(GridTexture::from_voxels(nx, ny, nz, voxels, min, max))
+    }
+
+    /// Largest value of any channel, the majorant for delta tracking.
+    pub fn max_value(&self) -> f64 {
+        self.voxels
+            .iter()
+            .map(|v| v.x().max(v.y()).max(v.z()))
+            .fold(0.0, f64::max)
+    }
+
+    fn voxel(&self, i: usize, j: usize, k: usize) -> Vec3 {
+        self.voxels[(k * self.ny + j) * self.nx + i]
+    }
+}
+
+impl Texture for GridTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Vec3) -> Vec3 {
+        let extent = self.max - self.min;
+        let local = [
+            (p.x() - self.min.x()) / extent.x(),
+            (p.y() - self.min.y()) / extent.y(),
+            (p.z() - self.min.z()) / extent.z(),
+        ];
+        if local.iter().any(|c| !(0.0..=1.0).contains(c)) {
+            return Vec3::zero();
+        }
+        // Voxel centres sit at (i + 0.5) / n.
+        let axis = |c: f64, n: usize| {
+            let x = (c * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = (x.floor() as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f64)
+        };
+        let (i0, i1, fx) = axis(local[0], self.nx);
+        let (j0, j1, fy) = axis(local[1], self.ny);
+        let (k0, k1, fz) = axis(local[2], self.nz);
+        let lerp = |a: Vec3, b: Vec3, t: f64| a * (1.0 - t) + b * t;
+        let x00 = lerp(self.voxel(i0, j0, k0), self.voxel(i1, j0, k0), fx);
+        let x10 = lerp(self.voxel(i0, j1, k0), self.voxel(i1, j1, k0), fx);
+        let x01 = lerp(self.voxel(i0, j0, k1), self.voxel(i1, j0, k1), fx);
+        let x11 = lerp(self.voxel(i0, j1, k1), self.voxel(i1, j1, k1), fx);
+        lerp(lerp(x00, x10, fy), lerp(x01, x11, fy), fz)
+    }
+}
+
+/// A participating medium whose density varies through space, read from the red channel of
+/// any texture at world positions: a `GridTexture`, or noise for procedural clouds.
+/// Collisions are found with delta tracking against `majorant`, the largest density the
+/// texture reaches after scaling, and shadow rays use ratio tracking.
+pub struct HeterogeneousMedium {
+    boundary: Option<Arc<dyn Hiitable>>,
+    density: Option<Arc<dyn Texture>>,
+    scale: f64,
+    majorant: f64,
+    albedo: Vec3,
+    emission: Option<Arc<dyn Texture>>,
+    phase: Option<Arc<dyn PhaseFunction>>,
+    phase_function: Option<Arc<dyn Material>>,
+}
+
+impl HeterogeneousMedium {
+    pub fn new(
+        boundary: Option<Arc<dyn Hiitable>>,
+        density: Option<Arc<dyn Texture>>,
+        scale: f64,
+        majorant: f64,
+        albedo: Vec3,
+    ) -> Result<Self> {
+        if !(majorant > 0.0 && majorant.is_finite()) {
+            return Err(Error::InvalidDensity(majorant));
+        }
+        Ok(Self {
+            boundary,
+            density,
+            scale,
+            majorant,
+            albedo,
+            emission: None,
+            phase: None,
+            phase_function: Some(Arc::new(Isotropic::new1(albedo))),
+        })
+    }
+
+    /// A medium filled from a voxel grid; the majorant comes from the grid itself.
+    pub fn from_grid(
+        boundary: Option<Arc<dyn Hiitable>>,
+        grid: GridTexture,
+        scale: f64,
+        albedo: Vec3,
+    ) -> Result<Self> {
+        let majorant = grid.max_value() * scale;
+        HeterogeneousMedium::new(boundary, Some(Arc::new(grid)), scale, majorant, albedo)
+    }
+
+    /// Uses `emission` (looked up by position) as the light added at each collision, which
+    /// makes the densest parts glow most, as in fire.
+    pub fn set_emission(&mut self, albedo: Vec3, emission: Option<Arc<dyn Texture>>) {
+        self.albedo = albedo;
+        self.emission = emission;
+        self.update_phase_function();
+    }
+
+    /// Replaces the isotropic phase function, e.g. with `DoubleHenyeyGreenstein` for clouds.
+    pub fn set_phase(&mut self, phase: Option<Arc<dyn PhaseFunction>>) {
+        self.phase = phase;
+        self.update_phase_function();
+    }
+
+    fn update_phase_function(&mut self) {
+        let mut phase_function = Isotropic::new1(self.albedo);
+        phase_function.set_emission(self.emission.clone());
+        if self.phase.is_some() {
+            phase_function.set_phase(self.phase.clone());
+        }
+        self.phase_function = Some(Arc::new(phase_function));
+    }
+
+    /// Density at `p` at ray time `tm`, so animated density textures move.
+    fn density_at(&self, p: &Vec3, tm: f64) -> f64 {
+        let c = TexCoord {
+            tm,
+            ..TexCoord::new(0.0, 0.0, p)
+        };
+        let d = self
+            .density
+            .as_ref()
+            .map_or(0.0, |d| d.eval(&c).x() * self.scale);
+        d.clamp(0.0, self.majorant)
+    }
+}
+
+impl Hiitable for HeterogeneousMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let boundary = match &self.boundary {
+            Some(b) => b,
+            None => return false,
+        };
+        let ray_length = r.direc().length();
+        // Free paths are memoryless, so tracking can restart at each segment.
+        for (t0, t1) in boundary_segments(boundary.as_ref(), r, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t -= (1.0 - random_f64()).ln() / (self.majorant * ray_length);
+                if t >= t1 {
+                    break;
+                }
+                let p = r.at(t);
+                // Accept real collisions; the rest are null collisions with fictitious matter.
+                if random_f64() * self.majorant < self.density_at(&p, r.tm()) {
+                    rec.t = t;
+                    rec.point3 = p;
+                    rec.normal = Vec3::new(1.0, 0.0, 0.0);
+                    rec.front_size = true;
+                    rec.tm = r.tm();
+                    rec.mat = self.phase_function.clone();
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut crate::aabb::AAbb) -> bool {
+        self.boundary
+            .as_ref()
+            .is_some_and(|b| b.bounding_box(time0, time1, output_box))
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let boundary = match &self.boundary {
+            Some(b) => b,
+            None => return 1.0,
+        };
+        let ray_length = r.direc().length();
+        let mut transmittance = 1.0;
+        for (t0, t1) in boundary_segments(boundary.as_ref(), r, t_min, t_max) {
+            let mut t = t0;
+            loop {
+                t -= (1.0 - random_f64()).ln() / (self.majorant * ray_length);
+                if t >= t1 {
+                    break;
+                }
+                transmittance *= 1.0 - self.density_at(&r.at(t), r.tm()) / self.majorant;
+                if transmittance <= 0.0 {
+                    return 0.0;
+                }
+            }
+        }
+        transmittance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::Sphere;
+
+    #[test]
+    fn test_grid_parse_and_filter() {
+        let mut bytes = b"VOL 2 1 1 1\n".to_vec();
+        for v in [1.0f32, 3.0] {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        let grid = GridTexture::parse(&bytes, Vec3::zero(), Vec3::ones()).unwrap();
+        assert_eq!(grid.max_value(), 3.0);
+        let mid = grid.value(0.0, 0.0, &Vec3::new(0.5, 0.5, 0.5));
+        assert!((mid.x() - 2.0).abs() < 1e-12);
+        assert_eq!(
+            grid.value(0.0, 0.0, &Vec3::new(2.0, 0.5, 0.5)),
+            Vec3::zero()
+        );
+        assert!(GridTexture::parse(b"VOL 2 1 1 1\n\0\0", Vec3::zero(), Vec3::ones()).is_err());
+    }
+
+    #[test]
+    fn test_ratio_tracking_matches_beer_lambert() {
+        let sphere: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(Sphere::new(&Vec3::zero(), 1.0, None)));
+        let grid = GridTexture::from_voxels(
+            1,
+            1,
+            1,
+            vec![Vec3::ones() * 0.5],
+            Vec3::ones() * -1.0,
+            Vec3::ones(),
+        );
+        let medium = HeterogeneousMedium::from_grid(sphere, grid, 1.0, Vec3::ones()).unwrap();
+        // Double the majorant so ratio tracking has null collisions to work through.
+        let medium = HeterogeneousMedium {
+            majorant: 1.0,
+            ..medium
+        };
+        let r = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let n = 20000;
+        let mean: f64 = (0..n)
+            .map(|_| medium.transmittance(&r, 0.001, f64::INFINITY))
+            .sum::<f64>()
+            / n as f64;
+        assert!((mean - (-1.0f64).exp()).abs() < 0.01, "{}", mean);
+    }
+
+    /// Empty before time 0.5 and dense after it.
+    struct Appearing;
+
+    impl Texture for Appearing {
+        fn value(&self, _u: f64, _v: f64, _p: &Vec3) -> Vec3 {
+            Vec3::zero()
+        }
+
+        fn eval(&self, c: &TexCoord) -> Vec3 {
+            if c.tm < 0.5 {
+                Vec3::zero()
+            } else {
+                Vec3::ones() * 50.0
+            }
+        }
+    }
+
+    #[test]
+    fn test_density_follows_ray_time() {
+        let sphere: Option<Arc<dyn Hiitable>> =
+            Some(Arc::new(Sphere::new(&Vec3::zero(), 1.0, None)));
+        let medium =
+            HeterogeneousMedium::new(sphere, Some(Arc::new(Appearing)), 1.0, 50.0, Vec3::ones())
+                .unwrap();
+        let early = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 0.0);
+        let late = Ray::new(Vec3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0), 1.0);
+        assert_eq!(medium.transmittance(&early, 0.001, f64::INFINITY), 1.0);
+        assert!(medium.transmittance(&late, 0.001, f64::INFINITY) < 1e-6);
+        let mut rec = HitRecord::new();
+        assert!(!medium.hit(&early, 0.001, f64::INFINITY, &mut rec));
+        assert!(medium.hit(&late, 0.001, f64::INFINITY, &mut rec));
+        assert_eq!(rec.tm, 1.0);
+    }
+}